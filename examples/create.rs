@@ -20,8 +20,7 @@ fn main() {
     println!("Collection Config: {:?}", collection_config);
     let mut collection = db.create::<Value>()
         .name("users")
-        .key("user_id")
-        .key_type(KeyType::String)
+        .with_config(&collection_config)
         .build()   ;     // Insert a document with a Global TTL of 60 seconds
     collection.insert(json!({
         "user_id": "1",
@@ -0,0 +1,21 @@
+use serde_json::json;
+use ememdb_rs::{InMemoryDB, TTL};
+
+fn main() {
+    let db = InMemoryDB::new("simple_db", TTL::NoTTL);
+
+    // Key-value access; the "users" collection is created on the first set
+    db.set("users", "user1", json!({"name": "kim", "age": 30}), None).unwrap();
+    db.set("users", "user2", json!({"name": "lee", "age": 25}), Some(TTL::GlobalTTL(60))).unwrap();
+
+    println!("user1: {:?}", db.get_value("users", "user1"));
+    println!("keys: {:?}", db.keys("users"));
+
+    db.del("users", "user2").unwrap();
+    println!("keys after del: {:?}", db.keys("users"));
+
+    // Join the key-value users with their orders
+    let orders = db.create_collection("orders").unwrap();
+    orders.insert(json!({"user_id": "user1", "product": "Laptop"}), None).unwrap();
+    println!("joined: {:?}", db.join("users", "orders", "key", "user_id"));
+}
@@ -6,7 +6,7 @@ fn main() -> Result<(), String> {
     let db = InMemoryDB::new("example_db", TTL::NoTTL);
 
     // 컬렉션 생성
-    let mut users = db.create::<Value>()
+    let users = db.create::<Value>()
         .name("users")
         .key("user_id")
         .key_type(KeyType::String)
@@ -39,7 +39,7 @@ fn main() -> Result<(), String> {
     });
 
     match users.upsert(updated_user.clone(), Some(TTL::CustomTTL(7200))) {
-        Ok(OperationResult::Updated { id, old_document, new_document }) => {
+        Ok(OperationResult::Updated { id, old_document, new_document, .. }) => {
             println!("Updated user with id: {}", id);
             println!("Old document: {:?}", old_document);
             println!("New document: {:?}", new_document);
@@ -67,7 +67,7 @@ fn main() -> Result<(), String> {
     }
 
     // 모든 사용자 조회
-    let all_users = users.select("*").execute();
+    let all_users = users.select("*").execute()?;
     println!("All users after upsert operations:");
     for user in all_users {
         println!("{:?}", user);
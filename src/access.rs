@@ -0,0 +1,225 @@
+// access.rs
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+use crate::db::{Collection, CollectionBuilder, InMemoryDB};
+use crate::error::EmemError;
+
+// What a role may do with a collection; each level includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Read,
+    Write,
+    // Create, drop, rename, truncate and migrate
+    Admin,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Permission::Read => write!(f, "read"),
+            Permission::Write => write!(f, "write"),
+            Permission::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+// Named set of grants. A grant's pattern is a collection name, "*" for every collection, or
+// "namespace.*" for every collection in a namespace. The highest matching grant wins.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Role {
+    pub name: String,
+    pub grants: Vec<(String, Permission)>,
+}
+
+impl Role {
+    pub fn new(name: &str) -> Self {
+        Role { name: name.to_string(), grants: Vec::new() }
+    }
+
+    pub fn grant(mut self, pattern: &str, permission: Permission) -> Self {
+        self.grants.push((pattern.to_string(), permission));
+        self
+    }
+
+    pub fn permission(&self, collection: &str) -> Option<Permission> {
+        self.grants.iter()
+            .filter(|(pattern, _)| pattern_matches(pattern, collection))
+            .map(|(_, permission)| *permission)
+            .max()
+    }
+
+    pub fn allows(&self, collection: &str, required: Permission) -> bool {
+        self.permission(collection).map_or(false, |granted| granted >= required)
+    }
+
+    pub(crate) fn require(&self, collection: &str, required: Permission) -> Result<(), EmemError> {
+        if self.allows(collection, required) {
+            Ok(())
+        } else {
+            Err(EmemError::PermissionDenied {
+                role: self.name.clone(),
+                collection: collection.to_string(),
+                required,
+            })
+        }
+    }
+}
+
+fn pattern_matches(pattern: &str, collection: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_suffix(".*") {
+        Some(namespace) => collection.strip_prefix(namespace).map_or(false, |rest| rest.starts_with('.')),
+        None => pattern == collection,
+    }
+}
+
+// A database handle restricted to the grants of one role (see InMemoryDB::handle).
+// Collections obtained through it check the role on every read and write.
+#[derive(Debug)]
+pub struct DbHandle {
+    db: Arc<InMemoryDB>,
+    role: Arc<Role>,
+}
+
+impl DbHandle {
+    pub(crate) fn new(db: Arc<InMemoryDB>, role: Role) -> Self {
+        DbHandle { db, role: Arc::new(role) }
+    }
+
+    pub fn role(&self) -> &Role {
+        &self.role
+    }
+
+    // Collections this role can read
+    pub fn collection_names(&self) -> Vec<String> {
+        self.db.collection_names().into_iter()
+            .filter(|name| self.role.allows(name, Permission::Read))
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<Collection>, EmemError> {
+        self.role.require(name, Permission::Read)?;
+        let collection = self.db.collection_arc(name)
+            .ok_or_else(|| EmemError::CollectionNotFound(name.to_string()))?;
+        Ok(collection.with_role(self.role.clone()))
+    }
+
+    // Create the collection `name`, with `configure` choosing its other settings; requires admin on
+    // that name. The name is set after `configure` runs, so it can't be changed there.
+    pub fn create<'s, T: 'static, F>(&'s self, name: &str, configure: F) -> Result<Arc<Collection>, EmemError>
+    where
+        F: FnOnce(CollectionBuilder<'s, T>) -> CollectionBuilder<'s, T>,
+    {
+        self.role.require(name, Permission::Admin)?;
+        let collection = configure(self.db.create::<T>()).name(name).try_build()?;
+        Ok(collection.with_role(self.role.clone()))
+    }
+
+    pub fn drop_collection(&self, name: &str) -> Result<(), EmemError> {
+        self.role.require(name, Permission::Admin)?;
+        self.db.drop_collection(name)
+    }
+
+    pub fn rename_collection(&self, from: &str, to: &str) -> Result<(), EmemError> {
+        self.role.require(from, Permission::Admin)?;
+        self.role.require(to, Permission::Admin)?;
+        self.db.rename_collection(from, to)
+    }
+
+    pub fn copy_collection(&self, from: &str, to: &str) -> Result<Arc<Collection>, EmemError> {
+        self.role.require(from, Permission::Read)?;
+        self.role.require(to, Permission::Admin)?;
+        let copy = self.db.copy_collection(from, to)?;
+        Ok(copy.with_role(self.role.clone()))
+    }
+
+    // Run a SQL-like SELECT; the role needs read access to every collection it touches
+    pub fn query(&self, sql: &str) -> Result<Vec<Value>, EmemError> {
+        let query = self.db.prepare(sql)?;
+        self.role.require(&query.source().collection_name, Permission::Read)?;
+        for target in query.join_targets() {
+            self.role.require(&target.collection_name, Permission::Read)?;
+        }
+        query.execute()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{KeyType, TTL};
+    use crate::db::Document;
+    use crate::subscription::{EventType, Subscription};
+    use serde_json::json;
+
+    fn denied(result: Result<impl fmt::Debug, EmemError>) -> bool {
+        matches!(result, Err(EmemError::PermissionDenied { .. }))
+    }
+
+    fn setup() -> InMemoryDB {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let notes = db.create::<Value>().name("notes").key("id").key_type(KeyType::String).build();
+        notes.insert(json!({"id": "n1", "text": "hi"}), None).unwrap();
+        db.create::<Value>().name("secret").key("id").key_type(KeyType::String).build();
+        db.define_role(Role::new("reader").grant("notes", Permission::Read));
+        db.define_role(Role::new("admin").grant("allowed", Permission::Admin));
+        db
+    }
+
+    #[test]
+    fn permissions_follow_the_highest_matching_grant() {
+        let role = Role::new("r").grant("app.*", Permission::Read).grant("app.logs", Permission::Write);
+        assert_eq!(role.permission("app.logs"), Some(Permission::Write));
+        assert_eq!(role.permission("app.users"), Some(Permission::Read));
+        assert_eq!(role.permission("application"), None);
+        assert!(!role.allows("app.users", Permission::Write));
+    }
+
+    #[test]
+    fn reader_is_denied_writes_and_admin_operations() {
+        let db = setup();
+        let handle = db.handle("reader").unwrap();
+        assert_eq!(handle.collection_names(), vec!["notes".to_string()]);
+        assert!(denied(handle.get("secret")));
+        assert!(denied(handle.query("SELECT * FROM secret")));
+        assert_eq!(handle.query("SELECT * FROM notes").unwrap().len(), 1);
+
+        let notes = handle.get("notes").unwrap();
+        assert_eq!(notes.select("*").execute().unwrap().len(), 1);
+        assert_eq!(notes.parallel_scan(|id, _| Some(id.to_string())).unwrap(), vec!["n1".to_string()]);
+        assert!(notes.subscribe(Subscription::new(EventType::Insert, |_, _| {})).is_ok());
+        assert!(denied(notes.insert(json!({"id": "n2"}), None)));
+        assert!(denied(notes.delete("n1")));
+        assert!(denied(notes.computed("len", |_| json!(0))));
+        assert!(denied(notes.add_validator(crate::validation::CheckConstraint::new("text", |_| true))));
+        assert!(denied(notes.reset_documents(Document::new("id", Vec::new()))));
+        assert!(denied(handle.drop_collection("notes")));
+        assert!(denied(handle.rename_collection("notes", "other")));
+        assert!(denied(handle.copy_collection("notes", "copy")));
+        assert!(denied(handle.create::<Value, _>("notes", |builder| builder)));
+        assert_eq!(db.get("notes").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn role_without_grants_cannot_subscribe_or_scan() {
+        let db = setup();
+        let notes = db.collection_arc("notes").unwrap().with_role(Arc::new(Role::new("nobody")));
+        assert!(denied(notes.subscribe(Subscription::new(EventType::Insert, |_, _| {}))));
+        assert!(denied(notes.parallel_scan(|id, _| Some(id.to_string()))));
+    }
+
+    #[test]
+    fn create_keeps_the_checked_name() {
+        let db = setup();
+        let handle = db.handle("admin").unwrap();
+        let created = handle.create::<Value, _>("allowed", |builder| builder.name("secret2").key("id")).unwrap();
+        assert_eq!(created.collection_name, "allowed");
+        assert!(db.get("secret2").is_err());
+        assert!(denied(handle.create::<Value, _>("secret", |builder| builder)));
+    }
+}
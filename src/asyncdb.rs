@@ -0,0 +1,176 @@
+// asyncdb.rs
+// Async wrappers for use inside tokio services (feature "async"). Every call runs the blocking
+// operation on tokio's blocking thread pool, so long scans, bulk writes and snapshot I/O never
+// stall the runtime's worker threads. Must be awaited inside a tokio runtime.
+//
+//   let db = AsyncDb::new(Arc::new(InMemoryDB::new("app", TTL::NoTTL)));
+//   let users = db.collection("users")?;
+//   users.insert(json!({"name": "kim"}), None).await?;
+//   let adults = users.select("*").gte("age", 18).execute_async().await?;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::config::TTL;
+use crate::db::{Collection, InMemoryDB, OperationResult, UpsertSummary};
+use crate::error::EmemError;
+use crate::query::QueryBuilder;
+
+// Run `task` on the blocking pool and wait for it without blocking the caller's worker thread
+pub(crate) async fn blocking<T, F>(task: F) -> Result<T, EmemError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, EmemError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(task)
+        .await
+        .map_err(|e| EmemError::Other(format!("Blocking task failed: {}", e)))?
+}
+
+#[derive(Debug, Clone)]
+pub struct AsyncDb {
+    db: Arc<InMemoryDB>,
+}
+
+impl AsyncDb {
+    pub fn new(db: Arc<InMemoryDB>) -> Self {
+        AsyncDb { db }
+    }
+
+    // The wrapped database, for configuration and the synchronous API
+    pub fn db(&self) -> &Arc<InMemoryDB> {
+        &self.db
+    }
+
+    pub fn collection(&self, name: &str) -> Result<AsyncCollection, EmemError> {
+        self.db.collection_arc(name)
+            .map(AsyncCollection::new)
+            .ok_or_else(|| EmemError::CollectionNotFound(name.to_string()))
+    }
+
+    pub fn collection_names(&self) -> Vec<String> {
+        self.db.collection_names()
+    }
+
+    pub async fn drop_collection(&self, name: &str) -> Result<(), EmemError> {
+        let (db, name) = (self.db.clone(), name.to_string());
+        blocking(move || db.drop_collection(&name)).await
+    }
+
+    pub async fn rename_collection(&self, from: &str, to: &str) -> Result<(), EmemError> {
+        let (db, from, to) = (self.db.clone(), from.to_string(), to.to_string());
+        blocking(move || db.rename_collection(&from, &to)).await
+    }
+
+    pub async fn copy_collection(&self, from: &str, to: &str) -> Result<AsyncCollection, EmemError> {
+        let (db, from, to) = (self.db.clone(), from.to_string(), to.to_string());
+        blocking(move || db.copy_collection(&from, &to)).await.map(AsyncCollection::new)
+    }
+
+    pub async fn drop_namespace(&self, namespace: &str) -> Result<Vec<String>, EmemError> {
+        let (db, namespace) = (self.db.clone(), namespace.to_string());
+        blocking(move || db.drop_namespace(&namespace)).await
+    }
+
+    pub async fn query(&self, sql: &str) -> Result<Vec<Value>, EmemError> {
+        let (db, sql) = (self.db.clone(), sql.to_string());
+        blocking(move || db.query(&sql)).await
+    }
+
+    pub async fn save_snapshot<P: Into<PathBuf>>(&self, path: P) -> Result<(), EmemError> {
+        let (db, path) = (self.db.clone(), path.into());
+        blocking(move || db.save_snapshot(path)).await
+    }
+
+    pub async fn load_snapshot<P: Into<PathBuf>>(&self, path: P) -> Result<usize, EmemError> {
+        let (db, path) = (self.db.clone(), path.into());
+        blocking(move || db.load_snapshot(path)).await
+    }
+
+    pub async fn sweep_expired(&self) -> Result<usize, EmemError> {
+        let db = self.db.clone();
+        blocking(move || Ok(db.sweep_expired())).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AsyncCollection {
+    collection: Arc<Collection>,
+}
+
+impl AsyncCollection {
+    pub fn new(collection: Arc<Collection>) -> Self {
+        AsyncCollection { collection }
+    }
+
+    pub fn collection(&self) -> &Arc<Collection> {
+        &self.collection
+    }
+
+    pub fn name(&self) -> &str {
+        &self.collection.collection_name
+    }
+
+    // Queries are built synchronously; finish them with execute_async
+    pub fn select(&self, fields: &str) -> QueryBuilder {
+        self.collection.select(fields)
+    }
+
+    pub async fn find(&self, filter: Value) -> Result<Vec<Value>, EmemError> {
+        let collection = self.collection.clone();
+        blocking(move || collection.find(filter)).await
+    }
+
+    pub async fn insert(&self, document: Value, ttl: Option<TTL>) -> Result<OperationResult, EmemError> {
+        let collection = self.collection.clone();
+        blocking(move || collection.insert(document, ttl)).await
+    }
+
+    pub async fn upsert(&self, document: Value, ttl: Option<TTL>) -> Result<OperationResult, EmemError> {
+        let collection = self.collection.clone();
+        blocking(move || collection.upsert(document, ttl)).await
+    }
+
+    pub async fn upsert_many(&self, documents: Vec<Value>, ttl: Option<TTL>) -> Result<UpsertSummary, EmemError> {
+        let collection = self.collection.clone();
+        blocking(move || collection.upsert_many(documents, ttl)).await
+    }
+
+    pub async fn update(&self, document: Value) -> Result<OperationResult, EmemError> {
+        let collection = self.collection.clone();
+        blocking(move || collection.update(document)).await
+    }
+
+    pub async fn update_if<P>(&self, id: &str, predicate: P, document: Value) -> Result<bool, EmemError>
+    where
+        P: Fn(&Value) -> bool + Send + 'static,
+    {
+        let (collection, id) = (self.collection.clone(), id.to_string());
+        blocking(move || collection.update_if(&id, predicate, document)).await
+    }
+
+    pub async fn replace(&self, id: &str, document: Value, ttl: Option<TTL>) -> Result<OperationResult, EmemError> {
+        let (collection, id) = (self.collection.clone(), id.to_string());
+        blocking(move || collection.replace(&id, document, ttl)).await
+    }
+
+    pub async fn delete(&self, id: &str) -> Result<OperationResult, EmemError> {
+        let (collection, id) = (self.collection.clone(), id.to_string());
+        blocking(move || collection.delete(&id)).await
+    }
+
+    pub async fn restore(&self, id: &str) -> Result<OperationResult, EmemError> {
+        let (collection, id) = (self.collection.clone(), id.to_string());
+        blocking(move || collection.restore(&id)).await
+    }
+
+    pub async fn purge_deleted(&self, older_than: Duration) -> Result<usize, EmemError> {
+        let collection = self.collection.clone();
+        blocking(move || collection.purge_deleted(older_than)).await
+    }
+
+    pub async fn truncate(&self) -> Result<usize, EmemError> {
+        let collection = self.collection.clone();
+        blocking(move || collection.truncate()).await
+    }
+}
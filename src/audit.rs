@@ -0,0 +1,53 @@
+// audit.rs
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::db::OperationResult;
+
+// Name of the system collection holding the audit trail
+pub const AUDIT_COLLECTION: &str = "_audit";
+
+// Actor recorded for writes made through a handle without `as_actor`
+pub const UNKNOWN_ACTOR: &str = "unknown";
+
+// One audited write. Stored in the audit collection with `seq` as its key, so it can be queried
+// like any other document, e.g. `db.get("_audit")?.select("*").eq("actor", "alice")`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub collection: String,
+    // insert, update or delete
+    pub operation: String,
+    pub id: String,
+    pub actor: String,
+    pub at_ms: u64,
+    // Null for inserts
+    pub old_document: Value,
+    // Null for deletes
+    pub new_document: Value,
+}
+
+impl AuditEntry {
+    pub fn from_result(collection: &str, actor: Option<&str>, result: &OperationResult, at_ms: u64) -> Self {
+        let (operation, id, old_document, new_document) = match result {
+            OperationResult::Inserted { id, document } => ("insert", id, Value::Null, document.clone()),
+            OperationResult::Updated { id, old_document, new_document, .. } => ("update", id, old_document.clone(), new_document.clone()),
+            OperationResult::Deleted { id, document } => ("delete", id, document.clone(), Value::Null),
+        };
+        AuditEntry {
+            collection: collection.to_string(),
+            operation: operation.to_string(),
+            id: id.clone(),
+            actor: actor.unwrap_or(UNKNOWN_ACTOR).to_string(),
+            at_ms,
+            old_document,
+            new_document,
+        }
+    }
+
+    pub fn to_document(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    pub fn from_document(document: &Value) -> Option<Self> {
+        serde_json::from_value(document.clone()).ok()
+    }
+}
@@ -0,0 +1,199 @@
+// ememdb.rs
+// Interactive shell over a snapshot file (feature "cli"):
+//
+//   ememdb state.json                       open or create state.json and start the REPL
+//   ememdb state.json -c "SELECT * FROM users LIMIT 5"
+use clap::Parser;
+use ememdb_rs::{InMemoryDB, KeyType, TTL};
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(name = "ememdb", about = "Inspect and query ememdb snapshot files")]
+struct Args {
+    #[arg(help = "Snapshot file; created on .save when it does not exist yet")]
+    snapshot: Option<PathBuf>,
+    #[arg(short, long, help = "Run one command and exit")]
+    command: Option<String>,
+}
+
+const HELP: &str = "\
+SELECT fields FROM collection [WHERE ...] [ORDER BY ...] [LIMIT n] [OFFSET n]
+collection?age=gte.18&select=name,age&order=age.desc   PostgREST-style query
+.collections                    list collections with their document counts
+.describe NAME                  collection settings
+.create NAME [KEY_FIELD]        create a collection with string keys (uuid keys without KEY_FIELD)
+.insert NAME JSON               insert a document
+.find NAME JSON                 Mongo-style filter, e.g. .find users {\"age\": {\"$gte\": 18}}
+.delete NAME ID                 delete a document
+.stats                          memory usage
+.open PATH                      load another snapshot
+.save [PATH]                    write the snapshot (to the opened file by default)
+.help                           this text
+.quit";
+
+struct Shell {
+    db: InMemoryDB,
+    path: Option<PathBuf>,
+}
+
+impl Shell {
+    fn open(path: Option<PathBuf>) -> Result<Self, String> {
+        let db = InMemoryDB::new("ememdb", TTL::NoTTL);
+        if let Some(path) = &path {
+            if path.exists() {
+                let loaded = db.load_snapshot(path)?;
+                println!("Loaded {} documents from {}", loaded, path.display());
+            } else {
+                println!("{} does not exist yet; .save will create it", path.display());
+            }
+        }
+        Ok(Shell { db, path })
+    }
+
+    fn run(&mut self, line: &str) -> Result<Option<String>, String> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        if !line.starts_with('.') {
+            let rows = match line.split_once('?') {
+                Some((collection, params)) if !collection.contains(char::is_whitespace) => {
+                    self.db.get(collection)?.query_params(params)?.execute()?
+                }
+                _ => self.db.query(line)?,
+            };
+            return Ok(Some(format_rows(&rows)));
+        }
+
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let (name, argument) = rest.split_once(char::is_whitespace).map_or((rest, ""), |(n, a)| (n, a.trim()));
+        let output = match command {
+            ".help" => HELP.to_string(),
+            ".collections" | ".tables" => {
+                let mut names = self.db.collection_names();
+                names.sort();
+                names.iter()
+                    .map(|name| format!("{}\t{}", name, self.db.get(name).map_or(0, |c| c.len())))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            ".describe" => pretty(&self.db.get(name)?.describe()),
+            ".create" => {
+                let builder = self.db.create::<Value>().name(required(name, "collection name")?);
+                if argument.is_empty() {
+                    builder.key("id").key_type(KeyType::UUID).build();
+                } else {
+                    builder.key(argument).key_type(KeyType::String).build();
+                }
+                format!("Created {}", name)
+            }
+            ".insert" => {
+                let document = parse_json(argument)?;
+                match self.db.get(name)?.insert(document, None)? {
+                    ememdb_rs::OperationResult::Inserted { id, .. } => format!("Inserted {}", id),
+                    other => format!("{:?}", other),
+                }
+            }
+            ".find" => {
+                let filter = if argument.is_empty() { Value::Null } else { parse_json(argument)? };
+                format_rows(&self.db.get(name)?.find(filter)?)
+            }
+            ".delete" => {
+                self.db.get(name)?.delete(required(argument, "document id")?)?;
+                format!("Deleted {}", argument)
+            }
+            ".stats" => {
+                let stats = self.db.memory_stats();
+                let mut lines: Vec<String> = stats.collections.iter()
+                    .map(|(name, c)| format!("{}\t{} documents\t{} bytes", name, c.documents, c.total_bytes))
+                    .collect();
+                lines.push(format!("total\t{} documents\t{} bytes", stats.documents, stats.total_bytes));
+                lines.join("\n")
+            }
+            ".open" => {
+                *self = Shell::open(Some(PathBuf::from(required(name, "path")?)))?;
+                return Ok(None);
+            }
+            ".save" => {
+                if !name.is_empty() {
+                    self.path = Some(PathBuf::from(name));
+                }
+                let path = self.path.as_ref().ok_or("No snapshot file; use .save PATH")?;
+                self.db.save_snapshot(path)?;
+                format!("Saved {}", path.display())
+            }
+            other => return Err(format!("Unknown command '{}'; try .help", other)),
+        };
+        Ok(Some(output))
+    }
+}
+
+fn required<'a>(value: &'a str, what: &str) -> Result<&'a str, String> {
+    if value.is_empty() {
+        Err(format!("Missing {}", what))
+    } else {
+        Ok(value)
+    }
+}
+
+fn parse_json(text: &str) -> Result<Value, String> {
+    serde_json::from_str(text).map_err(|e| format!("Invalid JSON: {}", e))
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+fn format_rows(rows: &[Value]) -> String {
+    let mut out: Vec<String> = rows.iter().map(|row| row.to_string()).collect();
+    out.push(format!("({} rows)", rows.len()));
+    out.join("\n")
+}
+
+fn main() {
+    let args = Args::parse();
+    let mut shell = match Shell::open(args.snapshot) {
+        Ok(shell) => shell,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(command) = args.command {
+        match shell.run(&command) {
+            Ok(output) => output.into_iter().for_each(|o| println!("{}", o)),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let stdin = io::stdin();
+    loop {
+        print!("ememdb> ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
+        }
+        if matches!(line.trim(), ".quit" | ".exit") {
+            break;
+        }
+        match shell.run(&line) {
+            Ok(Some(output)) => println!("{}", output),
+            Ok(None) => {}
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// bloom.rs
+// Bloom filters over a collection's keys and selected fields (CollectionBuilder::bloom_filter).
+// A negative answer is certain, so Collection::exists, key and equality queries and unique-key
+// checks skip the DashMap for values that were never written. Bloom filters can't forget, so each
+// one counts the values removed since it was built and is rebuilt from the documents on its next
+// lookup once too many are stale or it holds more values than it was sized for.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+// Smallest number of values a filter is sized for
+pub const MIN_BLOOM_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    capacity: usize,
+    false_positive_rate: f64,
+    inserted: usize,
+    removed: usize,
+}
+
+impl BloomFilter {
+    // Sized so that `capacity` values give about `false_positive_rate` false positives
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(MIN_BLOOM_CAPACITY);
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let bits = (-(capacity as f64) * rate.ln() / std::f64::consts::LN_2.powi(2)).ceil() as usize;
+        let hashes = ((bits as f64 / capacity as f64) * std::f64::consts::LN_2).round().max(1.0) as u32;
+        BloomFilter {
+            bits: vec![0; bits.div_ceil(64)],
+            hashes,
+            capacity,
+            false_positive_rate: rate,
+            inserted: 0,
+            removed: 0,
+        }
+    }
+
+    // Bit positions of `item`, by double hashing one 64-bit hash
+    fn positions<T: Hash + ?Sized>(&self, item: &T) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (h1, h2) = (hash & 0xFFFF_FFFF, (hash >> 32) | 1);
+        let bits = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize)
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        let positions: Vec<usize> = self.positions(item).collect();
+        for position in positions {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+        self.inserted += 1;
+    }
+
+    // False means `item` was never inserted; true means it probably was
+    pub fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        self.positions(item).all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+
+    // Note that a value was removed; it keeps testing positive until the filter is rebuilt
+    pub fn mark_removed(&mut self) {
+        self.removed += 1;
+    }
+
+    // Past its capacity, or more than half of what it holds removed
+    pub fn needs_rebuild(&self) -> bool {
+        self.inserted > self.capacity || self.removed * 2 > self.inserted.max(1)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn false_positive_rate(&self) -> f64 {
+        self.false_positive_rate
+    }
+
+    pub fn len(&self) -> usize {
+        self.inserted
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inserted == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_false_negatives_and_about_the_configured_false_positives() {
+        let mut filter = BloomFilter::new(10_000, DEFAULT_FALSE_POSITIVE_RATE);
+        for i in 0..10_000 {
+            filter.insert(&format!("key-{}", i));
+        }
+        assert!((0..10_000).all(|i| filter.contains(&format!("key-{}", i))));
+        let false_positives = (0..100_000).filter(|i| filter.contains(&format!("other-{}", i))).count();
+        assert!(false_positives < 2_000, "{} false positives in 100000", false_positives);
+        assert!(!filter.needs_rebuild());
+    }
+
+    #[test]
+    fn needs_rebuild_past_capacity_or_when_mostly_removed() {
+        let mut filter = BloomFilter::new(0, DEFAULT_FALSE_POSITIVE_RATE);
+        assert_eq!(filter.capacity(), MIN_BLOOM_CAPACITY);
+        for i in 0..10 {
+            filter.insert(&i);
+        }
+        (0..6).for_each(|_| filter.mark_removed());
+        assert!(filter.needs_rebuild());
+
+        let mut filter = BloomFilter::new(MIN_BLOOM_CAPACITY, DEFAULT_FALSE_POSITIVE_RATE);
+        for i in 0..=MIN_BLOOM_CAPACITY {
+            filter.insert(&i);
+        }
+        assert!(filter.needs_rebuild());
+    }
+}
+
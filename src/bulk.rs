@@ -0,0 +1,261 @@
+// bulk.rs
+// Batches of mixed writes against one collection, applied in order:
+//
+//   let summary = users.bulk()
+//       .insert(json!({"name": "kim"}))
+//       .update(json!({"id": "u2", "active": false}))
+//       .delete("u3")
+//       .atomic()
+//       .execute()?;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::access::Permission;
+use crate::config::{KeyType, TTL};
+use crate::db::{Collection, DocumentEntry, OperationResult};
+use crate::error::EmemError;
+
+// One queued write
+#[derive(Debug, Clone, PartialEq)]
+pub enum BulkOp {
+    Insert(Value),
+    Upsert(Value),
+    // Merges the top-level fields into the document with the same key, like Collection::update
+    Update(Value),
+    Replace { id: String, document: Value },
+    Delete(String),
+}
+
+impl BulkOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BulkOp::Insert(_) => "insert",
+            BulkOp::Upsert(_) => "upsert",
+            BulkOp::Update(_) => "update",
+            BulkOp::Replace { .. } => "replace",
+            BulkOp::Delete(_) => "delete",
+        }
+    }
+}
+
+// Outcome of a bulk write. Failed operations are reported with their position in the batch.
+// When an atomic batch fails, `rolled_back` is set, the id lists are empty and `failed` holds the
+// operation that stopped it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BulkWriteSummary {
+    pub inserted: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+    pub failed: Vec<(usize, EmemError)>,
+    pub rolled_back: bool,
+}
+
+impl BulkWriteSummary {
+    pub fn total(&self) -> usize {
+        self.inserted.len() + self.updated.len() + self.deleted.len() + self.failed.len()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+pub struct BulkWriteBuilder {
+    collection: Arc<Collection>,
+    ops: Vec<BulkOp>,
+    ttl: Option<TTL>,
+    atomic: bool,
+}
+
+impl BulkWriteBuilder {
+    pub fn new(collection: Arc<Collection>) -> Self {
+        BulkWriteBuilder {
+            collection,
+            ops: Vec::new(),
+            ttl: None,
+            atomic: false,
+        }
+    }
+
+    pub fn insert(mut self, document: Value) -> Self {
+        self.ops.push(BulkOp::Insert(document));
+        self
+    }
+
+    pub fn upsert(mut self, document: Value) -> Self {
+        self.ops.push(BulkOp::Upsert(document));
+        self
+    }
+
+    pub fn update(mut self, document: Value) -> Self {
+        self.ops.push(BulkOp::Update(document));
+        self
+    }
+
+    pub fn replace(mut self, id: &str, document: Value) -> Self {
+        self.ops.push(BulkOp::Replace { id: id.to_string(), document });
+        self
+    }
+
+    pub fn delete(mut self, id: &str) -> Self {
+        self.ops.push(BulkOp::Delete(id.to_string()));
+        self
+    }
+
+    pub fn op(mut self, op: BulkOp) -> Self {
+        self.ops.push(op);
+        self
+    }
+
+    // TTL for the batch's inserts and upserts (otherwise the collection default applies)
+    pub fn ttl(mut self, ttl: TTL) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    // Stop at the first failing operation and undo the ones before it. Undoing puts back the
+    // documents of this collection (with their TTLs) and is recorded and announced as ordinary
+    // writes; documents removed in other collections by cascading foreign keys are not restored.
+    // Concurrent readers can still see the batch half applied.
+    pub fn atomic(mut self) -> Self {
+        self.atomic = true;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn execute(self) -> Result<BulkWriteSummary, EmemError> {
+        let collection = &self.collection;
+        collection.authorize(Permission::Write)?;
+        let mut summary = BulkWriteSummary::default();
+        // Entries as they were before the batch first touched them, for an atomic rollback
+        let mut previous: Vec<(String, Option<DocumentEntry>)> = Vec::new();
+        let mut touched = HashSet::new();
+
+        for (index, op) in self.ops.into_iter().enumerate() {
+            if self.atomic {
+                if let Some(id) = target_key(collection, &op) {
+                    if touched.insert(id.clone()) {
+                        let entry = collection.documents.get(&id).map(|entry| entry.clone());
+                        previous.push((id, entry));
+                    }
+                }
+            }
+            let result = match op {
+                BulkOp::Insert(document) => collection.insert(document, self.ttl.clone()),
+                BulkOp::Upsert(document) => collection.upsert(document, self.ttl.clone()),
+                BulkOp::Update(document) => collection.update(document),
+                BulkOp::Replace { id, document } => collection.replace(&id, document, None),
+                BulkOp::Delete(id) => collection.delete(&id),
+            };
+            match result {
+                Ok(OperationResult::Inserted { id, .. }) => {
+                    if self.atomic && touched.insert(id.clone()) {
+                        previous.push((id.clone(), None));
+                    }
+                    summary.inserted.push(id);
+                }
+                Ok(OperationResult::Updated { id, .. }) => summary.updated.push(id),
+                Ok(OperationResult::Deleted { id, .. }) => summary.deleted.push(id),
+                Err(error) if self.atomic => {
+                    for (id, entry) in previous.into_iter().rev() {
+                        collection.revert_entry(&id, entry);
+                    }
+                    return Ok(BulkWriteSummary {
+                        failed: vec![(index, error)],
+                        rolled_back: true,
+                        ..BulkWriteSummary::default()
+                    });
+                }
+                Err(error) => summary.failed.push((index, error)),
+            }
+        }
+        Ok(summary)
+    }
+}
+
+// Key an operation writes, when it is known before the operation runs. Inserts into collections
+// with generated keys are tracked by the id they return instead.
+fn target_key(collection: &Collection, op: &BulkOp) -> Option<String> {
+    match op {
+        BulkOp::Insert(_) if matches!(collection.key_type, KeyType::Increment | KeyType::UUID | KeyType::ULID | KeyType::NanoId) => None,
+        BulkOp::Insert(document) | BulkOp::Upsert(document) => collection.document_key(document).ok(),
+        BulkOp::Update(document) => collection.key_field.as_ref()
+            .and_then(|key_field| document.get(key_field))
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        BulkOp::Replace { id, .. } | BulkOp::Delete(id) => Some(id.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::InMemoryDB;
+    use serde_json::json;
+
+    fn users(db: &InMemoryDB) -> Arc<Collection> {
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        users.insert(json!({"id": "u1", "n": 1}), None).unwrap();
+        users.insert(json!({"id": "u2", "n": 1}), None).unwrap();
+        users
+    }
+
+    fn batch(users: &Collection) -> BulkWriteBuilder {
+        users.bulk()
+            .insert(json!({"id": "u3", "n": 1}))
+            .update(json!({"id": "u1", "n": 2}))
+            .delete("u2")
+            .update(json!({"id": "u9", "n": 3}))
+    }
+
+    #[test]
+    fn atomic_batch_rolls_back_on_the_first_failure() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = users(&db);
+
+        let summary = batch(&users).atomic().execute().unwrap();
+        assert!(summary.rolled_back);
+        assert_eq!(summary.failed, [(3, EmemError::DocumentNotFound)]);
+        assert_eq!(summary.total(), 1);
+        assert_eq!(users.get("u1").unwrap(), Some(json!({"id": "u1", "n": 1})));
+        assert!(users.exists("u2"));
+        assert!(!users.exists("u3"));
+    }
+
+    #[test]
+    fn atomic_batch_removes_generated_keys_on_rollback() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let events = db.create::<Value>().name("events").key("id").key_type(KeyType::Increment).build();
+
+        let summary = events.bulk()
+            .insert(json!({"kind": "a"}))
+            .insert(json!({"kind": "b"}))
+            .delete("missing")
+            .atomic()
+            .execute()
+            .unwrap();
+        assert!(summary.rolled_back);
+        assert_eq!(summary.failed[0].0, 2);
+        assert_eq!(events.len(), 0);
+    }
+
+    #[test]
+    fn non_atomic_batch_keeps_going_after_a_failure() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = users(&db);
+
+        let summary = batch(&users).execute().unwrap();
+        assert!(!summary.rolled_back);
+        assert_eq!((summary.inserted, summary.updated, summary.deleted), (vec!["u3".to_string()], vec!["u1".to_string()], vec!["u2".to_string()]));
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(users.get("u1").unwrap(), Some(json!({"id": "u1", "n": 2})));
+        assert!(!users.exists("u2"));
+    }
+}
@@ -0,0 +1,112 @@
+// cache.rs
+// Backing stores for collections used as a read-through / write-through cache:
+//
+//   let users = db.create::<Value>()
+//       .name("users")
+//       .key("id")
+//       .key_type(KeyType::String)
+//       .loader(FnLoader::new("postgres", |id| fetch_user(id)).write_through(|id, doc| save_user(id, doc)))
+//       .build();
+//   let user = users.get("u1")?;   // loaded from postgres on the first call, cached afterwards
+use serde_json::Value;
+use std::fmt;
+use std::future::Future;
+use crate::sink::block_on;
+
+// Source of documents missing from a collection. `load` runs on Collection::get misses; `write`
+// runs after every successful insert, update and delete through the collection (None for
+// deletes). Failed writes are reported as AdminEvent::WriteThroughFailed; the cached write
+// stays in place.
+pub trait CacheLoader: Send + Sync {
+    fn name(&self) -> &str;
+    fn load(&self, id: &str) -> Result<Option<Value>, String>;
+
+    // Read-through only unless overridden
+    fn write(&self, _id: &str, _document: Option<&Value>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for dyn CacheLoader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CacheLoader({})", self.name())
+    }
+}
+
+type WriteFn = Box<dyn Fn(&str, Option<&Value>) -> Result<(), String> + Send + Sync>;
+
+// Loads with a closure, optionally writing back with another
+pub struct FnLoader<F> {
+    name: String,
+    load: F,
+    write: Option<WriteFn>,
+}
+
+impl<F> FnLoader<F>
+where
+    F: Fn(&str) -> Result<Option<Value>, String> + Send + Sync,
+{
+    pub fn new(name: &str, load: F) -> Self {
+        FnLoader { name: name.to_string(), load, write: None }
+    }
+
+    pub fn write_through<W>(mut self, write: W) -> Self
+    where
+        W: Fn(&str, Option<&Value>) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.write = Some(Box::new(write));
+        self
+    }
+}
+
+impl<F> CacheLoader for FnLoader<F>
+where
+    F: Fn(&str) -> Result<Option<Value>, String> + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn load(&self, id: &str) -> Result<Option<Value>, String> {
+        (self.load)(id)
+    }
+
+    fn write(&self, id: &str, document: Option<&Value>) -> Result<(), String> {
+        match &self.write {
+            Some(write) => write(id, document),
+            None => Ok(()),
+        }
+    }
+}
+
+// Loads with an async closure, polled to completion on the calling thread. As with AsyncFnSink,
+// futures that need a tokio runtime should spawn their work on a captured runtime Handle and
+// await the JoinHandle.
+pub struct AsyncFnLoader<F> {
+    name: String,
+    load: F,
+}
+
+impl<F, Fut> AsyncFnLoader<F>
+where
+    F: Fn(String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Option<Value>, String>>,
+{
+    pub fn new(name: &str, load: F) -> Self {
+        AsyncFnLoader { name: name.to_string(), load }
+    }
+}
+
+impl<F, Fut> CacheLoader for AsyncFnLoader<F>
+where
+    F: Fn(String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Option<Value>, String>>,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn load(&self, id: &str) -> Result<Option<Value>, String> {
+        block_on((self.load)(id.to_string()))
+    }
+}
@@ -0,0 +1,115 @@
+// changelog.rs
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use crate::filter::FilterExpr;
+
+// Number of events kept for resuming when no capacity is given
+pub const DEFAULT_CHANGE_LOG_CAPACITY: usize = 10_000;
+
+// One write as seen by change-feed consumers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub seq: u64,
+    pub collection: String,
+    // insert, update, delete or evicted
+    pub event: String,
+    pub id: String,
+    pub document: Value,
+    pub at_ms: u64,
+}
+
+// Which events a consumer wants: empty collections means all of them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChangeFilter {
+    #[serde(default)]
+    pub collections: Vec<String>,
+    #[serde(default)]
+    pub events: Vec<String>,
+    // Live query: only events whose document matches
+    #[serde(default)]
+    pub filter: Option<FilterExpr>,
+}
+
+impl ChangeFilter {
+    pub fn matches(&self, event: &ChangeEvent) -> bool {
+        (self.collections.is_empty() || self.collections.contains(&event.collection))
+            && (self.events.is_empty() || self.events.contains(&event.event))
+            && self.filter.as_ref().map_or(true, |f| f.matches(&event.document))
+    }
+}
+
+// Returns false once the listener is gone, which unregisters it
+type ChangeListener = Box<dyn Fn(&ChangeEvent) -> bool + Send + Sync>;
+
+// Bounded, sequence-numbered history of writes across all collections.
+// Consumers resume with `since(seq)` and receive new events through `listen`.
+pub struct ChangeLog {
+    capacity: usize,
+    events: Mutex<(u64, VecDeque<ChangeEvent>)>,
+    listeners: Mutex<Vec<ChangeListener>>,
+}
+
+impl fmt::Debug for ChangeLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ChangeLog(capacity: {}, last_seq: {})", self.capacity, self.last_seq())
+    }
+}
+
+impl ChangeLog {
+    pub fn new(capacity: usize) -> Self {
+        ChangeLog {
+            capacity: capacity.max(1),
+            events: Mutex::new((0, VecDeque::new())),
+            listeners: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Sequence number of the latest event (0 before the first one)
+    pub fn last_seq(&self) -> u64 {
+        self.events.lock().unwrap().0
+    }
+
+    // Listeners run while the log is locked so they see events in sequence order
+    pub(crate) fn append(&self, collection: &str, event: &str, id: &str, document: &Value) {
+        let mut events = self.events.lock().unwrap();
+        events.0 += 1;
+        let change = ChangeEvent {
+            seq: events.0,
+            collection: collection.to_string(),
+            event: event.to_string(),
+            id: id.to_string(),
+            document: document.clone(),
+            at_ms: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+        };
+        self.listeners.lock().unwrap().retain(|listener| listener(&change));
+        if events.1.len() == self.capacity {
+            events.1.pop_front();
+        }
+        events.1.push_back(change);
+    }
+
+    // Events after `seq`; errors when some of them were already dropped from the log
+    pub fn since(&self, seq: u64) -> Result<Vec<ChangeEvent>, String> {
+        let events = self.events.lock().unwrap();
+        if let Some(oldest) = events.1.front() {
+            if seq + 1 < oldest.seq {
+                return Err(format!("Sequence {} is no longer available; the oldest retained event is {}", seq, oldest.seq));
+            }
+        }
+        Ok(events.1.iter().filter(|e| e.seq > seq).cloned().collect())
+    }
+
+    // Called for every new event until the listener returns false. Listeners must not call back
+    // into the log.
+    pub fn listen(&self, listener: impl Fn(&ChangeEvent) -> bool + Send + Sync + 'static) {
+        self.listeners.lock().unwrap().push(Box::new(listener));
+    }
+}
@@ -1,5 +1,11 @@
 // config.rs
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::error::EmemError;
+use crate::eviction::EvictionPolicy;
+use crate::validation::{CheckConstraint, DocumentValidator, FIELD_TYPES};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TTL {
@@ -12,10 +18,84 @@ pub enum TTL {
 pub enum KeyType {
     Increment,
     UUID,
+    // Time-ordered 26-character ULID; keys sort in insertion order
+    ULID,
+    // 21-character URL-safe random id
+    NanoId,
     String,
     Custom, // Use specific fields from the document
 }
 
+// How KeyType::Increment keys are generated: `start`, `start + step`, ... rendered as `prefix`
+// followed by the number zero-padded to `width` digits, e.g. "ORD-000123"
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IncrementKey {
+    pub start: u64,
+    pub step: u64,
+    // Minimum number of digits; 0 for no padding
+    pub width: usize,
+    pub prefix: String,
+}
+
+impl Default for IncrementKey {
+    fn default() -> Self {
+        IncrementKey { start: 0, step: 1, width: 0, prefix: String::new() }
+    }
+}
+
+impl IncrementKey {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(mut self, start: u64) -> Self {
+        self.start = start;
+        self
+    }
+
+    pub fn step(mut self, step: u64) -> Self {
+        self.step = step;
+        self
+    }
+
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    pub fn format(&self, value: u64) -> String {
+        format!("{}{:0width$}", self.prefix, value, width = self.width)
+    }
+
+    pub fn validate(&self) -> Result<(), EmemError> {
+        if self.step == 0 {
+            return Err(EmemError::InvalidConfig("Increment step must be greater than zero".to_string()));
+        }
+        Ok(())
+    }
+}
+
+// What happens to referencing documents when the referenced document is deleted
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum OnDelete {
+    Restrict,
+    SetNull,
+    Cascade,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ForeignKey {
+    pub field: String,
+    pub references_collection: String,
+    pub references_field: String,
+    pub on_delete: OnDelete,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CollectionConfig<'a> {
     pub key_field: Option<&'a str>,
@@ -25,6 +105,24 @@ pub struct CollectionConfig<'a> {
     pub nullable_fields: Vec<&'a str>,
     pub field_types: Vec<(&'a str, &'a str)>,
     pub ttl: Option<TTL>,
+    pub defaults: Vec<(&'a str, Value)>,
+    pub foreign_keys: Vec<ForeignKey>,
+    #[serde(skip)]
+    pub checks: Vec<CheckConstraint>,
+    #[serde(default)]
+    pub soft_delete: bool,
+    #[serde(default)]
+    pub memory_limit: Option<usize>,
+    #[serde(default)]
+    pub max_document_size: Option<usize>,
+    #[serde(skip)]
+    pub eviction_policy: Option<Arc<dyn EvictionPolicy>>,
+    #[serde(default)]
+    pub increment: Option<IncrementKey>,
+    // Number of shards of the document map (a power of two); more shards mean less lock
+    // contention between writers and more threads for parallel scans
+    #[serde(default)]
+    pub shards: Option<usize>,
 }
 
 impl<'a> CollectionConfig<'a> {
@@ -37,6 +135,15 @@ impl<'a> CollectionConfig<'a> {
             nullable_fields: Vec::new(),
             field_types: Vec::new(),
             ttl: None,
+            defaults: Vec::new(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
+            soft_delete: false,
+            memory_limit: None,
+            max_document_size: None,
+            eviction_policy: None,
+            increment: None,
+            shards: None,
         }
     }
 
@@ -75,25 +182,187 @@ impl<'a> CollectionConfig<'a> {
         self
     }
 
-    pub fn validate(&self) -> Result<(), String> {
+    // Values filled in for fields missing from an inserted document
+    pub fn defaults(mut self, defaults: Vec<(&'a str, Value)>) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    // `field` must match `references_field` of an existing document in `collection`; deletes are restricted
+    pub fn foreign_key(self, field: &str, collection: &str, references_field: &str) -> Self {
+        self.foreign_key_on_delete(field, collection, references_field, OnDelete::Restrict)
+    }
+
+    pub fn foreign_key_on_delete(mut self, field: &str, collection: &str, references_field: &str, on_delete: OnDelete) -> Self {
+        self.foreign_keys.push(ForeignKey {
+            field: field.to_string(),
+            references_collection: collection.to_string(),
+            references_field: references_field.to_string(),
+            on_delete,
+        });
+        self
+    }
+
+    // Named invariant enforced on every write
+    pub fn check<F>(mut self, name: &str, predicate: F) -> Self
+    where
+        F: Fn(&Value) -> bool + Send + Sync + 'static,
+    {
+        self.checks.push(CheckConstraint::new(name, predicate));
+        self
+    }
+
+    // delete marks documents with `_deleted_at` instead of removing them
+    pub fn soft_delete(mut self, soft_delete: bool) -> Self {
+        self.soft_delete = soft_delete;
+        self
+    }
+
+    // Estimated bytes above which least recently used documents are evicted
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    // Largest accepted document, in bytes of its JSON encoding
+    pub fn max_document_size(mut self, bytes: usize) -> Self {
+        self.max_document_size = Some(bytes);
+        self
+    }
+
+    // Order in which documents are evicted under memory_limit (LRU when unset)
+    pub fn eviction_policy<P: EvictionPolicy + 'static>(mut self, policy: P) -> Self {
+        self.eviction_policy = Some(Arc::new(policy));
+        self
+    }
+
+    // Start, step and format of KeyType::Increment keys
+    pub fn increment(mut self, increment: IncrementKey) -> Self {
+        self.increment = Some(increment);
+        self
+    }
+
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = Some(shards);
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), EmemError> {
         if self.key_type == Some(KeyType::Custom) && self.key_field.is_none() {
-            return Err("Key field must be set when using Custom key type".to_string());
+            return Err(EmemError::InvalidConfig("Key field must be set when using Custom key type".to_string()));
         }
         
         // 추가적인 유효성 검사
         if let Some(key_field) = self.key_field {
             if !self.field_types.iter().any(|&(field, _)| field == key_field) {
-                return Err("Key field must be defined in field_types".to_string());
+                return Err(EmemError::InvalidConfig("Key field must be defined in field_types".to_string()));
+            }
+        }
+
+        if let Some(increment) = &self.increment {
+            increment.validate()?;
+        }
+
+        for (field, type_name) in &self.field_types {
+            if !FIELD_TYPES.contains(type_name) {
+                return Err(EmemError::InvalidConfig(format!("Unknown type '{}' for field '{}'", type_name, field)));
             }
         }
 
         // not_null_fields와 nullable_fields 중복 검사
         for field in &self.not_null_fields {
             if self.nullable_fields.contains(field) {
-                return Err(format!("Field '{}' cannot be both not-null and nullable", field));
+                return Err(EmemError::InvalidConfig(format!("Field '{}' cannot be both not-null and nullable", field)));
+            }
+        }
+
+        for (i, check) in self.checks.iter().enumerate() {
+            if self.checks[..i].iter().any(|c| c.name() == check.name()) {
+                return Err(EmemError::InvalidConfig(format!("Duplicate check constraint name '{}'", check.name())));
+            }
+        }
+
+        if let Some(shards) = self.shards {
+            if shards < 2 || !shards.is_power_of_two() {
+                return Err(EmemError::InvalidConfig(format!("Shard count must be a power of two greater than one, got {}", shards)));
+            }
+        }
+
+        if self.max_document_size == Some(0) {
+            return Err(EmemError::InvalidConfig("max_document_size must be greater than zero".to_string()));
+        }
+
+        for fk in &self.foreign_keys {
+            if fk.on_delete == OnDelete::SetNull && self.not_null_fields.contains(&fk.field.as_str()) {
+                return Err(EmemError::InvalidConfig(format!("Foreign key field '{}' uses SetNull but is declared not-null", fk.field)));
             }
         }
 
         Ok(())
     }
 }
+
+// Database-wide settings that can be changed at runtime with InMemoryDB::update_config
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DbConfig {
+    // How often the background sweeper removes expired documents; None pauses it
+    pub sweeper_interval: Option<Duration>,
+    // Estimated size of all documents, in bytes, above which a MemoryLimitExceeded event is emitted
+    pub memory_limit: Option<usize>,
+    // Documents older than this are removed by the sweeper regardless of their TTL
+    pub retention: Option<Duration>,
+    // Queries slower than this emit a SlowQuery event and are kept in InMemoryDB::slow_queries
+    pub slow_query_threshold: Option<Duration>,
+}
+
+impl DbConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sweeper_interval(mut self, interval: Duration) -> Self {
+        self.sweeper_interval = Some(interval);
+        self
+    }
+
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    pub fn slow_query_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_query_threshold = Some(threshold);
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), EmemError> {
+        if self.sweeper_interval == Some(Duration::ZERO) {
+            return Err(EmemError::InvalidConfig("Sweeper interval must be greater than zero".to_string()));
+        }
+        if self.retention == Some(Duration::ZERO) {
+            return Err(EmemError::InvalidConfig("Retention must be greater than zero".to_string()));
+        }
+        if self.memory_limit == Some(0) {
+            return Err(EmemError::InvalidConfig("Memory limit must be greater than zero".to_string()));
+        }
+        Ok(())
+    }
+
+    // (setting, old value, new value) for every setting that differs from `other`
+    pub fn changes(&self, other: &DbConfig) -> Vec<(String, Value, Value)> {
+        let old = serde_json::to_value(self).unwrap_or_default();
+        let new = serde_json::to_value(other).unwrap_or_default();
+        let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+            return Vec::new();
+        };
+        old.iter()
+            .filter(|(setting, value)| new.get(*setting) != Some(value))
+            .map(|(setting, value)| (setting.clone(), value.clone(), new.get(setting).cloned().unwrap_or(Value::Null)))
+            .collect()
+    }
+}
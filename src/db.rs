@@ -1,9 +1,40 @@
 use dashmap::DashMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::{Value, json};
 use uuid::Uuid;
-use std::{sync::{Arc, RwLock}, time::{Duration, SystemTime}};
-use crate::config::{TTL, KeyType};
-use crate::query::QueryBuilder;
+use std::{fmt, sync::{Arc, Mutex, RwLock, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant, SystemTime}};
+use crate::config::{TTL, KeyType, CollectionConfig, DbConfig, ForeignKey, IncrementKey, OnDelete};
+use crate::query::{JoinBuilder, JoinType, QueryBuilder, QueryOptions};
+use crate::filter::{Comparators, CompareFn, FilterExpr, QuerySpec};
+use crate::integrity::{OrphanPolicy, OrphanReport};
+use crate::validation::{value_type_name, DocumentValidator, SchemaValidator};
+use crate::metrics::{CollectionMetrics, CollectionStats, DbMemoryStats, FieldStats, LargeDocument, MemoryStats, NumericFieldStats, OperationKind, TtlStats};
+use crate::replay::{OperationRecorder, RecordedOp, SyncMode};
+use crate::subscription::{AdminEvent, AdminListeners, EventType, Subscription};
+use crate::sweeper::Sweeper;
+use crate::eviction::{EvictionPolicy, LruPolicy};
+use crate::view::{MaterializedView, SavedView};
+use crate::snapshot::Snapshot;
+use crate::changelog::{ChangeLog, DEFAULT_CHANGE_LOG_CAPACITY};
+use crate::sink::{ChangeSink, SinkConnector, SinkOptions};
+use crate::slowlog::{SlowQuery, SlowQueryLog};
+use crate::audit::{AuditEntry, AUDIT_COLLECTION};
+use crate::patch::PatchOp;
+use crate::keygen;
+use crate::bulk::BulkWriteBuilder;
+use crate::cache::CacheLoader;
+use crate::pipeline::Pipeline;
+use crate::text::Analyzer;
+use crate::trigram::TrigramIndex;
+use crate::hll::HyperLogLog;
+use crate::bloom::{BloomFilter, DEFAULT_FALSE_POSITIVE_RATE};
+use crate::resp::{KEY_FIELD as KV_KEY_FIELD, VALUE_FIELD as KV_VALUE_FIELD};
+use crate::access::{DbHandle, Permission, Role};
+use crate::error::EmemError;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::thread;
 // use crate::query::Query;
 
 #[derive(Debug, Clone)]
@@ -16,6 +47,7 @@ pub enum OperationResult {
         id: String,
         old_document: Value,
         new_document: Value,
+        mode: UpdateMode,
     },
     Deleted {
         id: String,
@@ -23,67 +55,936 @@ pub enum OperationResult {
     },
 }
 
+impl OperationResult {
+    // Key of the written document, including keys generated on insert
+    pub fn id(&self) -> &str {
+        match self {
+            OperationResult::Inserted { id, .. }
+            | OperationResult::Updated { id, .. }
+            | OperationResult::Deleted { id, .. } => id,
+        }
+    }
+
+    // The document as stored after the write (for deletes, the removed document)
+    pub fn document(&self) -> &Value {
+        match self {
+            OperationResult::Inserted { document, .. } | OperationResult::Deleted { document, .. } => document,
+            OperationResult::Updated { new_document, .. } => new_document,
+        }
+    }
+
+    // `document` deserialized into `T`, e.g. to read back defaults and the generated key
+    pub fn document_as<T: DeserializeOwned>(&self) -> Result<T, EmemError> {
+        serde_json::from_value(self.document().clone())
+            .map_err(|e| EmemError::Other(format!("Failed to deserialize document: {}", e)))
+    }
+}
+
+// How an update changed the stored document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateMode {
+    // Only the given fields were written (update, increment, push, patches, ...)
+    Merge,
+    // The whole document was overwritten (replace, upsert of an existing document)
+    Replace,
+}
+
+// What upsert_with does when a document with the same key already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnConflict {
+    // Fail with EmemError::DocumentExists
+    Error,
+    // Keep the stored document and write nothing
+    Ignore,
+    // Overwrite the stored document (what upsert does)
+    #[default]
+    Replace,
+    // Write the given top-level fields into the stored document, keeping the others and its TTL
+    // unless a TTL is given
+    MergeFields,
+}
+
+// Which version of the document find_one_and_update returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReturnDocument {
+    #[default]
+    Before,
+    After,
+}
+
+// Outcome of upsert_many. Documents that fail don't stop the batch; they are reported with
+// their position in the input.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UpsertSummary {
+    pub inserted: Vec<String>,
+    pub updated: Vec<String>,
+    pub failed: Vec<(usize, EmemError)>,
+}
+
+impl UpsertSummary {
+    pub fn total(&self) -> usize {
+        self.inserted.len() + self.updated.len() + self.failed.len()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+// Key field of collections made by create_collection
+pub const DEFAULT_KEY_FIELD: &str = "id";
+
+// Buckets in the histograms of Collection::field_stats
+pub const DEFAULT_HISTOGRAM_BUCKETS: usize = 10;
+
+// Marker set by delete on soft-delete collections (milliseconds since the Unix epoch)
+pub const DELETED_AT_FIELD: &str = "_deleted_at";
+
+// Namespace part of a collection name ("analytics" for "analytics.events"), None for flat names
+pub fn namespace_of(name: &str) -> Option<&str> {
+    name.rsplit_once('.').map(|(namespace, _)| namespace)
+}
+
+fn expiration_from(ttl: &TTL) -> Option<SystemTime> {
+    match ttl {
+        TTL::GlobalTTL(seconds) | TTL::CustomTTL(seconds) => Some(SystemTime::now() + Duration::from_secs(*seconds)),
+        TTL::NoTTL => None,
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
 #[derive(Debug)]
 pub struct InMemoryDB {
     name: String,
-    collections: RwLock<DashMap<String, Arc<Collection>>>,
+    // Shared with every handle of this database, including the parent_db of its collections
+    collections: Arc<RwLock<DashMap<String, Arc<Collection>>>>,
     default_ttl: TTL,
+    recorder: Arc<RwLock<Option<Arc<OperationRecorder>>>>,
+    config: Arc<RwLock<DbConfig>>,
+    admin_listeners: AdminListeners,
+    materialized_views: Arc<DashMap<String, Arc<MaterializedView>>>,
+    views: Arc<DashMap<String, SavedView>>,
+    change_log: Arc<RwLock<Option<Arc<ChangeLog>>>>,
+    slow_queries: Arc<SlowQueryLog>,
+    audit: Arc<RwLock<Option<Arc<Collection>>>>,
+    roles: Arc<DashMap<String, Role>>,
+    comparators: Comparators,
 }
 
 impl  InMemoryDB {
     pub fn new(name: &str, default_ttl: TTL) -> Self {
         InMemoryDB {
             name: name.to_string(),
-            collections: DashMap::new().into(),
+            collections: Arc::new(RwLock::new(DashMap::new())),
             default_ttl,
+            recorder: Arc::new(RwLock::new(None)),
+            config: Arc::new(RwLock::new(DbConfig::default())),
+            admin_listeners: AdminListeners::default(),
+            materialized_views: Arc::new(DashMap::new()),
+            views: Arc::new(DashMap::new()),
+            change_log: Arc::new(RwLock::new(None)),
+            slow_queries: Arc::new(SlowQueryLog::default()),
+            audit: Arc::new(RwLock::new(None)),
+            roles: Arc::new(DashMap::new()),
+            comparators: Comparators::default(),
         }
     }
     fn clone(&self) -> Self {
         InMemoryDB {
             name: self.name.clone(),
-            collections: RwLock::new(self.collections.read().unwrap().clone()),
+            collections: self.collections.clone(),
             default_ttl: self.default_ttl.clone(),
+            recorder: self.recorder.clone(),
+            config: self.config.clone(),
+            admin_listeners: self.admin_listeners.clone(),
+            materialized_views: self.materialized_views.clone(),
+            views: self.views.clone(),
+            change_log: self.change_log.clone(),
+            slow_queries: self.slow_queries.clone(),
+            audit: self.audit.clone(),
+            roles: self.roles.clone(),
+            comparators: self.comparators.clone(),
         }
     }
         pub fn create<T: 'static>(&self) -> CollectionBuilder<T> {
             CollectionBuilder::new(self)
         }
 
-    pub fn get(&self, name: &str) -> Result<Collection, String> {
-        let arc_collection = self.collections.read().unwrap().get(name).unwrap().value().clone();
+    // Validate `config` and create a collection named `name` from it: key field and type, unique
+    // keys, field types, not-null rules, checks, defaults, foreign keys, TTL and limits
+    pub fn create_from_config(&self, name: &str, config: &CollectionConfig) -> Result<Arc<Collection>, EmemError> {
+        config.validate()?;
+        if self.collection_arc(name).is_some() {
+            return Err(EmemError::CollectionExists(name.to_string()));
+        }
+        Ok(self.create::<Value>().name(name).with_config(config).build())
+    }
+
+    // Collection keyed by a generated UUID in "id", with every other setting at its default
+    pub fn create_collection(&self, name: &str) -> Result<Arc<Collection>, EmemError> {
+        if self.collection_arc(name).is_some() {
+            return Err(EmemError::CollectionExists(name.to_string()));
+        }
+        Ok(self.create::<Value>().name(name).key(DEFAULT_KEY_FIELD).key_type(KeyType::UUID).build())
+    }
+
+    // The collection named `name`, created as by create_collection when it doesn't exist
+    pub fn get_or_create_collection(&self, name: &str) -> Arc<Collection> {
+        self.collection_arc(name)
+            .unwrap_or_else(|| self.create::<Value>().name(name).key(DEFAULT_KEY_FIELD).key_type(KeyType::UUID).build())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Collection, EmemError> {
+        let arc_collection = self.collection_arc(name)
+            .ok_or_else(|| EmemError::CollectionNotFound(name.to_string()))?;
         Ok((*arc_collection).clone())
         }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Key-value access on top of collections, in the layout RespServer uses: each key is stored
+    // as {"key": <key>, "value": <value>} (or the collection's own key field). `set` creates the
+    // collection as a String-keyed collection when it doesn't exist, and fails on collections that
+    // generate their own keys. `get` already names the collection lookup, so reads go through
+    // get_value.
+    pub fn set(&self, collection: &str, key: &str, value: Value, ttl: Option<TTL>) -> Result<(), EmemError> {
+        let collection = match self.collection_arc(collection) {
+            Some(existing) => existing,
+            None => self.create::<Value>().name(collection).key(KV_KEY_FIELD).key_type(KeyType::String).build(),
+        };
+        let keyed_by_caller = match collection.key_type {
+            KeyType::String => true,
+            KeyType::Custom => collection.key_fn.is_none(),
+            _ => false,
+        };
+        if !keyed_by_caller {
+            return Err(EmemError::InvalidConfig(format!("Collection '{}' generates its own keys, so set can't store under '{}'", collection.collection_name, key)));
+        }
+        let key_field = collection.key_field.as_ref().ok_or(EmemError::KeyFieldNotSet)?;
+        let mut document = json!({ KV_VALUE_FIELD: value });
+        document[key_field.as_str()] = json!(key);
+        collection.upsert(document, ttl)?;
+        Ok(())
+    }
+
+    // Value stored under `key`, None when the key or the collection doesn't exist or has expired
+    pub fn get_value(&self, collection: &str, key: &str) -> Result<Option<Value>, EmemError> {
+        let Some(collection) = self.collection_arc(collection) else { return Ok(None) };
+        Ok(collection.get(key)?.map(|document| document.get(KV_VALUE_FIELD).cloned().unwrap_or(Value::Null)))
+    }
+
+    // Remove `key`; returns whether it existed
+    pub fn del(&self, collection: &str, key: &str) -> Result<bool, EmemError> {
+        let Some(collection) = self.collection_arc(collection) else { return Ok(false) };
+        match collection.delete(key) {
+            Ok(_) => Ok(true),
+            Err(EmemError::DocumentNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Live keys of `collection`, sorted
+    pub fn keys(&self, collection: &str) -> Result<Vec<String>, EmemError> {
+        let Some(collection) = self.collection_arc(collection) else { return Ok(Vec::new()) };
+        collection.authorize(Permission::Read)?;
+        let now = SystemTime::now();
+        let mut keys: Vec<String> = collection.documents.iter()
+            .filter(|r| r.value().expiration.map_or(true, |expiration| expiration > now) && !collection.is_deleted(&r.value().value))
+            .map(|r| r.key().clone())
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    // TTL applied to documents inserted without one, unless their collection sets its own
+    pub fn default_ttl(&self) -> &TTL {
+        &self.default_ttl
+    }
+
     pub fn collection_names(&self) -> Vec<String> {
         self.collections.read().unwrap().iter().map(|r| r.key().clone()).collect()
     }
+
+    // Remove a collection with its documents and subscriptions. Fails while another collection
+    // still declares a foreign key into it.
+    pub fn drop_collection(&self, name: &str) -> Result<(), EmemError> {
+        let collection = self.collection_arc(name)
+            .ok_or_else(|| EmemError::CollectionNotFound(name.to_string()))?;
+        self.check_droppable(&collection, &[name.to_string()])?;
+        self.record(RecordedOp::DropCollection { collection: name.to_string() });
+        self.remove_collection(name);
+        Ok(())
+    }
+
+    // Names are split on '.': "analytics.events" is collection "events" in namespace "analytics".
+    // Nested namespaces are listed along with their parents ("a" and "a.b" for "a.b.c").
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut namespaces: Vec<String> = Vec::new();
+        for name in self.collection_names() {
+            let mut namespace = namespace_of(&name);
+            while let Some(current) = namespace {
+                namespaces.push(current.to_string());
+                namespace = namespace_of(current);
+            }
+        }
+        namespaces.sort();
+        namespaces.dedup();
+        namespaces
+    }
+
+    // Collections in `namespace` and its nested namespaces, sorted by name
+    pub fn collections_in(&self, namespace: &str) -> Vec<String> {
+        let prefix = format!("{}.", namespace.trim_end_matches('.'));
+        let mut names: Vec<String> = self.collection_names().into_iter()
+            .filter(|name| name.starts_with(&prefix))
+            .collect();
+        names.sort();
+        names
+    }
+
+    // Drop every collection of a namespace, returning their names. Foreign keys between them are
+    // allowed; a foreign key from a collection outside the namespace blocks the whole drop.
+    pub fn drop_namespace(&self, namespace: &str) -> Result<Vec<String>, EmemError> {
+        let names = self.collections_in(namespace);
+        for name in &names {
+            if let Some(collection) = self.collection_arc(name) {
+                self.check_droppable(&collection, &names)?;
+            }
+        }
+        for name in &names {
+            self.record(RecordedOp::DropCollection { collection: name.clone() });
+            self.remove_collection(name);
+        }
+        Ok(names)
+    }
+
+    // Foreign keys from collections that are not being dropped along with this one
+    fn check_droppable(&self, collection: &Collection, dropping: &[String]) -> Result<(), EmemError> {
+        if let Some((referencing, fk)) = collection.referencing_foreign_keys().into_iter()
+            .find(|(referencing, _)| !dropping.contains(&referencing.collection_name))
+        {
+            return Err(EmemError::ForeignKeyViolation(format!(
+                "Cannot drop '{}': referenced by foreign key '{}' in '{}'",
+                collection.collection_name, fk.field, referencing.collection_name
+            )));
+        }
+        Ok(())
+    }
+
+    fn remove_collection(&self, name: &str) {
+        let Some((_, collection)) = self.collections.write().unwrap().remove(name) else {
+            return;
+        };
+        self.materialized_views.remove(name);
+        {
+            let mut audit = self.audit.write().unwrap();
+            if audit.as_ref().map_or(false, |audit| Arc::ptr_eq(&audit.documents, &collection.documents)) {
+                audit.take();
+            }
+        }
+        collection.subscriptions.write().unwrap().clear();
+        collection.documents.clear();
+        self.emit_admin_event(AdminEvent::CollectionDropped { collection: name.to_string() });
+    }
+
+    // Move a collection to a new name, keeping its documents, subscriptions and TTLs. Foreign keys
+    // of other collections are repointed; handles obtained before the rename keep the old name.
+    pub fn rename_collection(&self, from: &str, to: &str) -> Result<(), EmemError> {
+        {
+            let registry = self.collections.write().unwrap();
+            if registry.contains_key(to) {
+                return Err(EmemError::CollectionExists(to.to_string()));
+            }
+            let (_, collection) = registry.remove(from)
+                .ok_or_else(|| EmemError::CollectionNotFound(from.to_string()))?;
+            let mut renamed = (*collection).clone();
+            renamed.collection_name = to.to_string();
+            registry.insert(to.to_string(), Arc::new(renamed));
+
+            let referencing: Vec<String> = registry.iter()
+                .filter(|r| r.value().foreign_keys.iter().any(|fk| fk.references_collection == from))
+                .map(|r| r.key().clone())
+                .collect();
+            for name in referencing {
+                if let Some(mut r) = registry.get_mut(&name) {
+                    let mut repointed = (**r.value()).clone();
+                    for fk in repointed.foreign_keys.iter_mut().filter(|fk| fk.references_collection == from) {
+                        fk.references_collection = to.to_string();
+                    }
+                    *r.value_mut() = Arc::new(repointed);
+                }
+            }
+        }
+        if let Some((_, view)) = self.materialized_views.remove(from) {
+            self.materialized_views.insert(to.to_string(), view);
+        }
+        self.record(RecordedOp::RenameCollection { collection: from.to_string(), to: to.to_string() });
+        self.emit_admin_event(AdminEvent::CollectionRenamed { from: from.to_string(), to: to.to_string() });
+        Ok(())
+    }
+
+    // Create `to` as an independent copy of `from`: documents (with their TTLs), configuration,
+    // validators, computed fields and the increment counter. Subscriptions, statistics, the backing
+    // store (loader) and handle settings (actor, role, query options) are not copied, and
+    // self-referencing foreign keys point at the copy.
+    pub fn copy_collection(&self, from: &str, to: &str) -> Result<Arc<Collection>, EmemError> {
+        let source = self.collection_arc(from)
+            .ok_or_else(|| EmemError::CollectionNotFound(from.to_string()))?;
+        if self.collection_arc(to).is_some() {
+            return Err(EmemError::CollectionExists(to.to_string()));
+        }
+
+        let mut copy = (*source).clone();
+        copy.collection_name = to.to_string();
+        copy.documents = Arc::new(source.documents.iter()
+            .map(|r| (r.key().clone(), r.value().detached()))
+            .collect());
+        copy.next_id = Arc::new(AtomicU64::new(source.next_id.load(Ordering::SeqCst)));
+        copy.schema_version = Arc::new(AtomicU64::new(source.schema_version()));
+        copy.validators = Arc::new(RwLock::new(source.validators.read().unwrap().clone()));
+        copy.computed_fields = Arc::new(RwLock::new(source.computed_fields.read().unwrap().clone()));
+        copy.metrics = Arc::new(CollectionMetrics::new());
+        copy.subscriptions = Arc::new(RwLock::new(Vec::new()));
+        // Writes to the copy must not reach the source's backing store
+        copy.loader = None;
+        copy.actor = None;
+        copy.role = None;
+        copy.query_options = QueryOptions::default();
+        // Indexes, sketches and bloom filters are the copy's own, built from its documents below
+        copy.trigram_indexes = source.trigram_indexes.iter().map(|index| Arc::new(TrigramIndex::new(index.field()))).collect();
+        copy.cardinality_sketches = source.cardinality_sketches.keys()
+            .map(|field| (field.clone(), Arc::new(Mutex::new(HyperLogLog::new()))))
+            .collect();
+        copy.bloom_filters = source.bloom_filters.iter()
+            .map(|(field, filter)| {
+                let filter = filter.read().unwrap();
+                (field.clone(), Arc::new(RwLock::new(BloomFilter::new(filter.capacity(), filter.false_positive_rate()))))
+            })
+            .collect();
+        for fk in copy.foreign_keys.iter_mut().filter(|fk| fk.references_collection == from) {
+            fk.references_collection = to.to_string();
+        }
+
+        copy.rebuild_indexes();
+
+        let copy = Arc::new(copy);
+        {
+            let registry = self.collections.write().unwrap();
+            if registry.contains_key(to) {
+                return Err(EmemError::CollectionExists(to.to_string()));
+            }
+            registry.insert(to.to_string(), copy.clone());
+        }
+        self.record(RecordedOp::CopyCollection { collection: from.to_string(), to: to.to_string() });
+        Ok(copy)
+    }
+
+    pub(crate) fn collection_arc(&self, name: &str) -> Option<Arc<Collection>> {
+        self.collections.read().unwrap().get(name).map(|r| r.value().clone())
+    }
+
+    pub(crate) fn collection_arcs(&self) -> Vec<Arc<Collection>> {
+        self.collections.read().unwrap().iter().map(|r| r.value().clone()).collect()
+    }
+
+    // Store the results of `query` as a collection named `name`, maintained as the source changes
+    pub fn create_materialized_view(&self, name: &str, query: QueryBuilder) -> Result<Arc<MaterializedView>, EmemError> {
+        if self.collection_arc(name).is_some() {
+            return Err(EmemError::CollectionExists(name.to_string()));
+        }
+        let view = Arc::new(Collection::new(
+            Arc::new(self.clone()),
+            self.name.clone(),
+            name.to_string(),
+            None,
+            KeyType::Custom,
+            Vec::new(),
+        ));
+        self.collections.write().unwrap().insert(name.to_string(), view.clone());
+        let materialized = MaterializedView::create(name, query, view).inspect_err(|_| {
+            self.collections.write().unwrap().remove(name);
+        })?;
+        self.materialized_views.insert(name.to_string(), materialized.clone());
+        Ok(materialized)
+    }
+
+    pub fn materialized_view(&self, name: &str) -> Option<Arc<MaterializedView>> {
+        self.materialized_views.get(name).map(|r| r.value().clone())
+    }
+
+    // Register a query under a name so it can be run later by name
+    pub fn create_view<F>(&self, name: &str, factory: F) -> Result<(), EmemError>
+    where
+        F: Fn() -> QueryBuilder + Send + Sync + 'static,
+    {
+        if self.views.contains_key(name) {
+            return Err(EmemError::ViewExists(name.to_string()));
+        }
+        self.views.insert(name.to_string(), SavedView::new(name, factory));
+        Ok(())
+    }
+
+    pub fn drop_view(&self, name: &str) -> bool {
+        self.views.remove(name).is_some()
+    }
+
+    pub fn view_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.views.iter().map(|r| r.key().clone()).collect();
+        names.sort();
+        names
+    }
+
+    // A fresh query for a saved view; further filters can be chained before executing
+    pub fn view(&self, name: &str) -> Result<QueryBuilder, EmemError> {
+        self.views.get(name)
+            .map(|view| view.query())
+            .ok_or_else(|| EmemError::ViewNotFound(name.to_string()))
+    }
+
+    pub fn execute_view(&self, name: &str) -> Result<Vec<Value>, EmemError> {
+        self.view(name)?.execute()
+    }
+
+    // Inner join of two collections on `src_key` = `target_key`, e.g.
+    // db.join("users", "orders", "id", "user_id"). Target fields come back prefixed with "joined_";
+    // use JoinBuilder directly for other join types and projections. Fails like a query when either
+    // collection can't be read.
+    pub fn join(&self, src: &str, target: &str, src_key: &str, target_key: &str) -> Result<Vec<Value>, EmemError> {
+        let src_collection = self.collection_arc(src).ok_or_else(|| EmemError::CollectionNotFound(src.to_string()))?;
+        let target_collection = self.collection_arc(target).ok_or_else(|| EmemError::CollectionNotFound(target.to_string()))?;
+        JoinBuilder::new(src_collection, target_collection).on(src_key, target_key).join_type(JoinType::Inner).execute()
+    }
+
+    // Run a SQL-like SELECT statement (see sql.rs)
+    pub fn query(&self, sql: &str) -> Result<Vec<Value>, EmemError> {
+        self.prepare(sql)?.execute()
+    }
+
+    // Parse a SELECT statement into a QueryBuilder without running it
+    pub fn prepare(&self, sql: &str) -> Result<QueryBuilder, EmemError> {
+        crate::sql::prepare(self, sql).map_err(EmemError::InvalidQuery)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::capture(self)
+    }
+
+    // Write every collection to a JSON snapshot file
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), EmemError> {
+        Ok(Snapshot::capture(self).write(path)?)
+    }
+
+    // Restore the collections of a snapshot file, returning the number of documents loaded
+    pub fn load_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<usize, EmemError> {
+        Ok(Snapshot::read(path)?.restore(self))
+    }
+
+    // Run a find/count/aggregate/insert command document (see protocol.rs)
+    pub fn execute_command(&self, command: &Value) -> Result<Value, EmemError> {
+        Ok(crate::protocol::execute(self, command)?)
+    }
+
+    // Foreign keys and $ref links pointing at documents that no longer exist
+    pub fn find_orphans(&self) -> OrphanReport {
+        crate::integrity::find_orphans(self)
+    }
+
+    pub fn fix_orphans(&self, report: &OrphanReport, policy: OrphanPolicy) -> Result<usize, EmemError> {
+        Ok(crate::integrity::fix_orphans(self, report, policy)?)
+    }
+
+    // Record every mutation and query to a JSON-lines file for later replay
+    pub fn start_recording(&self, path: &str) -> Result<Arc<OperationRecorder>, EmemError> {
+        let recorder = Arc::new(OperationRecorder::create(path)?);
+        *self.recorder.write().unwrap() = Some(recorder.clone());
+        Ok(recorder)
+    }
+
+    // start_recording with the given durability, e.g. SyncMode::GroupCommit(Duration::from_millis(5))
+    pub fn start_recording_with(&self, path: &str, sync_mode: SyncMode) -> Result<Arc<OperationRecorder>, EmemError> {
+        let recorder = OperationRecorder::create_with(path, sync_mode)?;
+        *self.recorder.write().unwrap() = Some(recorder.clone());
+        Ok(recorder)
+    }
+
+    // Durability barrier: returns once every operation recorded so far is on disk
+    pub fn flush(&self) -> Result<(), EmemError> {
+        match self.recorder.read().unwrap().as_ref() {
+            Some(recorder) => Ok(recorder.flush()?),
+            None => Ok(()),
+        }
+    }
+
+    pub fn stop_recording(&self) -> Result<(), EmemError> {
+        match self.recorder.write().unwrap().take() {
+            Some(recorder) => Ok(recorder.flush()?),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn record(&self, op: RecordedOp) {
+        if let Some(recorder) = self.recorder.read().unwrap().as_ref() {
+            recorder.record(op);
+        }
+    }
+
+    // Start keeping the last `capacity` writes with sequence numbers; returns the existing log
+    // when one is already enabled
+    pub fn enable_change_log(&self, capacity: usize) -> Arc<ChangeLog> {
+        let mut change_log = self.change_log.write().unwrap();
+        change_log.get_or_insert_with(|| Arc::new(ChangeLog::new(capacity))).clone()
+    }
+
+    pub fn change_log(&self) -> Option<Arc<ChangeLog>> {
+        self.change_log.read().unwrap().clone()
+    }
+
+    pub fn disable_change_log(&self) {
+        self.change_log.write().unwrap().take();
+    }
+
+    // Forward writes to `sink` from a background thread, retrying failed batches with backoff.
+    // Enables the change log when it is not enabled yet.
+    pub fn connect_sink<S: ChangeSink + 'static>(&self, sink: S, options: SinkOptions) -> Result<SinkConnector, EmemError> {
+        let change_log = self.enable_change_log(DEFAULT_CHANGE_LOG_CAPACITY);
+        Ok(SinkConnector::start(&change_log, sink, options)?)
+    }
+
+    // Record every insert/update/delete in the `_audit` system collection; returns that collection.
+    // Writes are attributed to the actor of the handle they go through (see Collection::as_actor).
+    pub fn enable_audit_log(&self) -> Arc<Collection> {
+        let mut audit = self.audit.write().unwrap();
+        if let Some(collection) = audit.as_ref() {
+            return collection.clone();
+        }
+        let collection = match self.collection_arc(AUDIT_COLLECTION) {
+            Some(collection) => collection,
+            None => self.create::<Value>()
+                .name(AUDIT_COLLECTION)
+                .key("seq")
+                .key_type(KeyType::Increment)
+                // The trail is kept regardless of the database's default TTL
+                .ttl(TTL::NoTTL)
+                .build(),
+        };
+        *audit = Some(collection.clone());
+        collection
+    }
+
+    // Stop auditing; entries recorded so far stay in the `_audit` collection
+    pub fn disable_audit_log(&self) {
+        self.audit.write().unwrap().take();
+    }
+
+    pub(crate) fn audit_collection(&self) -> Option<Arc<Collection>> {
+        self.audit.read().unwrap().clone()
+    }
+
+    // Every audit entry in write order
+    pub fn audit_log(&self) -> Vec<AuditEntry> {
+        let Some(collection) = self.collection_arc(AUDIT_COLLECTION) else {
+            return Vec::new();
+        };
+        let mut entries: Vec<(u64, AuditEntry)> = collection.documents.iter()
+            .filter_map(|r| Some((r.key().parse().ok()?, AuditEntry::from_document(&r.value().value)?)))
+            .collect();
+        entries.sort_by_key(|(seq, _)| *seq);
+        entries.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    // Add or replace a role that handles can be bound to
+    pub fn define_role(&self, role: Role) {
+        self.roles.insert(role.name.clone(), role);
+    }
+
+    pub fn remove_role(&self, name: &str) -> bool {
+        self.roles.remove(name).is_some()
+    }
+
+    pub fn role_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.roles.iter().map(|r| r.key().clone()).collect();
+        names.sort();
+        names
+    }
+
+    // Register an ordering for order_by_with, e.g. semantic versions or natural sort. Registering
+    // an existing name replaces it for queries built afterwards.
+    pub fn register_comparator<F>(&self, name: &str, compare: F)
+    where
+        F: Fn(&Value, &Value) -> std::cmp::Ordering + Send + Sync + 'static,
+    {
+        self.comparators.register(name, compare);
+    }
+
+    pub fn remove_comparator(&self, name: &str) -> bool {
+        self.comparators.remove(name)
+    }
+
+    pub fn comparator_names(&self) -> Vec<String> {
+        self.comparators.names()
+    }
+
+    pub(crate) fn comparator(&self, name: &str) -> Option<CompareFn> {
+        self.comparators.get(name)
+    }
+
+    // A handle limited to the grants of `role`. The role is copied, so later changes to it only
+    // apply to handles obtained afterwards.
+    pub fn handle(&self, role: &str) -> Result<DbHandle, EmemError> {
+        let role = self.roles.get(role)
+            .map(|r| r.value().clone())
+            .ok_or_else(|| EmemError::RoleNotFound(role.to_string()))?;
+        Ok(DbHandle::new(Arc::new(self.clone()), role))
+    }
+
+    pub fn config(&self) -> DbConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    // Change runtime settings in place; an admin event is emitted for every setting that changed
+    pub fn update_config<F: FnOnce(&mut DbConfig)>(&self, update: F) -> Result<(), EmemError> {
+        let changes = {
+            let mut config = self.config.write().unwrap();
+            let mut updated = config.clone();
+            update(&mut updated);
+            updated.validate()?;
+            let changes = config.changes(&updated);
+            *config = updated;
+            changes
+        };
+        for (setting, old, new) in changes {
+            self.emit_admin_event(AdminEvent::ConfigChanged { setting, old, new });
+        }
+        Ok(())
+    }
+
+    pub fn on_admin_event(&self, callback: impl Fn(&AdminEvent) + Send + Sync + 'static) {
+        self.admin_listeners.add(callback);
+    }
+
+    pub(crate) fn emit_admin_event(&self, event: AdminEvent) {
+        self.admin_listeners.emit(&event);
+    }
+
+    // Queries that exceeded DbConfig::slow_query_threshold, oldest first
+    pub fn slow_queries(&self) -> Vec<SlowQuery> {
+        self.slow_queries.entries()
+    }
+
+    pub fn clear_slow_queries(&self) {
+        self.slow_queries.clear();
+    }
+
+    // Called for every slow query as it is recorded
+    pub fn on_slow_query(&self, callback: impl Fn(&SlowQuery) + Send + Sync + 'static) {
+        self.slow_queries.listen(callback);
+    }
+
+    pub(crate) fn record_slow_query(&self, query: SlowQuery) {
+        self.slow_queries.record(query);
+    }
+
+    // Remove documents whose TTL has passed or that are older than the configured retention.
+    // Documents still referenced through a Restrict foreign key are kept until the reference is gone.
+    pub fn sweep_expired(&self) -> usize {
+        let config = self.config();
+        let now = SystemTime::now();
+        let mut removed = 0;
+        let mut used = 0;
+        for collection in self.collection_arcs() {
+            let expired: Vec<String> = collection.documents.iter()
+                .filter(|r| {
+                    let entry = r.value();
+                    entry.expiration.is_some_and(|at| at <= now)
+                        || config.retention.is_some_and(|retention| entry.created_at + retention <= now)
+                })
+                .map(|r| r.key().clone())
+                .collect();
+            for id in expired {
+                if !collection.documents.contains_key(&id) {
+                    continue;
+                }
+                if let Ok(entry) = collection.remove_document(&id) {
+                    removed += 1;
+                    collection.metrics.record_swept(entry.expiration.map_or(false, |at| at <= now));
+                    collection.notify(EventType::Delete, &id, &entry.value);
+                }
+            }
+            collection.expire_fields(now);
+            if config.memory_limit.is_some() {
+                used += collection.memory_used();
+            }
+        }
+        if removed > 0 {
+            self.emit_admin_event(AdminEvent::Swept { removed });
+        }
+        if let Some(limit) = config.memory_limit {
+            if used > limit {
+                self.emit_admin_event(AdminEvent::MemoryLimitExceeded { used, limit });
+            }
+        }
+        removed
+    }
+
+    // Run sweep_expired in the background every `sweeper_interval`; stops when the handle is dropped
+    pub fn start_sweeper(self: &Arc<Self>) -> Sweeper {
+        Sweeper::spawn(Arc::downgrade(self), self.config.clone())
+    }
+
+    // Approximate memory used by every collection
+    pub fn memory_stats(&self) -> DbMemoryStats {
+        DbMemoryStats::from_collections(self.collection_arcs().into_iter()
+            .map(|collection| (collection.collection_name.clone(), collection.memory_stats()))
+            .collect())
+    }
+
+    // Look up a primary key in every collection, returning (collection name, document) pairs
+    pub fn find_key(&self, key: &str) -> Vec<(String, Value)> {
+        let now = SystemTime::now();
+        let mut found: Vec<(String, Value)> = self.collections.read().unwrap().iter()
+            .filter_map(|r| {
+                r.value().documents.get(key)
+                    .filter(|entry| !r.value().is_deleted(&entry.value) && entry.expiration.is_none_or(|at| at > now))
+                    .map(|entry| {
+                        entry.touch();
+                        (r.key().clone(), Value::clone(&entry.value))
+                    })
+            })
+            .collect();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        found
+    }
+
+    // Fan a filter document (field -> expected value) out over the given collections (all when empty)
+    pub fn search(&self, collections: &[&str], filter: &Value) -> Result<Vec<(String, Value)>, EmemError> {
+        let conditions = filter.as_object().ok_or_else(|| EmemError::InvalidQuery("Search filter must be a JSON object.".to_string()))?;
+        let registry = self.collections.read().unwrap();
+        let mut names: Vec<String> = if collections.is_empty() {
+            registry.iter().map(|r| r.key().clone()).collect()
+        } else {
+            collections.iter().map(|s| s.to_string()).collect()
+        };
+        names.sort();
+
+        let now = SystemTime::now();
+        let mut found = Vec::new();
+        for name in names {
+            let collection = registry.get(&name)
+                .ok_or_else(|| EmemError::CollectionNotFound(name.to_string()))?;
+            for entry in collection.documents.iter() {
+                if entry.value().expiration.is_some_and(|at| at <= now) {
+                    continue;
+                }
+                let doc = &entry.value().value;
+                if !collection.is_deleted(doc) && conditions.iter().all(|(field, expected)| doc.get(field) == Some(expected)) {
+                    found.push((name.clone(), Value::clone(doc)));
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+// Logical clock ordering document accesses for LRU eviction
+static ACCESS_CLOCK: AtomicU64 = AtomicU64::new(0);
+
+fn next_access_tick() -> u64 {
+    ACCESS_CLOCK.fetch_add(1, Ordering::Relaxed)
+}
+
+// Estimated memory footprint of a document: the length of its JSON encoding
+pub fn estimate_size(value: &Value) -> usize {
+    serde_json::to_vec(value).map_or(0, |bytes| bytes.len())
 }
 
 #[derive(Debug, Clone)]
 pub struct DocumentEntry {
-    pub value: Value,
+    // Shared so reads can hand out the stored document without copying it
+    pub value: Arc<Value>,
     pub expiration: Option<SystemTime>, // None means no TTL
+    pub created_at: SystemTime,
+    pub size: usize,
+    created_tick: u64,
+    last_access: Arc<AtomicU64>,
+    access_count: Arc<AtomicU64>,
+    // Top-level fields with their own TTL (see Collection::expire_field)
+    pub field_expirations: HashMap<String, SystemTime>,
 }
 
 impl DocumentEntry {
     pub fn new(value: Value, expiration: Option<SystemTime>) -> Self {
+        let tick = next_access_tick();
         DocumentEntry {
-            value,
+            size: estimate_size(&value),
+            value: Arc::new(value),
             expiration,
+            created_at: SystemTime::now(),
+            created_tick: tick,
+            last_access: Arc::new(AtomicU64::new(tick)),
+            access_count: Arc::new(AtomicU64::new(0)),
+            field_expirations: HashMap::new(),
+        }
+    }
+
+    // Same document, TTL and creation time with its own access counters, for copying into
+    // another collection. The document itself is shared until either side writes it.
+    pub(crate) fn detached(&self) -> Self {
+        let tick = next_access_tick();
+        DocumentEntry {
+            value: self.value.clone(),
+            expiration: self.expiration,
+            created_at: self.created_at,
+            size: self.size,
+            created_tick: self.created_tick,
+            last_access: Arc::new(AtomicU64::new(tick)),
+            access_count: Arc::new(AtomicU64::new(0)),
+            field_expirations: self.field_expirations.clone(),
         }
     }
 
     pub fn set(&mut self, value: Value) {
-       self.value = value;
+       self.size = estimate_size(&value);
+       self.value = Arc::new(value);
+       self.touch();
+    }
+
+    // Mutable access to the stored document; copies it first if a reader still holds it
+    pub fn document_mut(&mut self) -> &mut Value {
+        Arc::make_mut(&mut self.value)
+    }
+
+    // Position in write order; lower means written earlier
+    pub fn insertion_order(&self) -> u64 {
+        self.created_tick
+    }
+
+    // Tick of the last read or write; lower means less recently used
+    pub fn last_access(&self) -> u64 {
+        self.last_access.load(Ordering::Relaxed)
+    }
+
+    // Number of reads and writes since the document was inserted
+    pub fn access_count(&self) -> u64 {
+        self.access_count.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn touch(&self) {
+        self.last_access.store(next_access_tick(), Ordering::Relaxed);
+        self.access_count.fetch_add(1, Ordering::Relaxed);
     }
 
     pub fn update (&mut self, value: Value) {
      // update specific fields in value
-        let mut new_value = self.value.clone();
+        let mut new_value = Value::clone(&self.value);
         for (key, val) in value.as_object().unwrap() {
             new_value[key] = val.clone();
         }
-        self.value = new_value;
+        self.set(new_value);
     }
 }
 
@@ -105,16 +1006,109 @@ impl Document {
     }
 }
 
+type ComputeFn = Arc<dyn Fn(&Value) -> Value + Send + Sync>;
+
+// Builds the key of a KeyType::Custom document from its fields (see CollectionBuilder::key_fn)
+#[derive(Clone)]
+pub struct KeyGenerator(Arc<dyn Fn(&Value) -> String + Send + Sync>);
+
+impl KeyGenerator {
+    pub fn new<F>(generate: F) -> Self
+    where
+        F: Fn(&Value) -> String + Send + Sync + 'static,
+    {
+        KeyGenerator(Arc::new(generate))
+    }
+
+    pub fn generate(&self, document: &Value) -> String {
+        (self.0)(document)
+    }
+}
+
+impl fmt::Debug for KeyGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "KeyGenerator")
+    }
+}
+
+// Field derived from the rest of the document, recalculated on every write
+#[derive(Clone)]
+pub struct ComputedField {
+    pub name: String,
+    compute: ComputeFn,
+}
+
+impl ComputedField {
+    pub fn new<F>(name: &str, compute: F) -> Self
+    where
+        F: Fn(&Value) -> Value + Send + Sync + 'static,
+    {
+        ComputedField {
+            name: name.to_string(),
+            compute: Arc::new(compute),
+        }
+    }
+
+    pub fn apply(&self, document: &mut Value) {
+        let value = (self.compute)(document);
+        document[self.name.as_str()] = value;
+    }
+}
+
+impl fmt::Debug for ComputedField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ComputedField({})", self.name)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Collection {
     pub parent_db: Arc<InMemoryDB>,
-    pub documents: DashMap<String, DocumentEntry>,
+    pub(crate) documents: Arc<DashMap<String, DocumentEntry>>,
     pub key_field: Option<String>,
     pub key_type: KeyType,
     pub unique_keys: Vec<String>,
+    // Next value handed out by KeyType::Increment
     pub next_id: Arc<std::sync::atomic::AtomicU64>,
+    pub increment: IncrementKey,
+    // Derives KeyType::Custom keys from the document instead of reading the key field
+    pub key_fn: Option<KeyGenerator>,
     pub db_name: String,
     pub collection_name: String,
+    pub validators: Arc<RwLock<Vec<Arc<dyn DocumentValidator>>>>,
+    pub computed_fields: Arc<RwLock<Vec<ComputedField>>>,
+    pub defaults: Vec<(String, Value)>,
+    pub metrics: Arc<CollectionMetrics>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub query_options: QueryOptions,
+    pub schema_version: Arc<std::sync::atomic::AtomicU64>,
+    pub soft_delete: bool,
+    // Estimated bytes above which the least recently used documents are evicted
+    pub memory_limit: Option<usize>,
+    // Capped collection: inserting beyond this many documents evicts the oldest
+    pub max_documents: Option<usize>,
+    // Largest accepted document, in bytes of its JSON encoding
+    pub max_document_size: Option<usize>,
+    pub eviction_policy: Arc<dyn EvictionPolicy>,
+    pub(crate) subscriptions: Arc<RwLock<Vec<Arc<Subscription<'static>>>>>,
+    // Types declared through CollectionConfig::field_types
+    pub field_types: Vec<(String, String)>,
+    // TTL for inserts and upserts given no TTL; falls back to the database default when None
+    pub default_ttl: Option<TTL>,
+    // Who writes through this handle, recorded in the audit log
+    pub actor: Option<String>,
+    // Set on handles obtained through a DbHandle; None means unrestricted
+    role: Option<Arc<Role>>,
+    // Backing store for get misses and write-through (see CollectionBuilder::loader)
+    pub loader: Option<Arc<dyn CacheLoader>>,
+    // Text analysis per field for text_search; other fields use Analyzer::default()
+    pub text_analyzers: HashMap<String, Analyzer>,
+    // Substring indexes used by contains and like (see CollectionBuilder::trigram_index)
+    pub trigram_indexes: Vec<Arc<TrigramIndex>>,
+    // Distinct values written to each tracked field (see CollectionBuilder::track_cardinality)
+    pub cardinality_sketches: HashMap<String, Arc<Mutex<HyperLogLog>>>,
+    // Values present in each field with a bloom filter (see CollectionBuilder::bloom_filter)
+    pub bloom_filters: HashMap<String, Arc<RwLock<BloomFilter>>>,
 }
 impl Collection {
     pub fn new(
@@ -127,63 +1121,656 @@ impl Collection {
     ) -> Self {
         Collection {
             parent_db,
-            documents: DashMap::new(),
+            documents: Arc::new(DashMap::new()),
             key_field,
             key_type,
             unique_keys,
             next_id: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            increment: IncrementKey::default(),
+            key_fn: None,
             db_name,
             collection_name,
+            validators: Arc::new(RwLock::new(Vec::new())),
+            computed_fields: Arc::new(RwLock::new(Vec::new())),
+            defaults: Vec::new(),
+            metrics: Arc::new(CollectionMetrics::new()),
+            foreign_keys: Vec::new(),
+            query_options: QueryOptions::default(),
+            schema_version: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            soft_delete: false,
+            memory_limit: None,
+            max_documents: None,
+            max_document_size: None,
+            eviction_policy: Arc::new(LruPolicy),
+            subscriptions: Arc::new(RwLock::new(Vec::new())),
+            field_types: Vec::new(),
+            default_ttl: None,
+            actor: None,
+            role: None,
+            loader: None,
+            text_analyzers: HashMap::new(),
+            trigram_indexes: Vec::new(),
+            cardinality_sketches: HashMap::new(),
+            bloom_filters: HashMap::new(),
         }
     }
 
+    pub fn schema_version(&self) -> u64 {
+        self.schema_version.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    // Transform every document to a new schema version. All documents are transformed and validated
+    // before anything is written, so a failing migration leaves the collection untouched.
+    pub fn migrate<F>(&self, version: u64, transform: F) -> Result<usize, EmemError>
+    where
+        F: Fn(&Value) -> Value,
+    {
+        self.authorize(Permission::Admin)?;
+        let current = self.schema_version();
+        if version <= current {
+            return Err(EmemError::Migration(format!("Collection '{}' is already at schema version {}", self.collection_name, current)));
+        }
+
+        let mut migrated = Vec::with_capacity(self.documents.len());
+        for entry in self.documents.iter() {
+            let mut document = transform(&entry.value().value);
+            if let Some(key_field) = &self.key_field {
+                if document.get(key_field) != entry.value().value.get(key_field) {
+                    return Err(EmemError::Migration(format!("Migration to version {} changed key field '{}' of document '{}'", version, key_field, entry.key())));
+                }
+            }
+            self.apply_computed_fields(&mut document);
+            self.validate_document(&document)
+                .map_err(|e| EmemError::Migration(format!("Migration to version {} failed for document '{}': {}", version, entry.key(), e)))?;
+            migrated.push((entry.key().clone(), document));
+        }
+
+        let count = migrated.len();
+        for (id, document) in migrated {
+            if let Some(mut entry) = self.documents.get_mut(&id) {
+                entry.set(document);
+            }
+        }
+        self.rebuild_indexes();
+        self.schema_version.store(version, std::sync::atomic::Ordering::SeqCst);
+        Ok(count)
+    }
+
+    // A handle whose queries default to the given options (max_scan, timeout, ...)
+    pub fn with_query_options(&self, options: QueryOptions) -> Arc<Collection> {
+        let mut handle = self.clone();
+        handle.query_options = options;
+        Arc::new(handle)
+    }
+
+    pub(crate) fn with_role(&self, role: Arc<Role>) -> Arc<Collection> {
+        let mut handle = self.clone();
+        handle.role = Some(role);
+        Arc::new(handle)
+    }
+
+    pub(crate) fn authorize(&self, required: Permission) -> Result<(), EmemError> {
+        match &self.role {
+            Some(role) => role.require(&self.collection_name, required),
+            None => Ok(()),
+        }
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        namespace_of(&self.collection_name)
+    }
+
+    // A handle whose writes are attributed to `actor` in the audit log
+    pub fn as_actor(&self, actor: &str) -> Arc<Collection> {
+        let mut handle = self.clone();
+        handle.actor = Some(actor.to_string());
+        Arc::new(handle)
+    }
+
+    // Returns the registered subscription so it can be passed to unsubscribe
+    pub fn subscribe(&self, subscription: Subscription<'static>) -> Result<Arc<Subscription<'static>>, EmemError> {
+        self.authorize(Permission::Read)?;
+        let subscription = Arc::new(subscription);
+        self.subscriptions.write().unwrap().push(subscription.clone());
+        Ok(subscription)
+    }
+
+    pub fn unsubscribe(&self, subscription: &Arc<Subscription<'static>>) -> bool {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        let before = subscriptions.len();
+        subscriptions.retain(|s| !Arc::ptr_eq(s, subscription));
+        subscriptions.len() != before
+    }
+
+    // Declared field types, or types inferred from the stored documents when none were declared.
+    // Fields holding values of different types are reported as "any".
+    pub fn schema_fields(&self) -> Vec<(String, String)> {
+        if !self.field_types.is_empty() {
+            return self.field_types.clone();
+        }
+        let mut fields: Vec<(String, String)> = Vec::new();
+        for r in self.documents.iter() {
+            if self.is_deleted(&r.value().value) {
+                continue;
+            }
+            for (field, value) in r.value().value.as_object().into_iter().flatten() {
+                if value.is_null() {
+                    continue;
+                }
+                let type_name = value_type_name(value);
+                match fields.iter_mut().find(|(f, _)| f == field) {
+                    Some((_, existing)) if existing != type_name => {
+                        // Integers widen to floats; anything else becomes "any"
+                        *existing = match (existing.as_str(), type_name) {
+                            ("integer", "float") | ("float", "integer") => "float".to_string(),
+                            _ => "any".to_string(),
+                        };
+                    }
+                    Some(_) => {}
+                    None => fields.push((field.clone(), type_name.to_string())),
+                }
+            }
+        }
+        fields.sort();
+        fields
+    }
+
+    pub(crate) fn notify(&self, event: EventType, id: &str, data: &Value) {
+        if let Some(change_log) = self.parent_db.change_log() {
+            let name = match event {
+                EventType::Insert => Some("insert"),
+                EventType::Update => Some("update"),
+                EventType::Delete => Some("delete"),
+                EventType::Evicted => Some("evicted"),
+                EventType::ColumnUpdate(_) | EventType::ColumnExpired(_) => None,
+            };
+            if let Some(name) = name {
+                change_log.append(&self.collection_name, name, id, data);
+            }
+        }
+        let matching: Vec<Arc<Subscription<'static>>> = self.subscriptions.read().unwrap().iter()
+            .filter(|s| s.matches(&event))
+            .cloned()
+            .collect();
+        for subscription in matching {
+            subscription.trigger(id, data);
+        }
+    }
+
+    // Fire the Insert/Update/Delete (and per-column) events for a completed write
+    fn notify_result(&self, result: &OperationResult) {
+        match result {
+            OperationResult::Inserted { id, document } => self.notify(EventType::Insert, id, document),
+            OperationResult::Updated { id, old_document, new_document, .. } => {
+                self.notify(EventType::Update, id, new_document);
+                if let Some(fields) = new_document.as_object() {
+                    for (field, value) in fields {
+                        if old_document.get(field) != Some(value) {
+                            self.notify(EventType::ColumnUpdate(field), id, new_document);
+                        }
+                    }
+                }
+            }
+            OperationResult::Deleted { id, document } => self.notify(EventType::Delete, id, document),
+        }
+    }
+
+    // Estimated size of all documents in bytes
+    pub fn memory_used(&self) -> usize {
+        self.documents.iter().map(|r| r.value().size).sum()
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        for r in self.documents.iter() {
+            let bytes = r.value().size;
+            stats.documents += 1;
+            stats.document_bytes += bytes;
+            stats.index_bytes += r.key().len() + std::mem::size_of::<String>() + std::mem::size_of::<DocumentEntry>();
+            stats.largest_documents.push(LargeDocument {
+                collection: self.collection_name.clone(),
+                id: r.key().clone(),
+                bytes,
+            });
+            if stats.largest_documents.len() > 4 * crate::metrics::LARGEST_DOCUMENTS {
+                MemoryStats::keep_largest(&mut stats.largest_documents);
+            }
+        }
+        MemoryStats::keep_largest(&mut stats.largest_documents);
+        stats.total_bytes = stats.document_bytes + stats.index_bytes;
+        stats
+    }
+
+    // Evict documents in eviction policy order until the collection fits its memory limit.
+    // The document just written (`keep`) is never evicted.
+    fn enforce_memory_limit(&self, keep: &str) -> usize {
+        let limit = match self.memory_limit {
+            Some(limit) => limit,
+            None => return 0,
+        };
+        let mut used = self.memory_used();
+        if used <= limit {
+            return 0;
+        }
+        let mut candidates: Vec<(f64, u64, String)> = self.documents.iter()
+            .filter(|r| r.key() != keep)
+            .map(|r| (self.eviction_policy.score(r.key(), r.value()), r.value().last_access(), r.key().clone()))
+            .collect();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+        let mut evicted = 0;
+        for (_, _, id) in candidates {
+            if used <= limit {
+                break;
+            }
+            if !self.documents.contains_key(&id) {
+                continue;
+            }
+            if let Ok(entry) = self.remove_document(&id) {
+                used = used.saturating_sub(entry.size);
+                evicted += 1;
+                self.notify(EventType::Evicted, &id, &entry.value);
+            }
+        }
+        evicted
+    }
+
+    // Evict the oldest documents until the collection holds at most max_documents
+    fn enforce_max_documents(&self, keep: &str) -> usize {
+        let max = match self.max_documents {
+            Some(max) => max,
+            None => return 0,
+        };
+        let excess = self.documents.len().saturating_sub(max);
+        if excess == 0 {
+            return 0;
+        }
+        let mut oldest: Vec<(u64, String)> = self.documents.iter()
+            .filter(|r| r.key() != keep)
+            .map(|r| (r.value().insertion_order(), r.key().clone()))
+            .collect();
+        oldest.sort();
+
+        let mut evicted = 0;
+        for (_, id) in oldest {
+            if evicted == excess {
+                break;
+            }
+            if !self.documents.contains_key(&id) {
+                continue;
+            }
+            if let Ok(entry) = self.remove_document(&id) {
+                evicted += 1;
+                self.notify(EventType::Evicted, &id, &entry.value);
+            }
+        }
+        evicted
+    }
+
+    // Document and field counts, index size, read/write counters and latency percentiles per
+    // operation type since creation (or the last reset_stats)
+    pub fn stats(&self) -> CollectionStats {
+        let mut stats = CollectionStats::from_metrics(&self.metrics);
+        let now = SystemTime::now();
+        let mut fields: Vec<(String, usize, HashSet<String>)> = Vec::new();
+        for r in self.documents.iter() {
+            let entry = r.value();
+            stats.index_bytes += r.key().len() + std::mem::size_of::<String>() + std::mem::size_of::<DocumentEntry>();
+            if self.is_deleted(&entry.value) {
+                continue;
+            }
+            if entry.expiration.map_or(false, |at| at <= now) {
+                stats.expired += 1;
+                continue;
+            }
+            stats.documents += 1;
+            for (field, value) in entry.value.as_object().into_iter().flatten() {
+                let position = match fields.iter().position(|(f, _, _)| f == field) {
+                    Some(position) => position,
+                    None => {
+                        fields.push((field.clone(), 0, HashSet::new()));
+                        fields.len() - 1
+                    }
+                };
+                fields[position].1 += 1;
+                fields[position].2.insert(value.to_string());
+            }
+        }
+        stats.fields = fields.into_iter()
+            .map(|(field, present, distinct)| FieldStats { field, present, distinct: distinct.len() })
+            .collect();
+        stats.fields.sort_by(|a, b| a.field.cmp(&b.field));
+        stats
+    }
+
+    // Min, max, mean and a 10-bucket histogram of a numeric field, computed from the live documents
+    pub fn field_stats(&self, field: &str) -> NumericFieldStats {
+        self.field_stats_with(field, DEFAULT_HISTOGRAM_BUCKETS)
+    }
+
+    pub fn field_stats_with(&self, field: &str, buckets: usize) -> NumericFieldStats {
+        let now = SystemTime::now();
+        let values: Vec<Value> = self.documents.iter()
+            .filter(|r| !self.is_deleted(&r.value().value) && r.value().expiration.map_or(true, |at| at > now))
+            .filter_map(|r| r.value().value.get(field).cloned())
+            .collect();
+        NumericFieldStats::from_values(field, &values, buckets)
+    }
+
+    // Expiration counters, with the number of documents that have a TTL and the nearest expiration
+    pub fn ttl_stats(&self) -> TtlStats {
+        let mut stats = TtlStats::from_metrics(&self.metrics);
+        let now = SystemTime::now();
+        for r in self.documents.iter() {
+            let entry = r.value();
+            let Some(at) = entry.expiration else { continue };
+            if self.is_deleted(&entry.value) {
+                continue;
+            }
+            if at <= now {
+                stats.pending += 1;
+            } else {
+                stats.expiring += 1;
+                if stats.next_expiration.map_or(true, |next| at < next) {
+                    stats.next_expiration = Some(at);
+                }
+            }
+        }
+        stats
+    }
+
+    pub fn reset_stats(&self) {
+        self.metrics.reset();
+    }
+
+    // Register a computed field; it is stored on the document and can be filtered/sorted like any other field
+    pub fn computed<F>(&self, name: &str, compute: F) -> Result<(), EmemError>
+    where
+        F: Fn(&Value) -> Value + Send + Sync + 'static,
+    {
+        self.authorize(Permission::Admin)?;
+        let mut computed_fields = self.computed_fields.write().unwrap();
+        computed_fields.retain(|c| c.name != name);
+        computed_fields.push(ComputedField::new(name, compute));
+        Ok(())
+    }
+
+    fn apply_defaults(&self, document: &mut Value) {
+        if let Some(map) = document.as_object_mut() {
+            for (field, value) in &self.defaults {
+                if !map.contains_key(field) {
+                    map.insert(field.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    fn apply_computed_fields(&self, document: &mut Value) {
+        if !document.is_object() {
+            return;
+        }
+        for computed in self.computed_fields.read().unwrap().iter() {
+            computed.apply(document);
+        }
+    }
+
+    // Register a validator; validators run in registration order on every write
+    pub fn add_validator<V: DocumentValidator + 'static>(&self, validator: V) -> Result<(), EmemError> {
+        self.authorize(Permission::Admin)?;
+        self.validators.write().unwrap().push(Arc::new(validator));
+        Ok(())
+    }
+
+    pub fn validator_names(&self) -> Vec<String> {
+        self.validators.read().unwrap().iter().map(|v| v.name().to_string()).collect()
+    }
+
+    fn validate_document(&self, document: &Value) -> Result<(), EmemError> {
+        if let Some(max) = self.max_document_size {
+            let size = estimate_size(document);
+            if size > max {
+                return Err(EmemError::DocumentTooLarge { size, limit: max, collection: self.collection_name.clone() });
+            }
+        }
+        for validator in self.validators.read().unwrap().iter() {
+            validator.validate(document)
+                .map_err(|message| EmemError::ValidationFailed { validator: validator.name().to_string(), message })?;
+        }
+        Ok(())
+    }
+
+    // Every foreign key value must point at an existing document in the referenced collection
+    fn check_foreign_keys(&self, document: &Value) -> Result<(), EmemError> {
+        for fk in &self.foreign_keys {
+            let value = match document.get(&fk.field) {
+                None | Some(Value::Null) => continue,
+                Some(value) => value,
+            };
+            let referenced = self.parent_db.collection_arc(&fk.references_collection)
+                .ok_or_else(|| EmemError::ForeignKeyViolation(format!("Referenced collection '{}' not found for foreign key '{}'", fk.references_collection, fk.field)))?;
+            if referenced.ids_where(&fk.references_field, value).is_empty() {
+                return Err(EmemError::ForeignKeyViolation(format!(
+                    "Foreign key violation: no document in '{}' with {} = {} (field '{}')",
+                    fk.references_collection, fk.references_field, value, fk.field
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn ids_where(&self, field: &str, value: &Value) -> Vec<String> {
+        if self.key_field.as_deref() == Some(field) {
+            if let Some(id) = value.as_str() {
+                return if self.documents.contains_key(id) { vec![id.to_string()] } else { vec![] };
+            }
+        }
+        self.documents.iter()
+            .filter(|r| r.value().value.get(field) == Some(value))
+            .map(|r| r.key().clone())
+            .collect()
+    }
+
+    // Foreign keys in the database that point at this collection
+    fn referencing_foreign_keys(&self) -> Vec<(Arc<Collection>, ForeignKey)> {
+        self.parent_db.collection_arcs().into_iter()
+            .flat_map(|collection| {
+                collection.foreign_keys.iter()
+                    .filter(|fk| fk.references_collection == self.collection_name)
+                    .map(|fk| (collection.clone(), fk.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    // Remove a document, applying the on-delete rule of every foreign key referencing it
+    pub(crate) fn remove_document(&self, key: &str) -> Result<DocumentEntry, EmemError> {
+        self.remove_document_if(key, |_| true)
+    }
+
+    // remove_document, but only while `condition` holds for the stored document; checked under the
+    // entry lock at removal time, otherwise DocumentNotFound is returned
+    fn remove_document_if<P: Fn(&Value) -> bool>(&self, key: &str, condition: P) -> Result<DocumentEntry, EmemError> {
+        let current = self.documents.get(key).map(|entry| Value::clone(&entry.value)).ok_or(EmemError::DocumentNotFound)?;
+        let referencing = self.referencing_foreign_keys();
+
+        for (collection, fk) in &referencing {
+            let Some(value) = current.get(&fk.references_field) else { continue };
+            match fk.on_delete {
+                OnDelete::Restrict => {
+                    let ids = collection.ids_where(&fk.field, value);
+                    if !ids.is_empty() {
+                        return Err(EmemError::ForeignKeyViolation(format!(
+                            "Cannot delete '{}': referenced by {} document(s) in '{}' via '{}'",
+                            key, ids.len(), collection.collection_name, fk.field
+                        )));
+                    }
+                }
+                // The referencing documents must still be valid with the field set to null
+                OnDelete::SetNull => {
+                    for id in collection.ids_where(&fk.field, value) {
+                        let Some(mut document) = collection.documents.get(&id).map(|entry| Value::clone(&entry.value)) else { continue };
+                        document[fk.field.as_str()] = Value::Null;
+                        collection.apply_computed_fields(&mut document);
+                        collection.validate_document(&document)?;
+                    }
+                }
+                OnDelete::Cascade => {}
+            }
+        }
+
+        let (_, entry) = self.documents.remove_if(key, |_, entry| condition(&entry.value)).ok_or(EmemError::DocumentNotFound)?;
+        self.reindex(key, Some(&entry.value), None);
+
+        for (collection, fk) in referencing {
+            let value = match entry.value.get(&fk.references_field) {
+                Some(value) => value,
+                None => continue,
+            };
+            let ids = collection.ids_where(&fk.field, value);
+            match fk.on_delete {
+                OnDelete::Restrict => {}
+                // Written as updates of the referencing collection: validated, indexed and announced
+                OnDelete::SetNull => {
+                    for id in ids {
+                        match collection.modify(&id, |document| {
+                            document[fk.field.as_str()] = Value::Null;
+                            Ok(())
+                        }) {
+                            Ok(_) | Err(EmemError::DocumentNotFound) => {}
+                            Err(e) => return Err(e),
+                        }
+                    }
+                }
+                OnDelete::Cascade => {
+                    for id in ids {
+                        if collection.documents.contains_key(&id) {
+                            collection.remove_document(&id)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(entry)
+    }
+
+    // Summary of the collection's configuration
+    pub fn describe(&self) -> Value {
+        json!({
+            "db": self.db_name,
+            "name": self.collection_name,
+            "key_field": self.key_field,
+            "key_type": format!("{:?}", self.key_type),
+            "unique_keys": self.unique_keys,
+            "schema_version": self.schema_version(),
+            "validators": self.validator_names(),
+            "computed_fields": self.computed_fields.read().unwrap().iter().map(|c| c.name.clone()).collect::<Vec<_>>(),
+            "foreign_keys": self.foreign_keys.iter()
+                .map(|fk| format!("{} -> {}.{} ({:?})", fk.field, fk.references_collection, fk.references_field, fk.on_delete))
+                .collect::<Vec<_>>(),
+            "soft_delete": self.soft_delete,
+            "memory_limit": self.memory_limit,
+            "max_documents": self.max_documents,
+            "max_document_size": self.max_document_size,
+            "default_ttl": self.default_ttl,
+            "eviction_policy": self.eviction_policy.name(),
+            "shards": self.shard_count(),
+            "loader": self.loader.as_ref().map(|loader| loader.name().to_string()),
+            "documents": self.documents.len(),
+        })
+    }
+
 
 
     // Insert supporting single and multiple objects
+    pub fn insert(&self, document: serde_json::Value, ttl: Option<TTL>) -> Result<OperationResult, EmemError> {
+        self.authorize(Permission::Write)?;
+        self.parent_db.record(RecordedOp::Insert {
+            collection: self.collection_name.clone(),
+            document: document.clone(),
+            ttl: ttl.clone(),
+        });
+        let started = Instant::now();
+        let result = self.insert_document(document, ttl, false);
+        self.metrics.record(OperationKind::Insert, started.elapsed());
+        self.after_write(&result);
+        result
+    }
+
+    // Insert unless a live document with the same key exists, checked and written under the
+    // document's shard lock so concurrent writers can't slip in between; fails with DocumentExists
+    fn insert_new(&self, document: Value, ttl: Option<TTL>) -> Result<OperationResult, EmemError> {
+        let recorded = RecordedOp::Insert {
+            collection: self.collection_name.clone(),
+            document: document.clone(),
+            ttl: ttl.clone(),
+        };
+        let started = Instant::now();
+        let result = self.insert_document(document, ttl, true);
+        // Recorded only once it applied; replaying a conflicting insert would overwrite
+        if result.is_ok() {
+            self.parent_db.record(recorded);
+        }
+        self.metrics.record(OperationKind::Insert, started.elapsed());
+        self.after_write(&result);
+        result
+    }
+
    // Handle insert logic <div class="title">2024년도 강동구약사회 연수교육 조회서비스</div>
-   pub fn insert(&self, mut document: serde_json::Value, ttl: Option<TTL>) -> Result<OperationResult, String> {
+   fn insert_document(&self, mut document: serde_json::Value, ttl: Option<TTL>, only_new: bool) -> Result<OperationResult, EmemError> {
+
+    let key_field = self.key_field.as_ref().ok_or(EmemError::KeyFieldNotSet)?;
 
-    let key_field = self.key_field.as_ref().ok_or("Key field is not set.")?;
+    // 기본값 채우기
+    self.apply_defaults(&mut document);
 
     // 키 생성
     let doc_id = match self.key_type {
         KeyType::Increment => {
-            let doc_id = self.next_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst).to_string();
-            doc_id
+            // Stops handing out keys rather than wrapping around to ones already used
+            let next = self.next_id.fetch_update(std::sync::atomic::Ordering::SeqCst, std::sync::atomic::Ordering::SeqCst, |id| id.checked_add(self.increment.step))
+                .map_err(|_| EmemError::Other(format!("Increment keys of collection '{}' are exhausted", self.collection_name)))?;
+            self.increment.format(next)
         }
         KeyType::UUID => Uuid::new_v4().to_string(),
-        KeyType::String | KeyType::Custom => {
-            document.get(key_field)
-                .ok_or_else(|| format!("{} field not found in the document.", key_field))?
-                .as_str()
-                .ok_or_else(|| format!("{} is not a string.", key_field))?
-                .to_string()
-        }
+        KeyType::ULID => keygen::ulid(),
+        KeyType::NanoId => keygen::nanoid(),
+        KeyType::String | KeyType::Custom => self.document_key(&document)?,
     };
 
     // 자동 생성된 키를 문서에 추가
-    if matches!(self.key_type, KeyType::Increment | KeyType::UUID) {
+    if matches!(self.key_type, KeyType::Increment | KeyType::UUID | KeyType::ULID | KeyType::NanoId) || self.generates_keys() {
         document[key_field] = json!(doc_id.clone());
     }
 
     // TTL 처리
-    let expiration = match ttl {
-        Some(TTL::GlobalTTL(seconds)) | Some(TTL::CustomTTL(seconds)) => 
-            Some(SystemTime::now() + Duration::from_secs(seconds)),
-        Some(TTL::NoTTL) | None => None,
-    };
+    let expiration = expiration_from(&self.resolve_ttl(ttl));
+
+    self.apply_computed_fields(&mut document);
+    self.validate_document(&document)?;
+    self.check_foreign_keys(&document)?;
 
     // 유니크 키 검증
     for unique_key in &self.unique_keys {
         if let Some(value) = document.get(unique_key) {
-            if self.documents.iter().any(|r| r.value().value.get(unique_key) == Some(value)) {
-                return Err(format!("Duplicate value for unique key: {}", unique_key));
+            if self.may_contain(unique_key, value) && self.documents.iter().any(|r| r.value().value.get(unique_key) == Some(value)) {
+                return Err(EmemError::DuplicateKey(unique_key.clone()));
             }
         }
     }
 
     // 문서를 컬렉션에 삽입
-      self.documents.insert(doc_id.clone(), DocumentEntry { value: document.clone(), expiration });
+    match self.documents.entry(doc_id.clone()) {
+        dashmap::mapref::entry::Entry::Occupied(existing) if only_new && !self.is_deleted(&existing.get().value) => {
+            return Err(EmemError::DocumentExists(doc_id));
+        }
+        dashmap::mapref::entry::Entry::Occupied(mut existing) => {
+            existing.insert(DocumentEntry::new(document.clone(), expiration));
+        }
+        dashmap::mapref::entry::Entry::Vacant(vacant) => {
+            vacant.insert(DocumentEntry::new(document.clone(), expiration));
+        }
+    }
      
 
 
@@ -193,69 +1780,761 @@ impl Collection {
         })
 
         }
-    // Update supporting single and multiple objects
-    pub fn upsert(&mut self, document: Value, ttl: Option<TTL>) -> Result<OperationResult, String> {
-        let key_field = self.key_field.as_ref().ok_or("Key field is not set.")?;
-        let doc_id = document.get(key_field)
-            .ok_or_else(|| format!("{} field not found in the document.", key_field))?
+    fn generates_keys(&self) -> bool {
+        self.key_type == KeyType::Custom && self.key_fn.is_some()
+    }
+
+    // Key of a document written by the caller: generated by key_fn for Custom keys when one is
+    // set, otherwise the string in the key field
+    pub(crate) fn document_key(&self, document: &Value) -> Result<String, EmemError> {
+        let key_field = self.key_field.as_ref().ok_or(EmemError::KeyFieldNotSet)?;
+        if let (KeyType::Custom, Some(key_fn)) = (&self.key_type, &self.key_fn) {
+            return Ok(key_fn.generate(document));
+        }
+        Ok(document.get(key_field)
+            .ok_or_else(|| EmemError::MissingKey(key_field.clone()))?
             .as_str()
-            .ok_or_else(|| format!("{} is not a string.", key_field))?;
+            .ok_or_else(|| EmemError::InvalidKey(key_field.clone()))?
+            .to_string())
+    }
+
+    // The TTL a write uses: the one given, else the collection's default, else the database's.
+    // TTL::NoTTL opts a document out of the defaults.
+    fn resolve_ttl(&self, ttl: Option<TTL>) -> TTL {
+        ttl.or_else(|| self.default_ttl.clone())
+            .unwrap_or_else(|| self.parent_db.default_ttl().clone())
+    }
+
+    // Insert any serializable value; it must serialize to a JSON object. The generated key is
+    // available from the result's `id()`.
+    pub fn insert_t<T: Serialize>(&self, value: &T, ttl: Option<TTL>) -> Result<OperationResult, EmemError> {
+        let document = serde_json::to_value(value)
+            .map_err(|e| EmemError::Other(format!("Failed to serialize document: {}", e)))?;
+        if !document.is_object() {
+            return Err(EmemError::InvalidQuery("Document must serialize to a JSON object.".to_string()));
+        }
+        self.insert(document, ttl)
+    }
+
+    // Update supporting single and multiple objects
+    pub fn upsert(&self, document: Value, ttl: Option<TTL>) -> Result<OperationResult, EmemError> {
+        self.authorize(Permission::Write)?;
+        self.parent_db.record(RecordedOp::Upsert {
+            collection: self.collection_name.clone(),
+            document: document.clone(),
+            ttl: ttl.clone(),
+        });
+        let started = Instant::now();
+        let result = self.upsert_document(document, ttl);
+        match result {
+            Ok(OperationResult::Updated { .. }) => self.metrics.record(OperationKind::Update, started.elapsed()),
+            Ok(OperationResult::Inserted { .. }) => self.metrics.record(OperationKind::Insert, started.elapsed()),
+            _ => {}
+        }
+        self.after_write(&result);
+        result
+    }
+
+    // Upsert every document by its key, collecting the ids that were inserted or updated and the
+    // errors of the ones that failed
+    pub fn upsert_many(&self, documents: Vec<Value>, ttl: Option<TTL>) -> Result<UpsertSummary, EmemError> {
+        self.authorize(Permission::Write)?;
+        let mut summary = UpsertSummary::default();
+        for (index, document) in documents.into_iter().enumerate() {
+            match self.upsert(document, ttl.clone()) {
+                Ok(OperationResult::Inserted { id, .. }) => summary.inserted.push(id),
+                Ok(OperationResult::Updated { id, .. }) => summary.updated.push(id),
+                Ok(OperationResult::Deleted { .. }) => {}
+                Err(error) => summary.failed.push((index, error)),
+            }
+        }
+        Ok(summary)
+    }
+
+    // Insert `document`, or resolve a key conflict as `on_conflict` says. Returns None when the
+    // document was ignored because of a conflict.
+    pub fn upsert_with(&self, document: Value, ttl: Option<TTL>, on_conflict: OnConflict) -> Result<Option<OperationResult>, EmemError> {
+        self.authorize(Permission::Write)?;
+        let id = self.document_key(&document)?;
+        if on_conflict == OnConflict::Replace {
+            return self.upsert(document, ttl).map(Some);
+        }
+        // Known conflicts skip validating the new document; the insert itself checks again
+        let exists = self.documents.get(&id).is_some_and(|entry| !self.is_deleted(&entry.value));
+        if !exists {
+            match self.insert_new(document.clone(), ttl.clone()) {
+                Err(EmemError::DocumentExists(_)) => {}
+                result => return result.map(Some),
+            }
+        }
+        match on_conflict {
+            OnConflict::Replace => unreachable!(),
+            OnConflict::Error => Err(EmemError::DocumentExists(id)),
+            OnConflict::Ignore => Ok(None),
+            OnConflict::MergeFields => {
+                let fields = document.as_object()
+                    .ok_or_else(|| EmemError::InvalidQuery("Document must be a JSON object.".to_string()))?;
+                let (result, _) = self.modify_as(&id, UpdateMode::Merge, ttl.as_ref(), |stored| {
+                    let map = stored.as_object_mut().ok_or(EmemError::DocumentNotFound)?;
+                    for (field, value) in fields {
+                        map.insert(field.clone(), value.clone());
+                    }
+                    Ok(())
+                })?;
+                Ok(Some(result))
+            }
+        }
+    }
+
+    fn upsert_document(&self, mut document: Value, ttl: Option<TTL>) -> Result<OperationResult, EmemError> {
+        let key_field = self.key_field.as_ref().ok_or(EmemError::KeyFieldNotSet)?;
+        let doc_id = self.document_key(&document)?;
+        if self.generates_keys() {
+            document[key_field.as_str()] = json!(doc_id);
+        }
+        let doc_id = doc_id.as_str();
     
         // 문서 존재 여부 확인
         if self.documents.contains_key(doc_id) {
             // 문서가 존재하면 업데이트
             let old_document = self.documents.get(doc_id)
-                .map(|entry| entry.value.clone())
+                .map(|entry| Value::clone(&entry.value))
                 .ok_or("Failed to get existing document")?;
     
-            let expiration = match ttl {
-                Some(TTL::GlobalTTL(seconds)) | Some(TTL::CustomTTL(seconds)) => 
-                    Some(SystemTime::now() + Duration::from_secs(seconds)),
-                Some(TTL::NoTTL) | None => None,
-            };
+            let expiration = expiration_from(&self.resolve_ttl(ttl));
+
+            self.apply_computed_fields(&mut document);
+            self.validate_document(&document)?;
+            self.check_foreign_keys(&document)?;
     
-            // self.documents.insert(doc_id.to_string(), DocumentEntry { value: document.clone(), expiration });
-            self.parent_db.collections.read().unwrap().get(&self.collection_name).unwrap().documents.insert(doc_id.to_string(), DocumentEntry { value: document.clone(), expiration });
+            self.documents.insert(doc_id.to_string(), DocumentEntry::new(document.clone(), expiration));
             Ok(OperationResult::Updated {
                 id: doc_id.to_string(),
                 old_document,
                 new_document: document,
+                mode: UpdateMode::Replace,
             })
         } else {
             // 문서가 존재하지 않으면 새로 삽입
-            // self.insert(document, ttl)
-            self.parent_db.collections.read().unwrap().get(&self.collection_name).unwrap().insert(document, ttl)
+            self.insert_document(document, ttl, false)
         }
     }
-    pub fn update(&mut self, document: Value) -> Result<OperationResult, String> {
-        let key_field = self.key_field.as_ref().ok_or("Key field is not set.")?;
+    // Write the top-level fields of `document` into the stored document with the same key,
+    // keeping fields it doesn't mention and the document's TTL
+    pub fn update(&self, document: Value) -> Result<OperationResult, EmemError> {
+        let key_field = self.key_field.as_ref().ok_or(EmemError::KeyFieldNotSet)?;
         let doc_id = document.get(key_field)
-            .ok_or("Key field not found in the document.")?
+            .ok_or_else(|| EmemError::MissingKey("Key".to_string()))?
             .as_str()
-            .ok_or("Key value is not a string.")?;
+            .ok_or_else(|| EmemError::InvalidKey("Key value".to_string()))?
+            .to_string();
+        let (result, _) = self.modify(&doc_id, |stored| {
+            let map = stored.as_object_mut().ok_or(EmemError::DocumentNotFound)?;
+            for (field, value) in document.as_object().into_iter().flatten() {
+                map.insert(field.clone(), value.clone());
+            }
+            Ok(())
+        })?;
+        Ok(result)
+    }
 
-        if let Some(mut entry) = self.documents.get_mut(doc_id) {
-            let old_document = entry.value.clone();
-            entry.value = document.clone();
-            Ok(OperationResult::Updated {
-                id: doc_id.to_string(),
-                old_document,
-                new_document: document,
-            })
+    // Merge the top-level fields of `document` into the document stored under `id`, but only if
+    // the stored version satisfies `predicate`. The check and the write are atomic: if another
+    // write lands in between, the predicate is evaluated again on the newer version. Returns
+    // whether the update was applied.
+    pub fn update_if<P>(&self, id: &str, predicate: P, document: Value) -> Result<bool, EmemError>
+    where
+        P: Fn(&Value) -> bool,
+    {
+        let fields = document.as_object()
+            .ok_or_else(|| EmemError::InvalidQuery("Update must be a JSON object.".to_string()))?;
+        if let Some(key_field) = &self.key_field {
+            if fields.get(key_field).map_or(false, |key| key.as_str() != Some(id)) {
+                return Err(EmemError::InvalidQuery(format!("Update cannot change key field '{}'", key_field)));
+            }
+        }
+        let modified = self.modify(id, |stored| {
+            if !predicate(stored) {
+                return Err(EmemError::ConditionFailed(id.to_string()));
+            }
+            let map = stored.as_object_mut().ok_or(EmemError::DocumentNotFound)?;
+            for (field, value) in fields {
+                map.insert(field.clone(), value.clone());
+            }
+            Ok(())
+        });
+        match modified {
+            Ok(_) => Ok(true),
+            Err(EmemError::ConditionFailed(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Overwrite the whole document stored under `id`. The TTL is kept unless a new one is given
+    // (TTL::NoTTL removes it). The key field is filled in when missing and cannot differ from `id`.
+    pub fn replace(&self, id: &str, mut document: Value, ttl: Option<TTL>) -> Result<OperationResult, EmemError> {
+        if !document.is_object() {
+            return Err(EmemError::InvalidQuery("Replacement must be a JSON object.".to_string()));
+        }
+        if let Some(key_field) = &self.key_field {
+            match document.get(key_field) {
+                None | Some(Value::Null) => document[key_field.as_str()] = json!(id),
+                Some(key) if key.as_str() == Some(id) => {}
+                Some(_) => return Err(EmemError::InvalidQuery(format!("Replacement cannot change key field '{}'", key_field))),
+            }
+        }
+        let (result, _) = self.modify_as(id, UpdateMode::Replace, ttl.as_ref(), |stored| {
+            *stored = document.clone();
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<OperationResult, EmemError> {
+        let result = self.delete_if(key, |_| true)?;
+        self.parent_db.record(RecordedOp::Delete {
+            collection: self.collection_name.clone(),
+            id: key.to_string(),
+        });
+        Ok(result)
+    }
+
+    // Not recorded; callers record the delete once they know it applies
+    fn delete_if<P: Fn(&Value) -> bool>(&self, key: &str, condition: P) -> Result<OperationResult, EmemError> {
+        self.authorize(Permission::Write)?;
+        let started = Instant::now();
+        let result = if self.soft_delete {
+            self.mark_deleted_if(key, condition)
         } else {
-            Err("Document not found.".to_string())
+            self.remove_document_if(key, condition).map(|entry| Value::clone(&entry.value))
+        }.map(|document| OperationResult::Deleted {
+            id: key.to_string(),
+            document,
+        });
+        self.metrics.record(OperationKind::Delete, started.elapsed());
+        self.after_write(&result);
+        result
+    }
+
+    // Put back `previous` under `id` (None: remove the document), e.g. to undo an atomic bulk
+    // write. Recorded, audited and announced like any other write.
+    pub(crate) fn revert_entry(&self, id: &str, previous: Option<DocumentEntry>) {
+        let current = self.documents.get(id).map(|entry| Value::clone(&entry.value));
+        let result = match (previous, current) {
+            (Some(entry), current) => {
+                let document = Value::clone(&entry.value);
+                self.parent_db.record(RecordedOp::Upsert {
+                    collection: self.collection_name.clone(),
+                    document: document.clone(),
+                    ttl: None,
+                });
+                self.documents.insert(id.to_string(), entry);
+                self.reindex(id, current.as_ref(), Some(&document));
+                match current {
+                    Some(old_document) => OperationResult::Updated {
+                        id: id.to_string(),
+                        old_document,
+                        new_document: document,
+                        mode: UpdateMode::Replace,
+                    },
+                    None => OperationResult::Inserted { id: id.to_string(), document },
+                }
+            }
+            (None, Some(_)) => {
+                self.parent_db.record(RecordedOp::Delete {
+                    collection: self.collection_name.clone(),
+                    id: id.to_string(),
+                });
+                match self.documents.remove(id) {
+                    Some((_, entry)) => {
+                        self.reindex(id, Some(&entry.value), None);
+                        OperationResult::Deleted { id: id.to_string(), document: Value::clone(&entry.value) }
+                    }
+                    None => return,
+                }
+            }
+            (None, None) => return,
+        };
+        self.audit(&result);
+        self.notify_result(&result);
+        self.write_through(&result);
+    }
+
+    // Pass a write on to the collection's CacheLoader
+    fn write_through(&self, result: &OperationResult) {
+        let Some(loader) = &self.loader else { return };
+        let written = match result {
+            OperationResult::Inserted { id, document } => loader.write(id, Some(document)),
+            OperationResult::Updated { id, new_document, .. } => loader.write(id, Some(new_document)),
+            OperationResult::Deleted { id, .. } => loader.write(id, None),
+        };
+        if let Err(error) = written {
+            self.parent_db.emit_admin_event(AdminEvent::WriteThroughFailed {
+                collection: self.collection_name.clone(),
+                id: result.id().to_string(),
+                error,
+            });
+        }
+    }
+
+    fn after_write(&self, result: &Result<OperationResult, EmemError>) {
+        if let Ok(result) = result {
+            // Removed documents leave the indexes in remove_document_if; soft-deleted ones stay
+            match result {
+                OperationResult::Inserted { id, document } => self.reindex(id, None, Some(document)),
+                OperationResult::Updated { id, old_document, new_document, .. } => self.reindex(id, Some(old_document), Some(new_document)),
+                OperationResult::Deleted { .. } => {}
+            }
+            self.audit(result);
+            self.notify_result(result);
+            self.write_through(result);
+            if let OperationResult::Inserted { id, .. } = result {
+                self.enforce_max_documents(id);
+            }
+            if let OperationResult::Inserted { id, .. } | OperationResult::Updated { id, .. } = result {
+                self.enforce_memory_limit(id);
+            }
+        }
+    }
+
+    // Remove every document, firing a Delete event for each, and return how many were removed.
+    // Configuration, subscriptions and the increment counter are kept.
+    pub fn truncate(&self) -> Result<usize, EmemError> {
+        self.authorize(Permission::Admin)?;
+        for (collection, fk) in self.referencing_foreign_keys() {
+            if fk.on_delete == OnDelete::Restrict && collection.documents.iter().any(|r| r.value().value.get(&fk.field).map_or(false, |v| !v.is_null())) {
+                return Err(EmemError::ForeignKeyViolation(format!(
+                    "Cannot truncate '{}': referenced by documents in '{}' via '{}'",
+                    self.collection_name, collection.collection_name, fk.field
+                )));
+            }
+        }
+        self.parent_db.record(RecordedOp::Truncate { collection: self.collection_name.clone() });
+
+        let ids: Vec<String> = self.documents.iter().map(|r| r.key().clone()).collect();
+        let mut removed = 0;
+        for id in ids {
+            if !self.documents.contains_key(&id) {
+                continue;
+            }
+            let entry = self.remove_document(&id)?;
+            removed += 1;
+            self.notify(EventType::Delete, &id, &entry.value);
+        }
+        Ok(removed)
+    }
+
+    fn audit(&self, result: &OperationResult) {
+        if self.collection_name == AUDIT_COLLECTION {
+            return;
+        }
+        if let Some(audit) = self.parent_db.audit_collection() {
+            let entry = AuditEntry::from_result(&self.collection_name, self.actor.as_deref(), result, now_millis());
+            if let Ok(inserted) = audit.insert_document(entry.to_document(), None, false) {
+                audit.notify_result(&inserted);
+            }
+        }
+    }
+
+    // Change one stored document with `change`, which may run more than once: the result is only
+    // stored if the document was not written in the meantime, otherwise `change` is re-run on the
+    // newer version. The swap happens under the entry lock, so concurrent modifications never
+    // overwrite each other. Returns the write result and the value produced by `change`.
+    fn modify<T, F>(&self, id: &str, change: F) -> Result<(OperationResult, T), EmemError>
+    where
+        F: FnMut(&mut Value) -> Result<T, EmemError>,
+    {
+        self.modify_as(id, UpdateMode::Merge, None, change)
+    }
+
+    // modify, optionally setting a new TTL with the write
+    fn modify_as<T, F>(&self, id: &str, mode: UpdateMode, ttl: Option<&TTL>, change: F) -> Result<(OperationResult, T), EmemError>
+    where
+        F: FnMut(&mut Value) -> Result<T, EmemError>,
+    {
+        self.modify_selected(id, mode, ttl, |document| !self.is_deleted(document), change)
+    }
+
+    // modify_as for a document `select` accepts (soft-deleted ones included)
+    fn modify_selected<T, F, P>(&self, id: &str, mode: UpdateMode, ttl: Option<&TTL>, select: P, change: F) -> Result<(OperationResult, T), EmemError>
+    where
+        F: FnMut(&mut Value) -> Result<T, EmemError>,
+        P: Fn(&Value) -> bool,
+    {
+        self.authorize(Permission::Write)?;
+        let started = Instant::now();
+        let (written, output) = self.swap_document(id, mode, ttl, select, change)?;
+        // Recorded as the resulting document so a replay doesn't depend on the stored state
+        if let OperationResult::Updated { new_document, .. } = &written {
+            self.parent_db.record(RecordedOp::Replace {
+                collection: self.collection_name.clone(),
+                id: id.to_string(),
+                document: new_document.clone(),
+                ttl: ttl.cloned(),
+            });
+        }
+        self.metrics.record(OperationKind::Update, started.elapsed());
+        let result = Ok(written);
+        self.after_write(&result);
+        result.map(|written| (written, output))
+    }
+
+    // Apply `change` to the document while `select` accepts it, validated like any update, and
+    // retry if another write lands in between. Not recorded or announced; see modify_selected.
+    fn swap_document<T, F, P>(&self, id: &str, mode: UpdateMode, ttl: Option<&TTL>, select: P, mut change: F) -> Result<(OperationResult, T), EmemError>
+    where
+        F: FnMut(&mut Value) -> Result<T, EmemError>,
+        P: Fn(&Value) -> bool,
+    {
+        loop {
+            let current = self.documents.get(id)
+                .filter(|entry| select(&entry.value))
+                .map(|entry| entry.value.clone())
+                .ok_or(EmemError::DocumentNotFound)?;
+            let mut document = Value::clone(&current);
+            let output = change(&mut document)?;
+            self.apply_computed_fields(&mut document);
+            self.validate_document(&document)?;
+            self.check_foreign_keys(&document)?;
+
+            let mut entry = self.documents.get_mut(id).ok_or(EmemError::DocumentNotFound)?;
+            if Arc::ptr_eq(&entry.value, &current) {
+                entry.set(document.clone());
+                // Field TTLs belong to the document being replaced
+                if mode == UpdateMode::Replace {
+                    entry.field_expirations.clear();
+                }
+                if let Some(ttl) = ttl {
+                    entry.expiration = expiration_from(ttl);
+                    self.metrics.record_ttl_touch();
+                }
+                return Ok((OperationResult::Updated {
+                    id: id.to_string(),
+                    old_document: Value::clone(&current),
+                    new_document: document,
+                    mode,
+                }, output));
+            }
+        }
+    }
+
+    // Add `by` to a numeric field (missing or null counts as 0) and return the new value
+    pub fn increment<T: Into<Value>>(&self, id: &str, field: &str, by: T) -> Result<Value, EmemError> {
+        self.numeric_update(id, field, by.into(), i64::checked_add, |a, b| a + b)
+    }
+
+    pub fn decrement<T: Into<Value>>(&self, id: &str, field: &str, by: T) -> Result<Value, EmemError> {
+        self.numeric_update(id, field, by.into(), i64::checked_sub, |a, b| a - b)
+    }
+
+    pub fn multiply<T: Into<Value>>(&self, id: &str, field: &str, by: T) -> Result<Value, EmemError> {
+        self.numeric_update(id, field, by.into(), i64::checked_mul, |a, b| a * b)
+    }
+
+    // Integers stay integers unless the result overflows i64, then the field becomes a float
+    fn numeric_update(
+        &self,
+        id: &str,
+        field: &str,
+        operand: Value,
+        integer_op: fn(i64, i64) -> Option<i64>,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Result<Value, EmemError> {
+        let number_expected = |field: &str| EmemError::TypeMismatch { field: field.to_string(), expected: "a number".to_string() };
+        let operand_f64 = operand.as_f64().ok_or_else(|| number_expected("operand"))?;
+        let (_, value) = self.modify(id, |document| {
+            let map = document.as_object_mut().ok_or_else(|| number_expected(field))?;
+            let current = match map.get(field) {
+                None | Some(Value::Null) => json!(0),
+                Some(value) => value.clone(),
+            };
+            let integer = match (current.as_i64(), operand.as_i64()) {
+                (Some(a), Some(b)) => integer_op(a, b).map(Value::from),
+                _ => None,
+            };
+            let value = match integer {
+                Some(value) => value,
+                None => {
+                    let current = current.as_f64().ok_or_else(|| number_expected(field))?;
+                    serde_json::Number::from_f64(float_op(current, operand_f64))
+                        .map(Value::Number)
+                        .ok_or_else(|| number_expected(field))?
+                }
+            };
+            map.insert(field.to_string(), value.clone());
+            Ok(value)
+        })?;
+        Ok(value)
+    }
+
+    // Apply a JSON Merge Patch (RFC 7386): nested objects merge recursively and null removes a key.
+    // The patch must be an object and cannot change the key field.
+    pub fn merge_patch(&self, id: &str, patch: &Value) -> Result<OperationResult, EmemError> {
+        if !patch.is_object() {
+            return Err(EmemError::InvalidQuery("Merge patch must be a JSON object.".to_string()));
+        }
+        let (result, _) = self.modify(id, |document| {
+            let key = self.key_field.as_ref().and_then(|key_field| document.get(key_field).cloned());
+            crate::patch::merge_patch(document, patch);
+            self.check_key_unchanged(key.as_ref(), document)
+        })?;
+        Ok(result)
+    }
+
+    // Apply JSON Patch (RFC 6902) operations as one write: if any operation fails, nothing is
+    // stored and the error names the failed operation. The key field cannot be changed.
+    pub fn apply_patch(&self, id: &str, ops: &[PatchOp]) -> Result<OperationResult, EmemError> {
+        let (result, _) = self.modify(id, |document| {
+            let key = self.key_field.as_ref().and_then(|key_field| document.get(key_field).cloned());
+            crate::patch::apply_patch(document, ops).map_err(|(index, message)| EmemError::PatchFailed {
+                index,
+                op: ops[index].name().to_string(),
+                message,
+            })?;
+            self.check_key_unchanged(key.as_ref(), document)
+        })?;
+        Ok(result)
+    }
+
+    fn check_key_unchanged(&self, key: Option<&Value>, document: &Value) -> Result<(), EmemError> {
+        match &self.key_field {
+            Some(key_field) if document.get(key_field) != key => {
+                Err(EmemError::InvalidQuery(format!("Patch cannot change key field '{}'", key_field)))
+            }
+            _ => Ok(()),
         }
     }
 
-    pub fn delete(&mut self, key: &str) -> Result<OperationResult, String> {
-        if let Some((_, entry)) = self.documents.remove(key) {
-            Ok(OperationResult::Deleted {
-                id: key.to_string(),
-                document: entry.value,
+    // Append `value` to an array field (missing or null starts an empty array); returns the new array
+    pub fn push<T: Into<Value>>(&self, id: &str, field: &str, value: T) -> Result<Value, EmemError> {
+        let value = value.into();
+        self.array_update(id, field, |array| array.push(value.clone()))
+    }
+
+    // Remove every element equal to `value`
+    pub fn pull<T: Into<Value>>(&self, id: &str, field: &str, value: T) -> Result<Value, EmemError> {
+        let value = value.into();
+        self.array_update(id, field, |array| array.retain(|element| element != &value))
+    }
+
+    // Append `value` unless the array already contains it
+    pub fn add_to_set<T: Into<Value>>(&self, id: &str, field: &str, value: T) -> Result<Value, EmemError> {
+        let value = value.into();
+        self.array_update(id, field, |array| {
+            if !array.contains(&value) {
+                array.push(value.clone());
+            }
+        })
+    }
+
+    fn array_update<F: FnMut(&mut Vec<Value>)>(&self, id: &str, field: &str, mut change: F) -> Result<Value, EmemError> {
+        let array_expected = || EmemError::TypeMismatch { field: field.to_string(), expected: "an array".to_string() };
+        let (_, array) = self.modify(id, |document| {
+            let map = document.as_object_mut().ok_or_else(array_expected)?;
+            let slot = map.entry(field.to_string()).or_insert_with(|| Value::Array(Vec::new()));
+            if slot.is_null() {
+                *slot = Value::Array(Vec::new());
+            }
+            let array = slot.as_array_mut().ok_or_else(array_expected)?;
+            change(array);
+            Ok(Value::Array(array.clone()))
+        })?;
+        Ok(array)
+    }
+
+    // Remove `field` from the document `ttl` from now, keeping the rest of the document. Expired
+    // fields are removed by sweep_expired (and the sweeper), which fires ColumnExpired(field).
+    // Replacing the whole document (replace, upsert) clears its field TTLs.
+    pub fn expire_field(&self, id: &str, field: &str, ttl: Duration) -> Result<(), EmemError> {
+        self.authorize(Permission::Write)?;
+        let mut entry = self.documents.get_mut(id)
+            .filter(|entry| !self.is_deleted(&entry.value))
+            .ok_or(EmemError::DocumentNotFound)?;
+        if entry.value.get(field).is_none() {
+            return Err(EmemError::MissingKey(field.to_string()));
+        }
+        entry.field_expirations.insert(field.to_string(), SystemTime::now() + ttl);
+        Ok(())
+    }
+
+    // Remove the field's TTL; returns whether it had one
+    pub fn persist_field(&self, id: &str, field: &str) -> Result<bool, EmemError> {
+        self.authorize(Permission::Write)?;
+        let mut entry = self.documents.get_mut(id).ok_or(EmemError::DocumentNotFound)?;
+        Ok(entry.field_expirations.remove(field).is_some())
+    }
+
+    // Time left before the field expires, None when it has no TTL
+    pub fn field_ttl(&self, id: &str, field: &str) -> Option<Duration> {
+        let entry = self.documents.get(id)?;
+        let expiration = entry.field_expirations.get(field)?;
+        Some(expiration.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+
+    // Remove every field whose TTL ran out by `now`, returning how many were removed. The removal
+    // is an ordinary update, so a field the validators require stays until they accept it.
+    pub(crate) fn expire_fields(&self, now: SystemTime) -> usize {
+        let due: Vec<(String, Vec<String>)> = self.documents.iter()
+            .filter_map(|r| {
+                let fields: Vec<String> = r.value().field_expirations.iter()
+                    .filter(|(_, at)| **at <= now)
+                    .map(|(field, _)| field.clone())
+                    .collect();
+                (!fields.is_empty()).then(|| (r.key().clone(), fields))
             })
-        } else {
-            Err("Document not found.".to_string())
+            .collect();
+        let mut expired = 0;
+        for (id, fields) in due {
+            let removed = self.modify(&id, |document| {
+                let map = document.as_object_mut().ok_or(EmemError::DocumentNotFound)?;
+                for field in &fields {
+                    map.remove(field);
+                }
+                Ok(())
+            });
+            if let Ok((OperationResult::Updated { new_document, .. }, ())) = removed {
+                if let Some(mut entry) = self.documents.get_mut(&id) {
+                    for field in &fields {
+                        entry.field_expirations.remove(field);
+                    }
+                }
+                for field in &fields {
+                    self.notify(EventType::ColumnExpired(field), &id, &new_document);
+                }
+                expired += fields.len();
+            }
+        }
+        self.metrics.record_expired_fields(expired);
+        expired
+    }
+
+    // Ids of the live documents matching `filter`, oldest first
+    fn ids_matching(&self, filter: Option<&FilterExpr>) -> Vec<String> {
+        let now = SystemTime::now();
+        let mut ids: Vec<(u64, String)> = self.documents.iter()
+            .filter(|r| {
+                let entry = r.value();
+                !self.is_deleted(&entry.value)
+                    && entry.expiration.map_or(true, |at| at > now)
+                    && filter.map_or(true, |filter| filter.matches(&entry.value))
+            })
+            .map(|r| (r.value().insertion_order(), r.key().clone()))
+            .collect();
+        ids.sort();
+        ids.into_iter().map(|(_, id)| id).collect()
+    }
+
+    // Merge the top-level fields of `update` into the oldest document matching a Mongo-style
+    // filter and return it as it was before or after the update (None when nothing matched).
+    // The filter is re-checked atomically with the write, so concurrent callers never claim the
+    // same document.
+    pub fn find_one_and_update(&self, filter: Value, update: Value, return_document: ReturnDocument) -> Result<Option<Value>, EmemError> {
+        let filter = crate::protocol::parse_filter(&filter)?;
+        let fields = update.as_object()
+            .ok_or_else(|| EmemError::InvalidQuery("Update must be a JSON object.".to_string()))?;
+        if let Some(key_field) = &self.key_field {
+            if fields.contains_key(key_field) {
+                return Err(EmemError::InvalidQuery(format!("Update cannot change key field '{}'", key_field)));
+            }
         }
+        for id in self.ids_matching(filter.as_ref()) {
+            let modified = self.modify(&id, |document| {
+                if !filter.as_ref().map_or(true, |filter| filter.matches(document)) {
+                    return Err(EmemError::DocumentNotFound);
+                }
+                let map = document.as_object_mut().ok_or(EmemError::DocumentNotFound)?;
+                for (field, value) in fields {
+                    map.insert(field.clone(), value.clone());
+                }
+                Ok(())
+            });
+            match modified {
+                Ok((OperationResult::Updated { old_document, new_document, .. }, ())) => {
+                    return Ok(Some(match return_document {
+                        ReturnDocument::Before => old_document,
+                        ReturnDocument::After => new_document,
+                    }));
+                }
+                Ok(_) => {}
+                // Changed or removed by someone else since it was picked; try the next one
+                Err(EmemError::DocumentNotFound) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    // Delete the oldest document matching a Mongo-style filter and return it
+    pub fn find_one_and_delete(&self, filter: Value) -> Result<Option<Value>, EmemError> {
+        let filter = crate::protocol::parse_filter(&filter)?;
+        for id in self.ids_matching(filter.as_ref()) {
+            match self.delete_if(&id, |document| filter.as_ref().map_or(true, |filter| filter.matches(document))) {
+                Ok(OperationResult::Deleted { document, .. }) => {
+                    self.parent_db.record(RecordedOp::Delete {
+                        collection: self.collection_name.clone(),
+                        id,
+                    });
+                    return Ok(Some(document));
+                }
+                Ok(_) => {}
+                Err(EmemError::DocumentNotFound) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn is_deleted(&self, document: &Value) -> bool {
+        self.soft_delete && document.get(DELETED_AT_FIELD).map_or(false, |v| !v.is_null())
+    }
+
+    // The caller announces the write as a delete
+    fn mark_deleted_if<P: Fn(&Value) -> bool>(&self, key: &str, condition: P) -> Result<Value, EmemError> {
+        let select = |document: &Value| !self.is_deleted(document) && condition(document);
+        let (written, ()) = self.swap_document(key, UpdateMode::Merge, None, select, |document| {
+            document[DELETED_AT_FIELD] = json!(now_millis());
+            Ok(())
+        })?;
+        match written {
+            OperationResult::Updated { new_document, .. } => Ok(new_document),
+            _ => Err(EmemError::DocumentNotFound),
+        }
+    }
+
+    // Undo a soft delete; recorded and announced as an update
+    pub fn restore(&self, key: &str) -> Result<OperationResult, EmemError> {
+        self.authorize(Permission::Write)?;
+        match self.documents.get(key) {
+            None => return Err(EmemError::DocumentNotFound),
+            Some(entry) if !self.is_deleted(&entry.value) => return Err(EmemError::NotDeleted(key.to_string())),
+            Some(_) => {}
+        }
+        let (result, ()) = self.modify_selected(key, UpdateMode::Merge, None, |document| self.is_deleted(document), |document| {
+            if let Some(map) = document.as_object_mut() {
+                map.remove(DELETED_AT_FIELD);
+            }
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    // Permanently remove documents soft-deleted at least `older_than` ago, returning how many were removed
+    pub fn purge_deleted(&self, older_than: Duration) -> Result<usize, EmemError> {
+        self.authorize(Permission::Write)?;
+        let cutoff = now_millis().saturating_sub(older_than.as_millis() as u64);
+        let tombstones: Vec<String> = self.documents.iter()
+            .filter(|r| self.is_deleted(&r.value().value))
+            .filter(|r| r.value().value.get(DELETED_AT_FIELD).and_then(|v| v.as_u64()).map_or(true, |at| at <= cutoff))
+            .map(|r| r.key().clone())
+            .collect();
+        let mut purged = 0;
+        for id in tombstones {
+            if self.documents.contains_key(&id) {
+                self.remove_document(&id)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
     }
 
     // Select chainable operations for building queries
@@ -268,9 +2547,247 @@ impl Collection {
         }
     }
 
-    pub fn reset_documents(&mut self, documents: Document) {
+    // Analyzer configured for `field`, or the default one
+    pub fn analyzer(&self, field: &str) -> Analyzer {
+        self.text_analyzers.get(field).cloned().unwrap_or_default()
+    }
+
+    // Stored documents, counting expired and soft-deleted ones not yet removed
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.documents.shards().len()
+    }
+
+    // Call `visit` on every document, spreading the shards of the document map over up to one
+    // thread per core, and collect what it returns. Each shard is read-locked while it is visited,
+    // so `visit` must not write to this collection.
+    pub fn parallel_scan<T, F>(&self, visit: F) -> Result<Vec<T>, EmemError>
+    where
+        T: Send,
+        F: Fn(&str, &DocumentEntry) -> Option<T> + Sync,
+    {
+        self.authorize(Permission::Read)?;
+        let shards = self.documents.shards();
+        let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(shards.len());
+        if threads <= 1 {
+            return Ok(self.documents.iter().filter_map(|r| visit(r.key(), r.value())).collect());
+        }
+        let visit = &visit;
+        Ok(thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|worker| scope.spawn(move || {
+                    let mut found = Vec::new();
+                    for shard in shards.iter().skip(worker).step_by(threads) {
+                        let shard = shard.read();
+                        found.extend(shard.iter().filter_map(|(key, entry)| visit(key, entry.get())));
+                    }
+                    found
+                }))
+                .collect();
+            workers.into_iter()
+                .flat_map(|worker| worker.join().unwrap_or_default())
+                .collect()
+        }))
+    }
+
+    // Document stored under `id`. On a miss, a collection with a CacheLoader loads the document
+    // and keeps it with the default TTL; loading is not a write, so it fires no events and isn't
+    // written back.
+    pub fn get(&self, id: &str) -> Result<Option<Value>, EmemError> {
+        self.authorize(Permission::Read)?;
+        if let Some(entry) = self.documents.get(id) {
+            if entry.expiration.map_or(true, |expiration| expiration > SystemTime::now()) && !self.is_deleted(&entry.value) {
+                entry.touch();
+                return Ok(Some(Value::clone(&entry.value)));
+            }
+        }
+        let Some(loader) = &self.loader else { return Ok(None) };
+        let loaded = loader.load(id)
+            .map_err(|e| EmemError::Other(format!("Cache loader '{}' failed to load '{}': {}", loader.name(), id, e)))?;
+        let Some(mut document) = loaded else { return Ok(None) };
+        if let Some(key_field) = &self.key_field {
+            if document.get(key_field).map_or(true, Value::is_null) {
+                document[key_field.as_str()] = json!(id);
+            }
+        }
+        self.apply_computed_fields(&mut document);
+        let expiration = expiration_from(&self.resolve_ttl(None));
+        self.documents.insert(id.to_string(), DocumentEntry::new(document.clone(), expiration));
+        self.reindex(id, None, Some(&document));
+        self.enforce_max_documents(id);
+        self.enforce_memory_limit(id);
+        Ok(Some(document))
+    }
+
+    // Aggregation pipeline over this collection (see pipeline.rs)
+    pub fn pipeline(&self) -> Pipeline {
+        Pipeline::new(Arc::new(self.clone()))
+    }
+
+    // Batch of inserts, updates and deletes applied in order with one summary
+    pub fn bulk(&self) -> BulkWriteBuilder {
+        BulkWriteBuilder::new(Arc::new(self.clone()))
+    }
+
+    // Every field except the comma-separated `fields`, e.g. select_except("password, ssn")
+    pub fn select_except(&self, fields: &str) -> QueryBuilder {
+        self.select("*").except(fields)
+    }
+
+    // Documents matching a MongoDB-style filter document, e.g.
+    // {"age": {"$gte": 18}, "name": {"$regex": "^J"}, "$or": [{"city": "Seoul"}, {"vip": true}]}
+    pub fn find(&self, filter: Value) -> Result<Vec<Value>, EmemError> {
+        let mut query = self.select("*");
+        if let Some(expr) = crate::protocol::parse_filter(&filter)? {
+            query = query.where_expr(expr);
+        }
+        query.execute()
+    }
+
+    // Build a query from PostgREST-style parameters, e.g. "age=gte.18&select=name,age&order=age.desc"
+    pub fn query_params(&self, params: &str) -> Result<QueryBuilder, EmemError> {
+        crate::rest::apply(self.select("*"), params).map_err(EmemError::InvalidQuery)
+    }
+
+    // Rebuild a query from a saved QuerySpec
+    pub fn query(&self, spec: &QuerySpec) -> QueryBuilder {
+        let mut query = self.select(&spec.fields.join(","));
+        if let Some(filter) = &spec.filter {
+            query = query.where_expr(filter.clone());
+        }
+        for (field, order) in &spec.order_by {
+            query = query.order_by(field, *order);
+        }
+        if let Some(limit) = spec.limit {
+            query = query.limit(limit);
+        }
+        query.offset(spec.offset)
+    }
+
+    pub fn reset_documents(&self, documents: Document) -> Result<(), EmemError> {
+        self.authorize(Permission::Admin)?;
         self.documents.clear();
-        self.documents = documents.documents;
+        for (key, entry) in documents.documents {
+            self.documents.insert(key, entry);
+        }
+        self.rebuild_indexes();
+        Ok(())
+    }
+
+    // Move a document's index entries from `old` to `new` (None when absent)
+    pub(crate) fn reindex(&self, id: &str, old: Option<&Value>, new: Option<&Value>) {
+        for index in &self.trigram_indexes {
+            if let Some(old) = old {
+                index.remove(id, old);
+            }
+            if let Some(new) = new {
+                index.insert(id, new);
+            }
+        }
+        for (field, filter) in &self.bloom_filters {
+            let before = old.and_then(|old| old.get(field));
+            let after = new.and_then(|new| new.get(field));
+            if before == after {
+                continue;
+            }
+            let mut filter = filter.write().unwrap();
+            if before.is_some() {
+                filter.mark_removed();
+            }
+            if let Some(value) = after {
+                filter.insert(&value.to_string());
+            }
+        }
+        if let Some(new) = new {
+            for (field, sketch) in &self.cardinality_sketches {
+                if let Some(value) = new.get(field) {
+                    sketch.lock().unwrap().insert_value(value);
+                }
+            }
+        }
+    }
+
+    // Index every stored document again, for documents written around the collection's methods.
+    // Also drops values of removed documents from the cardinality sketches.
+    pub fn rebuild_indexes(&self) {
+        for index in &self.trigram_indexes {
+            index.clear();
+        }
+        for sketch in self.cardinality_sketches.values() {
+            sketch.lock().unwrap().clear();
+        }
+        for filter in self.bloom_filters.values() {
+            let mut filter = filter.write().unwrap();
+            *filter = BloomFilter::new(filter.capacity().max(self.documents.len() * 2), filter.false_positive_rate());
+        }
+        for entry in self.documents.iter() {
+            self.reindex(entry.key(), None, Some(&entry.value));
+        }
+    }
+
+    // False when no stored document can have `value` in `field`, going by the field's bloom filter;
+    // always true for fields without one. Rebuilds the filter first when it has gone stale.
+    pub(crate) fn may_contain(&self, field: &str, value: &Value) -> bool {
+        let Some(filter) = self.bloom_filters.get(field) else { return true };
+        if filter.read().unwrap().needs_rebuild() {
+            // Held while reading the documents, so writes made meanwhile land in the new filter
+            let mut filter = filter.write().unwrap();
+            if filter.needs_rebuild() {
+                *filter = BloomFilter::new(filter.capacity().max(self.documents.len() * 2), filter.false_positive_rate());
+                for entry in self.documents.iter() {
+                    if let Some(value) = entry.value.get(field) {
+                        filter.insert(&value.to_string());
+                    }
+                }
+            }
+        }
+        filter.read().unwrap().contains(&value.to_string())
+    }
+
+    // Whether a live document has the key `id`. Keys missing from the key field's bloom filter are
+    // rejected without reading the collection.
+    pub fn exists(&self, id: &str) -> bool {
+        if let Some(key_field) = &self.key_field {
+            if !self.may_contain(key_field, &json!(id)) {
+                return false;
+            }
+        }
+        let now = SystemTime::now();
+        self.documents.get(id)
+            .is_some_and(|entry| !self.is_deleted(&entry.value) && entry.expiration.is_none_or(|at| at > now))
+    }
+
+    // Approximate number of distinct values of `field`. Tracked fields answer from their sketch,
+    // which counts every value written since the collection was built (or rebuild_indexes ran),
+    // including values of documents since removed or changed; other fields are estimated from a
+    // scan of the live documents.
+    pub fn estimated_cardinality(&self, field: &str) -> u64 {
+        if let Some(sketch) = self.cardinality_sketches.get(field) {
+            return sketch.lock().unwrap().estimate();
+        }
+        let now = SystemTime::now();
+        let mut sketch = HyperLogLog::new();
+        for r in self.documents.iter() {
+            let entry = r.value();
+            if self.is_deleted(&entry.value) || entry.expiration.map_or(false, |at| at <= now) {
+                continue;
+            }
+            if let Some(value) = entry.value.get(field) {
+                sketch.insert_value(value);
+            }
+        }
+        sketch.estimate()
+    }
+
+    pub fn trigram_index(&self, field: &str) -> Option<&Arc<TrigramIndex>> {
+        self.trigram_indexes.iter().find(|index| index.field() == field)
     }
 }
 
@@ -281,6 +2798,25 @@ pub struct CollectionBuilder<'a, T> {
     key_field: Option<String>,
     key_type: KeyType,
     unique_keys: Vec<String>,
+    validators: Vec<Arc<dyn DocumentValidator>>,
+    computed_fields: Vec<ComputedField>,
+    defaults: Vec<(String, Value)>,
+    foreign_keys: Vec<ForeignKey>,
+    soft_delete: bool,
+    memory_limit: Option<usize>,
+    max_documents: Option<usize>,
+    max_document_size: Option<usize>,
+    eviction_policy: Option<Arc<dyn EvictionPolicy>>,
+    loader: Option<Arc<dyn CacheLoader>>,
+    text_analyzers: HashMap<String, Analyzer>,
+    trigram_fields: Vec<String>,
+    cardinality_fields: Vec<String>,
+    bloom_fields: Vec<String>,
+    field_types: Vec<(String, String)>,
+    default_ttl: Option<TTL>,
+    increment: IncrementKey,
+    key_fn: Option<KeyGenerator>,
+    shards: Option<usize>,
     _marker: std::marker::PhantomData<T>,
 }
 impl<'a, T> CollectionBuilder<'a, T> {
@@ -291,6 +2827,25 @@ impl<'a, T> CollectionBuilder<'a, T> {
                 key_field: None,
                 key_type: KeyType::UUID,
                 unique_keys: Vec::new(),
+                validators: Vec::new(),
+                computed_fields: Vec::new(),
+                defaults: Vec::new(),
+                foreign_keys: Vec::new(),
+                soft_delete: false,
+                memory_limit: None,
+                max_documents: None,
+                max_document_size: None,
+                eviction_policy: None,
+                loader: None,
+                text_analyzers: HashMap::new(),
+                trigram_fields: Vec::new(),
+                cardinality_fields: Vec::new(),
+                bloom_fields: Vec::new(),
+                field_types: Vec::new(),
+                default_ttl: None,
+                increment: IncrementKey::default(),
+                key_fn: None,
+                shards: None,
                 _marker: std::marker::PhantomData,
             }
         }
@@ -318,12 +2873,200 @@ impl<'a, T> CollectionBuilder<'a, T> {
             self
         }
 
-    // Build the collection
+    // Default values for fields missing on insert
+    pub fn defaults(mut self, defaults: Vec<(&str, Value)>) -> Self {
+        self.defaults = defaults.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+        self
+    }
+
+    // delete marks documents with `_deleted_at`; see Collection::restore and Collection::purge_deleted
+    pub fn soft_delete(mut self) -> Self {
+        self.soft_delete = true;
+        self
+    }
+
+    // Evict least recently used documents once their estimated size exceeds `bytes`
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    // Bounded ring: once `max` documents are stored, each insert evicts the oldest one
+    pub fn max_documents(mut self, max: usize) -> Self {
+        self.max_documents = Some(max);
+        self
+    }
+
+    // Start, step and format of KeyType::Increment keys
+    pub fn increment(mut self, increment: IncrementKey) -> Self {
+        self.increment = increment;
+        self
+    }
+
+    // Generate keys from the document, e.g. `key_fn(|doc| format!("{}:{}", doc["region"], doc["email"]))`.
+    // Implies KeyType::Custom; the generated key is written to the key field on insert and upsert.
+    pub fn key_fn<F>(mut self, generate: F) -> Self
+    where
+        F: Fn(&Value) -> String + Send + Sync + 'static,
+    {
+        self.key_type = KeyType::Custom;
+        self.key_fn = Some(KeyGenerator::new(generate));
+        self
+    }
+
+    // Default TTL for documents inserted without one (overrides the database default)
+    pub fn ttl(mut self, ttl: TTL) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    // Reject writes whose JSON encoding is larger than `bytes`
+    pub fn max_document_size(mut self, bytes: usize) -> Self {
+        self.max_document_size = Some(bytes);
+        self
+    }
+
+    // Which documents memory_limit evicts first (least recently used by default)
+    pub fn eviction_policy<P: EvictionPolicy + 'static>(mut self, policy: P) -> Self {
+        self.eviction_policy = Some(Arc::new(policy));
+        self
+    }
+
+    // Declare field types; writes are validated against them and schema_fields reports them
+    pub fn field_types(mut self, types: Vec<(String, String)>) -> Self {
+        if !types.is_empty() {
+            self.validators.push(Arc::new(SchemaValidator::new(types.iter().map(|(f, t)| (f.as_str(), t.as_str())).collect())));
+        }
+        self.field_types = types;
+        self
+    }
+
+    // Apply the options declared in a CollectionConfig
+    pub fn with_config(mut self, config: &CollectionConfig) -> Self {
+        if let Some(key_field) = config.key_field {
+            self.key_field = Some(key_field.to_string());
+        }
+        if let Some(key_type) = &config.key_type {
+            self.key_type = key_type.clone();
+        }
+        if !config.unique_keys.is_empty() {
+            self.unique_keys = config.unique_keys.iter().map(|s| s.to_string()).collect();
+        }
+        if !config.defaults.is_empty() {
+            self.defaults = config.defaults.iter().map(|(k, v)| (k.to_string(), v.clone())).collect();
+        }
+        self.foreign_keys.extend(config.foreign_keys.iter().cloned());
+        self.soft_delete |= config.soft_delete;
+        if config.memory_limit.is_some() {
+            self.memory_limit = config.memory_limit;
+        }
+        if config.max_document_size.is_some() {
+            self.max_document_size = config.max_document_size;
+        }
+        if config.ttl.is_some() {
+            self.default_ttl = config.ttl.clone();
+        }
+        if let Some(increment) = &config.increment {
+            self.increment = increment.clone();
+        }
+        if let Some(shards) = config.shards {
+            self = self.shards(shards);
+        }
+        if config.eviction_policy.is_some() {
+            self.eviction_policy = config.eviction_policy.clone();
+        }
+        if !config.field_types.is_empty() {
+            self.field_types = config.field_types.iter().map(|(f, t)| (f.to_string(), t.to_string())).collect();
+        }
+        let schema = SchemaValidator::from_config(config);
+        if !schema.is_empty() {
+            self.validators.push(Arc::new(schema));
+        }
+        for check in &config.checks {
+            self.validators.push(Arc::new(check.clone()));
+        }
+        self
+    }
+
+    // How text_search analyzes `field`
+    pub fn text_analyzer(mut self, field: &str, analyzer: Analyzer) -> Self {
+        self.text_analyzers.insert(field.to_string(), analyzer);
+        self
+    }
+
+    // Keep a trigram index on the string field `field`, so contains and like on it read only
+    // candidate documents instead of the whole collection
+    pub fn trigram_index(mut self, field: &str) -> Self {
+        if !self.trigram_fields.iter().any(|f| f == field) {
+            self.trigram_fields.push(field.to_string());
+        }
+        self
+    }
+
+    // Keep a HyperLogLog sketch (4 KiB) of the values written to `field`, so
+    // estimated_cardinality answers without a scan
+    pub fn track_cardinality(mut self, field: &str) -> Self {
+        if !self.cardinality_fields.iter().any(|f| f == field) {
+            self.cardinality_fields.push(field.to_string());
+        }
+        self
+    }
+
+    // Keep a bloom filter of the values of `field` (the key field included), so exists, unique
+    // checks and equality filters on it reject absent values without reading the collection
+    pub fn bloom_filter(mut self, field: &str) -> Self {
+        if !self.bloom_fields.iter().any(|f| f == field) {
+            self.bloom_fields.push(field.to_string());
+        }
+        self
+    }
+
+    // Read missing documents from `loader` on get, and pass writes on to it
+    pub fn loader<L: CacheLoader + 'static>(mut self, loader: L) -> Self {
+        self.loader = Some(Arc::new(loader));
+        self
+    }
+
+    // Split the document map into `shards` shards (rounded up to a power of two, at least 2)
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = Some(shards.max(2).next_power_of_two());
+        self
+    }
+
+    // Add a document validator
+    pub fn validator<V: DocumentValidator + 'static>(mut self, validator: V) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    // Add a field computed from the document on every write
+    pub fn computed<F>(mut self, name: &str, compute: F) -> Self
+    where
+        F: Fn(&Value) -> Value + Send + Sync + 'static,
+    {
+        self.computed_fields.push(ComputedField::new(name, compute));
+        self
+    }
+
+    // Build the collection. Panics on invalid settings, such as an increment step of zero; use
+    // try_build to get the error instead.
     pub fn build(self) -> Arc<Collection> {
-     
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_build(self) -> Result<Arc<Collection>, EmemError> {
+    self.increment.validate()?;
     let new_db = Arc::from(self.db.clone());
+    new_db.record(RecordedOp::CreateCollection {
+        collection: self.name.clone(),
+        key_field: self.key_field.clone(),
+        key_type: self.key_type.clone(),
+        unique_keys: self.unique_keys.clone(),
+        soft_delete: self.soft_delete,
+        increment: (self.increment != IncrementKey::default()).then(|| self.increment.clone()),
+    });
     
-    let new_collection = Collection::new(
+    let mut new_collection = Collection::new(
         new_db.clone(),
         self.db.name.clone(),
         self.name.clone(),
@@ -331,11 +3074,281 @@ impl<'a, T> CollectionBuilder<'a, T> {
         self.key_type,
         self.unique_keys
     );
+    *new_collection.validators.write().unwrap() = self.validators;
+    *new_collection.computed_fields.write().unwrap() = self.computed_fields;
+    new_collection.defaults = self.defaults;
+    new_collection.foreign_keys = self.foreign_keys;
+    new_collection.soft_delete = self.soft_delete;
+    new_collection.field_types = self.field_types;
+    new_collection.memory_limit = self.memory_limit;
+    new_collection.max_documents = self.max_documents;
+    new_collection.max_document_size = self.max_document_size;
+    new_collection.default_ttl = self.default_ttl;
+    new_collection.next_id.store(self.increment.start, std::sync::atomic::Ordering::SeqCst);
+    new_collection.increment = self.increment;
+    new_collection.key_fn = self.key_fn;
+    new_collection.loader = self.loader;
+    new_collection.text_analyzers = self.text_analyzers;
+    new_collection.trigram_indexes = self.trigram_fields.iter().map(|field| Arc::new(TrigramIndex::new(field))).collect();
+    new_collection.bloom_filters = self.bloom_fields.iter()
+        .map(|field| (field.clone(), Arc::new(RwLock::new(BloomFilter::new(0, DEFAULT_FALSE_POSITIVE_RATE)))))
+        .collect();
+    new_collection.cardinality_sketches = self.cardinality_fields.iter()
+        .map(|field| (field.clone(), Arc::new(Mutex::new(HyperLogLog::new()))))
+        .collect();
+    if let Some(shards) = self.shards {
+        new_collection.documents = Arc::new(DashMap::with_shard_amount(shards));
+    }
+    if let Some(policy) = self.eviction_policy {
+        new_collection.eviction_policy = policy;
+    }
     let collection_arc = Arc::new(new_collection.clone());
     
     new_db.collections.write().unwrap().insert(self.name.clone(), collection_arc.clone());
 
-    collection_arc
+    Ok(collection_arc)
 
 }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_collection_has_its_own_indexes() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let source = db.create::<Value>()
+            .name("src")
+            .key("id")
+            .key_type(KeyType::String)
+            .trigram_index("name")
+            .track_cardinality("name")
+            .bloom_filter("id")
+            .build();
+        source.insert(json!({"id": "a", "name": "hello world"}), None).unwrap();
+
+        let copy = db.copy_collection("src", "dst").unwrap();
+        assert_eq!(copy.select("*").contains("name", "hello").execute().unwrap().len(), 1);
+        copy.delete("a").unwrap();
+        for i in 0..100 {
+            copy.insert(json!({"id": format!("c{}", i), "name": format!("name {}", i)}), None).unwrap();
+        }
+
+        assert_eq!(source.select("*").contains("name", "hello").execute().unwrap().len(), 1);
+        assert!(copy.select("*").contains("name", "hello").execute().unwrap().is_empty());
+        assert_eq!(source.estimated_cardinality("name"), 1);
+        assert!(source.exists("a"));
+        assert!(!source.bloom_filters["id"].read().unwrap().contains(&json!("c1").to_string()));
+        assert!(copy.exists("c1"));
+    }
+
+    #[test]
+    fn soft_delete_and_restore_are_regular_writes() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).soft_delete().build();
+        db.enable_audit_log();
+        users.insert(json!({"id": "u1", "name": "kim"}), None).unwrap();
+        let size = users.documents.get("u1").unwrap().size;
+
+        users.delete("u1").unwrap();
+        assert!(users.documents.get("u1").unwrap().size > size);
+        assert_eq!(users.restore("u1").unwrap().id(), "u1");
+        assert_eq!(users.documents.get("u1").unwrap().size, size);
+        assert_eq!(users.restore("u1").err(), Some(EmemError::NotDeleted("u1".to_string())));
+
+        let operations: Vec<String> = db.audit_log().into_iter().map(|entry| entry.operation).collect();
+        assert_eq!(operations, ["insert", "delete", "update"]);
+    }
+
+    #[test]
+    fn set_null_goes_through_updates() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        let config = CollectionConfig::new().foreign_key_on_delete("user_id", "users", "id", OnDelete::SetNull);
+        let orders = db.create::<Value>().name("orders").key("id").key_type(KeyType::String).with_config(&config).build();
+        let strict_config = CollectionConfig::new()
+            .foreign_key_on_delete("user_id", "users", "id", OnDelete::SetNull)
+            .not_null(vec!["user_id"]);
+        let invoices = db.create::<Value>().name("invoices").key("id").key_type(KeyType::String).with_config(&strict_config).build();
+        db.enable_audit_log();
+        users.insert(json!({"id": "u1"}), None).unwrap();
+        users.insert(json!({"id": "u2"}), None).unwrap();
+        orders.insert(json!({"id": "o1", "user_id": "u1"}), None).unwrap();
+        invoices.insert(json!({"id": "i1", "user_id": "u2"}), None).unwrap();
+
+        users.delete("u1").unwrap();
+        assert_eq!(orders.documents.get("o1").unwrap().value["user_id"], Value::Null);
+        assert!(db.audit_log().iter().any(|entry| entry.collection == "orders" && entry.operation == "update"));
+
+        assert!(users.delete("u2").is_err());
+        assert!(users.exists("u2"));
+        assert_eq!(invoices.documents.get("i1").unwrap().value["user_id"], json!("u2"));
+    }
+
+    #[test]
+    fn join_returns_errors() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = db.create_collection("users").unwrap();
+        let orders = db.create_collection("orders").unwrap();
+        let id = users.insert(json!({"name": "kim"}), None).unwrap().id().to_string();
+        orders.insert(json!({"user_id": id}), None).unwrap();
+
+        assert_eq!(db.join("users", "orders", "id", "user_id").unwrap().len(), 1);
+        assert_eq!(db.join("users", "missing", "id", "user_id"), Err(EmemError::CollectionNotFound("missing".to_string())));
+    }
+
+    #[test]
+    fn find_key_and_search_skip_expired_documents() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        users.insert(json!({"id": "u1", "role": "admin"}), None).unwrap();
+        users.insert(json!({"id": "u2", "role": "admin"}), None).unwrap();
+        users.documents.get_mut("u1").unwrap().expiration = Some(SystemTime::now() - Duration::from_secs(1));
+
+        assert!(db.find_key("u1").is_empty());
+        assert_eq!(db.find_key("u2").len(), 1);
+        let found = db.search(&["users"], &json!({"role": "admin"})).unwrap();
+        assert_eq!(found, vec![("users".to_string(), json!({"id": "u2", "role": "admin"}))]);
+    }
+
+    #[test]
+    fn join_drops_unmatched_rows() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        let orders = db.create::<Value>().name("orders").key("oid").key_type(KeyType::String).build();
+        users.insert(json!({"id": "u1"}), None).unwrap();
+        users.insert(json!({"id": "u2"}), None).unwrap();
+        orders.insert(json!({"oid": "o1", "user_id": "u1"}), None).unwrap();
+
+        let rows = db.join("users", "orders", "id", "user_id").unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["id"], json!("u1"));
+        assert_eq!(rows[0]["joined_oid"], json!("o1"));
+    }
+
+    #[test]
+    fn replace_clears_field_ttls() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let kv = db.create::<Value>().name("kv").key("id").key_type(KeyType::String).build();
+        kv.insert(json!({"id": "k", "secret": 1, "name": "a"}), None).unwrap();
+        kv.expire_field("k", "secret", Duration::ZERO).unwrap();
+        kv.expire_field("k", "name", Duration::ZERO).unwrap();
+        kv.update(json!({"id": "k", "name": "b"})).unwrap();
+        assert!(kv.field_ttl("k", "name").is_some());
+
+        kv.replace("k", json!({"id": "k", "secret": 2, "name": "c"}), None).unwrap();
+        assert!(kv.field_ttl("k", "secret").is_none());
+        db.sweep_expired();
+        assert_eq!(kv.documents.get("k").unwrap().value["secret"], json!(2));
+    }
+
+    #[test]
+    fn increment_rejects_zero_step_and_overflow() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let zero = db.create::<Value>().name("zero").key("id").key_type(KeyType::Increment).increment(IncrementKey::new().step(0)).try_build();
+        assert!(matches!(zero, Err(EmemError::InvalidConfig(_))));
+        assert!(db.get("zero").is_err());
+
+        let seq = db.create::<Value>().name("seq").key("id").key_type(KeyType::Increment)
+            .increment(IncrementKey::new().start(u64::MAX - 1))
+            .build();
+        assert_eq!(seq.insert(json!({}), None).unwrap().id(), (u64::MAX - 1).to_string());
+        assert!(seq.insert(json!({}), None).is_err());
+        assert_eq!(seq.len(), 1);
+    }
+
+    #[test]
+    fn set_refuses_collections_with_generated_keys() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        db.create_collection("uuids").unwrap();
+        assert!(matches!(db.set("uuids", "mykey", json!(1), None), Err(EmemError::InvalidConfig(_))));
+        assert!(db.keys("uuids").unwrap().is_empty());
+
+        db.set("kv", "mykey", json!(1), None).unwrap();
+        db.set("kv", "mykey", json!(2), None).unwrap();
+        assert_eq!(db.get_value("kv", "mykey").unwrap(), Some(json!(2)));
+        assert_eq!(db.keys("kv").unwrap(), vec!["mykey".to_string()]);
+    }
+
+    #[test]
+    fn copy_collection_does_not_write_through_to_the_source_store() {
+        struct Store(Arc<Mutex<Vec<String>>>);
+        impl CacheLoader for Store {
+            fn name(&self) -> &str {
+                "store"
+            }
+            fn load(&self, _id: &str) -> Result<Option<Value>, String> {
+                Ok(None)
+            }
+            fn write(&self, id: &str, _document: Option<&Value>) -> Result<(), String> {
+                self.0.lock().unwrap().push(id.to_string());
+                Ok(())
+            }
+        }
+
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let source = db.create::<Value>().name("src").key("id").key_type(KeyType::String).loader(Store(written.clone())).build();
+        source.insert(json!({"id": "a"}), None).unwrap();
+        let copy = db.copy_collection("src", "dst").unwrap();
+        copy.insert(json!({"id": "b"}), None).unwrap();
+        copy.delete("a").unwrap();
+        assert!(copy.loader.is_none());
+        assert_eq!(*written.lock().unwrap(), vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn upsert_with_error_never_overwrites_concurrent_inserts() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let items = db.create::<Value>().name("items").key("id").key_type(KeyType::String).build();
+        let inserted = std::sync::atomic::AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for writer in 0..8 {
+                let (items, inserted) = (&items, &inserted);
+                scope.spawn(move || {
+                    for i in 0..200 {
+                        let document = json!({"id": format!("k{}", i), "writer": writer});
+                        if items.upsert_with(document, None, OnConflict::Error).is_ok() {
+                            inserted.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(inserted.load(Ordering::SeqCst), 200);
+        assert_eq!(items.len(), 200);
+
+        assert!(matches!(items.upsert_with(json!({"id": "k0", "x": 1}), None, OnConflict::Ignore), Ok(None)));
+        assert!(items.upsert_with(json!({"id": "k0", "x": 1}), None, OnConflict::MergeFields).unwrap().is_some());
+        assert_eq!(items.documents.get("k0").unwrap().value["x"], json!(1));
+    }
+
+    #[test]
+    fn query_reports_sql_errors_as_invalid_queries() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        db.create_collection("users").unwrap();
+        assert!(matches!(db.query("SELECT * FROM users WHERE"), Err(EmemError::InvalidQuery(_))));
+        assert!(matches!(db.query("SELECT * FROM missing"), Err(EmemError::InvalidQuery(_))));
+        assert_eq!(db.query("SELECT * FROM users").unwrap(), Vec::<Value>::new());
+    }
+
+    #[test]
+    fn delete_records_only_applied_deletes() {
+        let path = std::env::temp_dir().join(format!("ememdb-delete-{}.jsonl", std::process::id()));
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let items = db.create::<Value>().name("items").key("id").key_type(KeyType::String).build();
+        items.insert(json!({"id": "a"}), None).unwrap();
+        let recorder = db.start_recording(path.to_str().unwrap()).unwrap();
+        assert!(items.delete("missing").is_err());
+        items.delete("a").unwrap();
+        recorder.flush().unwrap();
+
+        let ops: Vec<String> = std::fs::read_to_string(&path).unwrap().lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["op"].as_str().unwrap().to_string())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ops, vec!["delete".to_string()]);
+    }
+}
+
@@ -0,0 +1,89 @@
+// error.rs
+use std::time::Duration;
+use thiserror::Error;
+use crate::access::Permission;
+
+// Errors returned by collections, queries and configuration. The messages match the strings
+// returned before this type existed, so `e.to_string()` output is unchanged.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum EmemError {
+    #[error("Collection '{0}' not found.")]
+    CollectionNotFound(String),
+    #[error("Collection '{0}' already exists.")]
+    CollectionExists(String),
+    #[error("View '{0}' not found.")]
+    ViewNotFound(String),
+    #[error("View '{0}' already exists.")]
+    ViewExists(String),
+    #[error("Document not found.")]
+    DocumentNotFound,
+    #[error("Document '{0}' already exists.")]
+    DocumentExists(String),
+    #[error("Document '{0}' is not deleted.")]
+    NotDeleted(String),
+    #[error("Key field is not set.")]
+    KeyFieldNotSet,
+    // The key field (or "Key" for updates) is missing from the document
+    #[error("{0} field not found in the document.")]
+    MissingKey(String),
+    #[error("{0} is not a string.")]
+    InvalidKey(String),
+    #[error("Field '{field}' is not {expected}")]
+    TypeMismatch { field: String, expected: String },
+    // `index` is the position of the failed operation in the patch
+    #[error("Patch operation {index} ({op}) failed: {message}")]
+    PatchFailed { index: usize, op: String, message: String },
+    // The stored document didn't satisfy the condition of a conditional write
+    #[error("Document '{0}' does not satisfy the update condition.")]
+    ConditionFailed(String),
+    // single() or one() matched more than one document in the named collection
+    #[error("Query on collection '{0}' matched more than one document.")]
+    MultipleMatches(String),
+    #[error("Duplicate value for unique key: {0}")]
+    DuplicateKey(String),
+    #[error("Document size of {size} bytes exceeds the limit of {limit} bytes for collection '{collection}'")]
+    DocumentTooLarge { size: usize, limit: usize, collection: String },
+    #[error("Validator '{validator}' rejected document: {message}")]
+    ValidationFailed { validator: String, message: String },
+    // Missing referenced document or collection, or a delete blocked by OnDelete::Restrict
+    #[error("{0}")]
+    ForeignKeyViolation(String),
+    #[error("{0}")]
+    Migration(String),
+    #[error("{0}")]
+    InvalidQuery(String),
+    #[error("Query exceeded max_scan of {0} documents")]
+    ScanLimitExceeded(usize),
+    #[error("Query timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("{0}")]
+    InvalidConfig(String),
+    #[error("Role '{0}' not found.")]
+    RoleNotFound(String),
+    #[error("Role '{role}' lacks {required} permission on collection '{collection}'")]
+    PermissionDenied { role: String, collection: String, required: Permission },
+    // Errors from the string-based modules (sql, rest, protocol, snapshot, replay, ...)
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type EmemResult<T> = Result<T, EmemError>;
+
+impl From<String> for EmemError {
+    fn from(message: String) -> Self {
+        EmemError::Other(message)
+    }
+}
+
+impl From<&str> for EmemError {
+    fn from(message: &str) -> Self {
+        EmemError::Other(message.to_string())
+    }
+}
+
+// Lets modules that still return Result<_, String> use `?` on collection and query calls
+impl From<EmemError> for String {
+    fn from(error: EmemError) -> Self {
+        error.to_string()
+    }
+}
@@ -0,0 +1,142 @@
+// eviction.rs
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::time::SystemTime;
+use crate::db::DocumentEntry;
+
+// Decides which documents go first when a collection is over its memory limit.
+// Documents are evicted in ascending score order.
+pub trait EvictionPolicy: Send + Sync {
+    fn name(&self) -> &str;
+    fn score(&self, id: &str, entry: &DocumentEntry) -> f64;
+}
+
+impl fmt::Debug for dyn EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EvictionPolicy({})", self.name())
+    }
+}
+
+fn seconds_since_epoch(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
+
+// Least recently used first (the default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LruPolicy;
+
+impl EvictionPolicy for LruPolicy {
+    fn name(&self) -> &str {
+        "lru"
+    }
+
+    fn score(&self, _id: &str, entry: &DocumentEntry) -> f64 {
+        entry.last_access() as f64
+    }
+}
+
+// Least frequently used first
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LfuPolicy;
+
+impl EvictionPolicy for LfuPolicy {
+    fn name(&self) -> &str {
+        "lfu"
+    }
+
+    fn score(&self, _id: &str, entry: &DocumentEntry) -> f64 {
+        entry.access_count() as f64
+    }
+}
+
+// Oldest write first
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FifoPolicy;
+
+impl EvictionPolicy for FifoPolicy {
+    fn name(&self) -> &str {
+        "fifo"
+    }
+
+    fn score(&self, _id: &str, entry: &DocumentEntry) -> f64 {
+        entry.insertion_order() as f64
+    }
+}
+
+// Uniformly random victims
+#[derive(Debug, Clone, Default)]
+pub struct RandomPolicy {
+    state: RandomState,
+}
+
+impl RandomPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EvictionPolicy for RandomPolicy {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn score(&self, id: &str, entry: &DocumentEntry) -> f64 {
+        let mut hasher = self.state.build_hasher();
+        id.hash(&mut hasher);
+        entry.last_access().hash(&mut hasher);
+        hasher.finish() as f64
+    }
+}
+
+// Documents closest to expiring first; documents without a TTL go last
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TtlSoonestPolicy;
+
+impl EvictionPolicy for TtlSoonestPolicy {
+    fn name(&self) -> &str {
+        "ttl_soonest"
+    }
+
+    fn score(&self, _id: &str, entry: &DocumentEntry) -> f64 {
+        entry.expiration.map_or(f64::MAX, seconds_since_epoch)
+    }
+}
+
+type ScoreFn = Arc<dyn Fn(&str, &DocumentEntry) -> f64 + Send + Sync>;
+
+// User supplied scoring function
+#[derive(Clone)]
+pub struct ScoredPolicy {
+    name: String,
+    score: ScoreFn,
+}
+
+impl ScoredPolicy {
+    pub fn new<F>(name: &str, score: F) -> Self
+    where
+        F: Fn(&str, &DocumentEntry) -> f64 + Send + Sync + 'static,
+    {
+        ScoredPolicy {
+            name: name.to_string(),
+            score: Arc::new(score),
+        }
+    }
+}
+
+impl EvictionPolicy for ScoredPolicy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn score(&self, id: &str, entry: &DocumentEntry) -> f64 {
+        (self.score)(id, entry)
+    }
+}
+
+impl fmt::Debug for ScoredPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ScoredPolicy({})", self.name)
+    }
+}
@@ -0,0 +1,413 @@
+// filter.rs
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use dashmap::DashMap;
+use crate::text::{fuzzy_matches, sounds_like, Analyzer};
+
+// Compiled patterns are cached per thread; the cache is reset once it grows past this size
+const REGEX_CACHE_SIZE: usize = 128;
+
+thread_local! {
+    static REGEX_CACHE: RefCell<HashMap<String, Option<Regex>>> = RefCell::new(HashMap::new());
+}
+
+fn regex_matches(pattern: &str, text: &str) -> bool {
+    REGEX_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if !cache.contains_key(pattern) {
+            if cache.len() >= REGEX_CACHE_SIZE {
+                cache.clear();
+            }
+            cache.insert(pattern.to_string(), Regex::new(pattern).ok());
+        }
+        cache[pattern].as_ref().is_some_and(|regex| regex.is_match(text))
+    })
+}
+
+// Serializable representation of a query filter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum FilterExpr {
+    Eq { field: String, value: Value },
+    Neq { field: String, value: Value },
+    Gt { field: String, value: f64 },
+    Gte { field: String, value: f64 },
+    Lt { field: String, value: f64 },
+    Lte { field: String, value: f64 },
+    In { field: String, values: Vec<Value> },
+    Regex { field: String, pattern: String },
+    Exists { field: String, exists: bool },
+    // Date/time field within the bounds (inclusive, milliseconds since the Unix epoch). The field
+    // may hold an RFC 3339 string or epoch milliseconds; anything else never matches.
+    DateRange { field: String, from_ms: Option<i64>, to_ms: Option<i64> },
+    // Strictly after / before `value` in sort order (see compare_values), for any value type
+    After { field: String, value: Value },
+    Before { field: String, value: Value },
+    // String field containing every token of `query`, both analyzed with `analyzer`
+    Text { field: String, query: String, analyzer: Analyzer },
+    // String field containing `value` as a substring (case-sensitive)
+    Contains { field: String, value: String },
+    // String field matching an SQL LIKE pattern: % is any run of characters, _ any one character
+    Like { field: String, pattern: String },
+    // String field (or one of its words) within `max_distance` edits of `value`, ignoring case
+    Fuzzy { field: String, value: String, max_distance: usize },
+    // String field with a word pronounced like a word of `value` (same Soundex code)
+    SoundsLike { field: String, value: String },
+    And { exprs: Vec<FilterExpr> },
+    Or { exprs: Vec<FilterExpr> },
+    Not { expr: Box<FilterExpr> },
+}
+
+impl FilterExpr {
+    pub fn eq<T: Into<Value>>(field: &str, value: T) -> Self {
+        FilterExpr::Eq { field: field.to_string(), value: value.into() }
+    }
+
+    pub fn neq<T: Into<Value>>(field: &str, value: T) -> Self {
+        FilterExpr::Neq { field: field.to_string(), value: value.into() }
+    }
+
+    pub fn gt<T: Into<f64>>(field: &str, value: T) -> Self {
+        FilterExpr::Gt { field: field.to_string(), value: value.into() }
+    }
+
+    pub fn gte<T: Into<f64>>(field: &str, value: T) -> Self {
+        FilterExpr::Gte { field: field.to_string(), value: value.into() }
+    }
+
+    pub fn lt<T: Into<f64>>(field: &str, value: T) -> Self {
+        FilterExpr::Lt { field: field.to_string(), value: value.into() }
+    }
+
+    pub fn lte<T: Into<f64>>(field: &str, value: T) -> Self {
+        FilterExpr::Lte { field: field.to_string(), value: value.into() }
+    }
+
+    pub fn in_<T: Into<Value>>(field: &str, values: Vec<T>) -> Self {
+        FilterExpr::In { field: field.to_string(), values: values.into_iter().map(|v| v.into()).collect() }
+    }
+
+    // String field matching `pattern`; fails on invalid patterns instead of never matching
+    pub fn regex(field: &str, pattern: &str) -> Result<Self, String> {
+        Regex::new(pattern).map_err(|e| format!("Invalid regex '{}': {}", pattern, e))?;
+        Ok(FilterExpr::Regex { field: field.to_string(), pattern: pattern.to_string() })
+    }
+
+    pub fn exists(field: &str, exists: bool) -> Self {
+        FilterExpr::Exists { field: field.to_string(), exists }
+    }
+
+    pub fn after<T: Into<Value>>(field: &str, value: T) -> Self {
+        FilterExpr::After { field: field.to_string(), value: value.into() }
+    }
+
+    pub fn before<T: Into<Value>>(field: &str, value: T) -> Self {
+        FilterExpr::Before { field: field.to_string(), value: value.into() }
+    }
+
+    // Dates accept RFC 3339 strings ("2024-01-01T00:00:00Z", "2024-01-01") or epoch milliseconds
+    pub fn after_date<T: Into<Value>>(field: &str, date: T) -> Result<Self, String> {
+        let at = parse_date_operand(date.into())?;
+        Ok(FilterExpr::DateRange { field: field.to_string(), from_ms: Some(at + 1), to_ms: None })
+    }
+
+    pub fn before_date<T: Into<Value>>(field: &str, date: T) -> Result<Self, String> {
+        let at = parse_date_operand(date.into())?;
+        Ok(FilterExpr::DateRange { field: field.to_string(), from_ms: None, to_ms: Some(at - 1) })
+    }
+
+    // Both bounds included
+    pub fn date_between<T: Into<Value>, U: Into<Value>>(field: &str, from: T, to: U) -> Result<Self, String> {
+        let from = parse_date_operand(from.into())?;
+        let to = parse_date_operand(to.into())?;
+        Ok(FilterExpr::DateRange { field: field.to_string(), from_ms: Some(from), to_ms: Some(to) })
+    }
+
+    pub fn text(field: &str, query: &str, analyzer: Analyzer) -> Self {
+        FilterExpr::Text { field: field.to_string(), query: query.to_string(), analyzer }
+    }
+
+    pub fn contains(field: &str, value: &str) -> Self {
+        FilterExpr::Contains { field: field.to_string(), value: value.to_string() }
+    }
+
+    pub fn like(field: &str, pattern: &str) -> Self {
+        FilterExpr::Like { field: field.to_string(), pattern: pattern.to_string() }
+    }
+
+    pub fn fuzzy(field: &str, value: &str, max_distance: usize) -> Self {
+        FilterExpr::Fuzzy { field: field.to_string(), value: value.to_string(), max_distance }
+    }
+
+    pub fn sounds_like(field: &str, value: &str) -> Self {
+        FilterExpr::SoundsLike { field: field.to_string(), value: value.to_string() }
+    }
+
+    pub fn and(exprs: Vec<FilterExpr>) -> Self {
+        FilterExpr::And { exprs }
+    }
+
+    pub fn or(exprs: Vec<FilterExpr>) -> Self {
+        FilterExpr::Or { exprs }
+    }
+
+    pub fn matches(&self, doc: &Value) -> bool {
+        match self {
+            FilterExpr::Eq { field, value } => doc.get(field).is_some_and(|val| val == value),
+            FilterExpr::Neq { field, value } => doc.get(field).is_none_or(|val| val != value),
+            FilterExpr::Gt { field, value } => Self::number(doc, field).is_some_and(|n| n > *value),
+            FilterExpr::Gte { field, value } => Self::number(doc, field).is_some_and(|n| n >= *value),
+            FilterExpr::Lt { field, value } => Self::number(doc, field).is_some_and(|n| n < *value),
+            FilterExpr::Lte { field, value } => Self::number(doc, field).is_some_and(|n| n <= *value),
+            FilterExpr::In { field, values } => doc.get(field).is_some_and(|val| values.iter().any(|v| v == val)),
+            FilterExpr::Regex { field, pattern } => doc.get(field).and_then(|val| val.as_str()).is_some_and(|s| regex_matches(pattern, s)),
+            FilterExpr::Exists { field, exists } => doc.get(field).is_some() == *exists,
+            FilterExpr::DateRange { field, from_ms, to_ms } => doc.get(field).and_then(parse_timestamp).is_some_and(|at| {
+                from_ms.is_none_or(|from| at >= from) && to_ms.is_none_or(|to| at <= to)
+            }),
+            FilterExpr::After { field, value } => doc.get(field).is_some_and(|val| compare_values(val, value) == Ordering::Greater),
+            FilterExpr::Before { field, value } => doc.get(field).is_some_and(|val| compare_values(val, value) == Ordering::Less),
+            FilterExpr::Text { field, query, analyzer } => doc.get(field).and_then(|val| val.as_str()).is_some_and(|text| analyzer.matches(text, query)),
+            FilterExpr::Contains { field, value } => doc.get(field).and_then(|val| val.as_str()).is_some_and(|text| text.contains(value.as_str())),
+            FilterExpr::Like { field, pattern } => doc.get(field).and_then(|val| val.as_str()).is_some_and(|text| like_matches(pattern, text)),
+            FilterExpr::Fuzzy { field, value, max_distance } => doc.get(field).and_then(|val| val.as_str()).is_some_and(|text| fuzzy_matches(text, value, *max_distance)),
+            FilterExpr::SoundsLike { field, value } => doc.get(field).and_then(|val| val.as_str()).is_some_and(|text| sounds_like(text, value)),
+            FilterExpr::And { exprs } => exprs.iter().all(|e| e.matches(doc)),
+            FilterExpr::Or { exprs } => exprs.iter().any(|e| e.matches(doc)),
+            FilterExpr::Not { expr } => !expr.matches(doc),
+        }
+    }
+
+    fn number(doc: &Value, field: &str) -> Option<f64> {
+        doc.get(field).and_then(|val| val.as_f64())
+    }
+}
+
+impl std::ops::Not for FilterExpr {
+    type Output = FilterExpr;
+
+    fn not(self) -> FilterExpr {
+        FilterExpr::Not { expr: Box::new(self) }
+    }
+}
+
+// SQL LIKE: % matches any run of characters (including none), _ exactly one
+fn like_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last % and the text position it was tried at, to backtrack to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '_' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '%' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '%')
+}
+
+fn parse_date_operand(date: Value) -> Result<i64, String> {
+    parse_timestamp(&date).ok_or_else(|| format!("Expected an RFC 3339 date or epoch milliseconds but found {}", date))
+}
+
+// Milliseconds since the Unix epoch for an RFC 3339 string ("2024-01-01T09:30:00.250+09:00",
+// "2024-01-01 09:30:00Z" or a plain "2024-01-01" taken as UTC midnight) or an integer number of
+// epoch milliseconds
+pub fn parse_timestamp(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64(),
+        Value::String(text) => parse_rfc3339(text),
+        _ => None,
+    }
+}
+
+fn parse_rfc3339(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let part = text.get(range)?;
+        if part.bytes().all(|b| b.is_ascii_digit()) { part.parse().ok() } else { None }
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    if text.get(4..5)? != "-" || text.get(7..8)? != "-" || !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    if text.len() == 10 {
+        return Some(days * 86_400_000);
+    }
+
+    if !matches!(text.get(10..11)?, "T" | "t" | " ") || text.get(13..14)? != ":" || text.get(16..17)? != ":" {
+        return None;
+    }
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let mut rest = text.get(19..)?;
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        let padded = format!("{:0<3}", &fraction[..digits.min(3)]);
+        millis = padded.parse::<i64>().ok()?;
+        rest = &fraction[digits..];
+    }
+    let offset_minutes = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.get(0..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.get(3..4)? != ":" {
+                return None;
+            }
+            let hours: i64 = rest.get(1..3)?.parse().ok()?;
+            let minutes: i64 = rest.get(4..6)?.parse().ok()?;
+            sign * (hours * 60 + minutes)
+        }
+    };
+    let seconds = days * 86_400 + hour * 3_600 + minute * 60 + second - offset_minutes * 60;
+    Some(seconds * 1_000 + millis)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Ordering used for sorting: null sorts first, numbers and strings compare naturally
+pub fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64().partial_cmp(&y.as_f64()).unwrap_or(Ordering::Equal),
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        _ => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+// Compare two documents by a list of (field, order) keys
+pub fn compare_by(a: &Value, b: &Value, keys: &[(String, SortOrder)]) -> Ordering {
+    for (field, order) in keys {
+        let ordering = compare_values(a.get(field).unwrap_or(&Value::Null), b.get(field).unwrap_or(&Value::Null));
+        let ordering = match order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+// Custom ordering registered with InMemoryDB::register_comparator and used by order_by_with
+pub type CompareFn = Arc<dyn Fn(&Value, &Value) -> Ordering + Send + Sync>;
+
+// Named comparators of a database, shared by all its handles
+#[derive(Clone, Default)]
+pub struct Comparators {
+    comparators: Arc<DashMap<String, CompareFn>>,
+}
+
+impl Comparators {
+    pub fn register(&self, name: &str, compare: impl Fn(&Value, &Value) -> Ordering + Send + Sync + 'static) {
+        self.comparators.insert(name.to_string(), Arc::new(compare));
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        self.comparators.remove(name).is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Option<CompareFn> {
+        self.comparators.get(name).map(|r| r.value().clone())
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.comparators.iter().map(|r| r.key().clone()).collect();
+        names.sort();
+        names
+    }
+}
+
+impl fmt::Debug for Comparators {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+// A query that can be stored and rebuilt later
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuerySpec {
+    pub collection: String,
+    #[serde(default)]
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub filter: Option<FilterExpr>,
+    #[serde(default)]
+    pub order_by: Vec<(String, SortOrder)>,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rfc3339_checks_the_day_of_the_month() {
+        assert_eq!(parse_rfc3339("1970-01-02"), Some(86_400_000));
+        assert!(parse_rfc3339("2024-02-29").is_some());
+        assert!(parse_rfc3339("2000-02-29T00:00:00Z").is_some());
+        assert_eq!(parse_rfc3339("2023-02-29"), None);
+        assert_eq!(parse_rfc3339("1900-02-29"), None);
+        assert_eq!(parse_rfc3339("2024-04-31T12:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2024-12-32"), None);
+        assert!(parse_rfc3339("2024-12-31").is_some());
+    }
+}
@@ -0,0 +1,299 @@
+// graphql.rs
+// GraphQL schema generated from the registered collections (feature "graphql").
+// For a collection `users` the schema contains:
+//
+//   type Users { <one field per declared or inferred document field>, _json: the whole document }
+//   users(filter: JSON, order_by: [String!], limit: Int, offset: Int): [Users!]!
+//     filter is a Mongo-style document; pass it as a variable since "$gte" is not a GraphQL name.
+//     order_by entries are field names, prefixed with '-' for descending.
+//   users_by_key(key: String!): Users
+//   insert_users / upsert_users / update_users(document: JSON!): Users
+//   delete_users(key: String!): Users
+//   subscription users_changes: UsersChange!   { event, id, document } for every write
+//     fed by the collection's change stream; http::router serves subscriptions over WebSocket at
+//     GET /graphql when the "websocket" feature is enabled too.
+//
+// The schema reflects the collections that exist when it is built; rebuild it after creating more.
+use async_graphql::dynamic::{
+    Field, FieldFuture, FieldValue, InputValue, Object, ResolverContext, Scalar, Schema, Subscription,
+    SubscriptionField, SubscriptionFieldFuture, TypeRef,
+};
+use async_graphql::futures_util::stream;
+use async_graphql::Value as GqlValue;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use crate::db::{Collection, InMemoryDB, OperationResult};
+use crate::filter::SortOrder;
+use crate::subscription::{EventType, Subscription as ChangeSubscription};
+
+const JSON_SCALAR: &str = "JSON";
+
+// A write delivered to a `<collection>_changes` subscription
+struct ChangeEvent {
+    event: &'static str,
+    id: String,
+    document: Value,
+}
+
+// Removes the change-stream callbacks once the GraphQL subscription is dropped
+struct ChangeFeed {
+    collection: Arc<Collection>,
+    subscriptions: Vec<Arc<ChangeSubscription<'static>>>,
+}
+
+impl Drop for ChangeFeed {
+    fn drop(&mut self) {
+        for subscription in &self.subscriptions {
+            self.collection.unsubscribe(subscription);
+        }
+    }
+}
+
+fn graphql_name(name: &str) -> String {
+    let mut out: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if out.is_empty() || out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+fn type_name(collection: &str) -> String {
+    let name = graphql_name(collection);
+    let mut chars = name.chars();
+    chars.next().map(|c| c.to_ascii_uppercase()).into_iter().chain(chars).collect()
+}
+
+fn scalar_type(field_type: &str) -> &'static str {
+    match field_type {
+        "string" => TypeRef::STRING,
+        "integer" | "int" => TypeRef::INT,
+        "number" | "float" => TypeRef::FLOAT,
+        "boolean" | "bool" => TypeRef::BOOLEAN,
+        _ => JSON_SCALAR,
+    }
+}
+
+fn to_graphql(value: &Value) -> Option<GqlValue> {
+    GqlValue::from_json(value.clone()).ok()
+}
+
+fn lookup(db: &InMemoryDB, name: &str) -> async_graphql::Result<Arc<Collection>> {
+    db.collection_arc(name).ok_or_else(|| format!("Collection '{}' not found.", name).into())
+}
+
+fn json_argument(ctx: &ResolverContext, name: &str) -> async_graphql::Result<Option<Value>> {
+    match ctx.args.get(name) {
+        Some(value) if !value.is_null() => Ok(Some(value.deserialize::<Value>()?)),
+        _ => Ok(None),
+    }
+}
+
+fn document_result(result: OperationResult) -> Option<FieldValue<'static>> {
+    let document = match result {
+        OperationResult::Inserted { document, .. } => document,
+        OperationResult::Updated { new_document, .. } => new_document,
+        OperationResult::Deleted { document, .. } => document,
+    };
+    Some(FieldValue::owned_any(document))
+}
+
+fn document_type(name: &str, fields: &[(String, String)]) -> Object {
+    let mut object = Object::new(type_name(name));
+    for (field, field_type) in fields {
+        if graphql_name(field) != *field || field.starts_with("__") {
+            continue;
+        }
+        let key = field.clone();
+        object = object.field(Field::new(field.as_str(), TypeRef::named(scalar_type(field_type)), move |ctx| {
+            let key = key.clone();
+            FieldFuture::new(async move {
+                let document = ctx.parent_value.try_downcast_ref::<Value>()?;
+                Ok(document.get(&key).and_then(to_graphql).map(FieldValue::value))
+            })
+        }));
+    }
+    // A GraphQL object needs at least one field
+    object.field(Field::new("_json", TypeRef::named_nn(JSON_SCALAR), |ctx| {
+        FieldFuture::new(async move {
+            let document = ctx.parent_value.try_downcast_ref::<Value>()?;
+            Ok(to_graphql(document).map(FieldValue::value))
+        })
+    }))
+}
+
+fn change_type(name: &str) -> Object {
+    let string_field = |field: &'static str| {
+        Field::new(field, TypeRef::named_nn(TypeRef::STRING), move |ctx| {
+            FieldFuture::new(async move {
+                let change = ctx.parent_value.try_downcast_ref::<ChangeEvent>()?;
+                let text = if field == "event" { change.event.to_string() } else { change.id.clone() };
+                Ok(Some(FieldValue::value(text)))
+            })
+        })
+    };
+    Object::new(format!("{}Change", type_name(name)))
+        .field(string_field("event"))
+        .field(string_field("id"))
+        .field(Field::new("document", TypeRef::named(type_name(name)), |ctx| {
+            FieldFuture::new(async move {
+                let change = ctx.parent_value.try_downcast_ref::<ChangeEvent>()?;
+                Ok(Some(FieldValue::owned_any(change.document.clone())))
+            })
+        }))
+}
+
+fn list_field(db: &Arc<InMemoryDB>, name: &str) -> Field {
+    let (db, collection) = (db.clone(), name.to_string());
+    Field::new(graphql_name(name), TypeRef::named_nn_list_nn(type_name(name)), move |ctx| {
+        let (db, collection) = (db.clone(), collection.clone());
+        FieldFuture::new(async move {
+            let mut query = lookup(&db, &collection)?.select("*");
+            if let Some(filter) = json_argument(&ctx, "filter")? {
+                if let Some(expr) = crate::protocol::parse_filter(&filter)? {
+                    query = query.where_expr(expr);
+                }
+            }
+            if let Some(order_by) = ctx.args.get("order_by") {
+                for key in order_by.list()?.iter() {
+                    let key = key.string()?;
+                    query = match key.strip_prefix('-') {
+                        Some(field) => query.order_by(field, SortOrder::Desc),
+                        None => query.order_by(key, SortOrder::Asc),
+                    };
+                }
+            }
+            if let Some(limit) = ctx.args.get("limit") {
+                query = query.limit(limit.u64()? as usize);
+            }
+            if let Some(offset) = ctx.args.get("offset") {
+                query = query.offset(offset.u64()? as usize);
+            }
+            let rows = query.execute()?;
+            Ok(Some(FieldValue::list(rows.into_iter().map(FieldValue::owned_any))))
+        })
+    })
+    .argument(InputValue::new("filter", TypeRef::named(JSON_SCALAR)))
+    .argument(InputValue::new("order_by", TypeRef::named_nn_list(TypeRef::STRING)))
+    .argument(InputValue::new("limit", TypeRef::named(TypeRef::INT)))
+    .argument(InputValue::new("offset", TypeRef::named(TypeRef::INT)))
+}
+
+fn by_key_field(db: &Arc<InMemoryDB>, name: &str) -> Field {
+    let (db, collection) = (db.clone(), name.to_string());
+    Field::new(format!("{}_by_key", graphql_name(name)), TypeRef::named(type_name(name)), move |ctx| {
+        let (db, collection) = (db.clone(), collection.clone());
+        FieldFuture::new(async move {
+            let collection = lookup(&db, &collection)?;
+            let key = ctx.args.try_get("key")?.string()?.to_string();
+            let key_field = collection.key_field.clone().ok_or("Key field is not set.")?;
+            let mut rows = collection.select("*").eq(&key_field, key).execute()?;
+            Ok(rows.pop().map(FieldValue::owned_any))
+        })
+    })
+    .argument(InputValue::new("key", TypeRef::named_nn(TypeRef::STRING)))
+}
+
+fn mutation_field(db: &Arc<InMemoryDB>, name: &str, action: &'static str) -> Field {
+    let (db, collection) = (db.clone(), name.to_string());
+    let argument = if action == "delete" {
+        InputValue::new("key", TypeRef::named_nn(TypeRef::STRING))
+    } else {
+        InputValue::new("document", TypeRef::named_nn(JSON_SCALAR))
+    };
+    Field::new(format!("{}_{}", action, graphql_name(name)), TypeRef::named(type_name(name)), move |ctx| {
+        let (db, collection) = (db.clone(), collection.clone());
+        FieldFuture::new(async move {
+            let collection = lookup(&db, &collection)?;
+            let result = match action {
+                "delete" => collection.delete(ctx.args.try_get("key")?.string()?)?,
+                _ => {
+                    let document = json_argument(&ctx, "document")?.ok_or("document is required")?;
+                    match action {
+                        "insert" => collection.insert(document, None)?,
+                        "upsert" => collection.upsert(document, None)?,
+                        _ => collection.update(document)?,
+                    }
+                }
+            };
+            Ok(document_result(result))
+        })
+    })
+    .argument(argument)
+}
+
+fn changes_field(db: &Arc<InMemoryDB>, name: &str) -> SubscriptionField {
+    let (db, collection) = (db.clone(), name.to_string());
+    SubscriptionField::new(format!("{}_changes", graphql_name(name)), TypeRef::named_nn(format!("{}Change", type_name(name))), move |_| {
+        let (db, collection) = (db.clone(), collection.clone());
+        SubscriptionFieldFuture::new(async move {
+            let collection = lookup(&db, &collection)?;
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let mut subscriptions = Vec::new();
+            for (event_type, event) in [
+                (EventType::Insert, "insert"),
+                (EventType::Update, "update"),
+                (EventType::Delete, "delete"),
+                (EventType::Evicted, "evicted"),
+            ] {
+                let sender = sender.clone();
+                subscriptions.push(collection.subscribe(ChangeSubscription::new(event_type, move |id, document| {
+                    let _ = sender.send(ChangeEvent { event, id: id.to_string(), document: document.clone() });
+                }))?);
+            }
+            let feed = ChangeFeed { collection, subscriptions };
+            Ok(stream::unfold((receiver, feed), |(mut receiver, feed)| async move {
+                let change = receiver.recv().await?;
+                Some((Ok::<_, async_graphql::Error>(FieldValue::owned_any(change)), (receiver, feed)))
+            }))
+        })
+    })
+}
+
+// Build the schema for every collection currently registered in `db`
+pub fn schema(db: Arc<InMemoryDB>) -> Result<Schema, String> {
+    let mut names = db.collection_names();
+    names.sort();
+
+    let collection_names = {
+        let db = db.clone();
+        Field::new("collections", TypeRef::named_nn_list_nn(TypeRef::STRING), move |_| {
+            let mut names = db.collection_names();
+            names.sort();
+            FieldFuture::from_value(Some(GqlValue::List(names.into_iter().map(GqlValue::from).collect())))
+        })
+    };
+    let mut query = Object::new("Query").field(collection_names);
+    let mut mutation = Object::new("Mutation");
+    let mut subscription = Subscription::new("Subscription");
+    let mut types = Vec::new();
+    for name in &names {
+        let collection = match db.collection_arc(name) {
+            Some(collection) => collection,
+            None => continue,
+        };
+        types.push(document_type(name, &collection.schema_fields()));
+        types.push(change_type(name));
+        query = query.field(list_field(&db, name)).field(by_key_field(&db, name));
+        for action in ["insert", "upsert", "update", "delete"] {
+            mutation = mutation.field(mutation_field(&db, name, action));
+        }
+        subscription = subscription.field(changes_field(&db, name));
+    }
+
+    let has_collections = !types.is_empty();
+    let mut builder = Schema::build(
+        "Query",
+        has_collections.then_some("Mutation"),
+        has_collections.then_some("Subscription"),
+    )
+    .register(Scalar::new(JSON_SCALAR))
+    .register(query);
+    if has_collections {
+        builder = builder.register(mutation).register(subscription);
+    }
+    for object in types {
+        builder = builder.register(object);
+    }
+    builder.finish().map_err(|e| format!("Failed to build GraphQL schema: {}", e))
+}
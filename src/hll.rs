@@ -0,0 +1,102 @@
+// hll.rs
+// HyperLogLog distinct-value estimator: 4096 one-byte registers (4 KiB) estimate any number of
+// distinct values with a standard error of about 1.6%. Used by Collection::estimated_cardinality and
+// QueryBuilder::count_distinct.
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog { registers: vec![0; REGISTERS] }
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+        let register = (hash >> (64 - PRECISION)) as usize;
+        // Position of the first set bit after the register bits; the sentinel bit caps it
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        if rank > self.registers[register] {
+            self.registers[register] = rank;
+        }
+    }
+
+    // JSON values are counted by their encoding, so 1 and "1" are different values
+    pub fn insert_value(&mut self, value: &Value) {
+        self.insert(&value.to_string());
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        let empty = self.registers.iter().filter(|&&r| r == 0).count();
+        // Small cardinalities are counted more precisely from the empty registers
+        let estimate = if raw <= 2.5 * m && empty > 0 { m * (m / empty as f64).ln() } else { raw };
+        estimate.round() as u64
+    }
+
+    // Afterwards estimates the distinct values seen by either
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, &theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(theirs);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.registers.iter_mut().for_each(|r| *r = 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relative_error(estimate: u64, actual: u64) -> f64 {
+        (estimate as f64 - actual as f64).abs() / actual as f64
+    }
+
+    #[test]
+    fn estimates_within_two_percent() {
+        for n in [100, 1_000, 10_000, 100_000] {
+            let mut hll = HyperLogLog::new();
+            for i in 0..n {
+                hll.insert_value(&Value::from(format!("user-{}", i)));
+                // Repeats don't count
+                hll.insert_value(&Value::from(format!("user-{}", i / 2)));
+            }
+            assert!(relative_error(hll.estimate(), n) < 0.02, "{} estimated as {}", n, hll.estimate());
+        }
+        assert_eq!(HyperLogLog::new().estimate(), 0);
+    }
+
+    #[test]
+    fn merge_estimates_the_union() {
+        let (mut left, mut right) = (HyperLogLog::new(), HyperLogLog::new());
+        for i in 0..6_000 {
+            left.insert(&i);
+        }
+        for i in 4_000..10_000 {
+            right.insert(&i);
+        }
+        left.merge(&right);
+        assert!(relative_error(left.estimate(), 10_000) < 0.02, "estimated {}", left.estimate());
+    }
+}
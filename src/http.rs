@@ -0,0 +1,283 @@
+// http.rs
+// Embedded REST API (feature "http"), handy for running ememdb as a standalone test service.
+//
+//   GET    /db                       collection names
+//   GET    /db/{collection}?age=gte.18&select=name,age&order=age.desc&limit=10   (see rest.rs)
+//   POST   /db/{collection}          insert one document or an array of documents
+//   GET    /db/{collection}/{id}     one document
+//   PUT    /db/{collection}/{id}     upsert the document under {id}
+//   PATCH  /db/{collection}/{id}     merge the top-level fields of the body into the document
+//   DELETE /db/{collection}/{id}
+//   POST   /graphql                  with the "graphql" feature (see graphql.rs)
+//   GET    /graphql                  GraphQL over WebSocket (graphql-transport-ws or graphql-ws) for
+//                                    subscriptions, with the "graphql" and "websocket" features
+//   GET    /changes                  WebSocket change feed with the "websocket" feature (see websocket.rs)
+use axum::extract::{Path, RawQuery, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use crate::db::{Collection, InMemoryDB, OperationResult};
+use crate::error::EmemError;
+
+type Db = State<Arc<InMemoryDB>>;
+
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError { status, message: message.into() }
+    }
+}
+
+impl From<String> for ApiError {
+    fn from(message: String) -> Self {
+        let status = if message.contains("not found") { StatusCode::NOT_FOUND } else { StatusCode::BAD_REQUEST };
+        ApiError { status, message }
+    }
+}
+
+impl From<EmemError> for ApiError {
+    fn from(error: EmemError) -> Self {
+        let status = match error {
+            EmemError::CollectionNotFound(_) | EmemError::DocumentNotFound | EmemError::ViewNotFound(_) => StatusCode::NOT_FOUND,
+            EmemError::DuplicateKey(_) => StatusCode::CONFLICT,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        ApiError { status, message: error.to_string() }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+// Routes for `db`; nest or merge it into a larger application as needed
+pub fn router(db: Arc<InMemoryDB>) -> Router {
+    let router = Router::new()
+        .route("/db", get(list_collections))
+        .route("/db/{collection}", get(query).post(insert))
+        .route("/db/{collection}/{id}", get(fetch).put(replace).patch(patch).delete(remove));
+    #[cfg(all(feature = "graphql", not(feature = "websocket")))]
+    let router = router.route("/graphql", axum::routing::post(graphql));
+    #[cfg(all(feature = "graphql", feature = "websocket"))]
+    let router = router.route("/graphql", get(graphql_ws).post(graphql));
+    #[cfg(feature = "websocket")]
+    let router = router.route("/changes", get(crate::websocket::changes));
+    router.with_state(db)
+}
+
+// Serve `db` on `addr` (e.g. "127.0.0.1:8080") until the task is cancelled
+pub async fn serve(db: Arc<InMemoryDB>, addr: &str) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, router(db)).await
+}
+
+fn collection(db: &InMemoryDB, name: &str) -> ApiResult<Arc<Collection>> {
+    db.collection_arc(name)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("Collection '{}' not found.", name)))
+}
+
+fn find_by_id(collection: &Collection, id: &str) -> ApiResult<Value> {
+    let key_field = collection.key_field.as_deref().ok_or(EmemError::KeyFieldNotSet)?;
+    collection.select("*").eq(key_field, id).execute()?
+        .pop()
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("Document '{}' not found.", id)))
+}
+
+fn result_body(result: OperationResult) -> Value {
+    match result {
+        OperationResult::Inserted { id, document } => json!({ "id": id, "document": document }),
+        OperationResult::Updated { id, new_document, .. } => json!({ "id": id, "document": new_document }),
+        OperationResult::Deleted { id, document } => json!({ "id": id, "document": document }),
+    }
+}
+
+async fn list_collections(State(db): Db) -> Json<Vec<String>> {
+    let mut names = db.collection_names();
+    names.sort();
+    Json(names)
+}
+
+async fn query(State(db): Db, Path(name): Path<String>, RawQuery(params): RawQuery) -> ApiResult<Json<Vec<Value>>> {
+    let collection = collection(&db, &name)?;
+    let rows = collection.query_params(params.as_deref().unwrap_or(""))?.execute()?;
+    Ok(Json(rows))
+}
+
+async fn insert(State(db): Db, Path(name): Path<String>, Json(body): Json<Value>) -> ApiResult<(StatusCode, Json<Value>)> {
+    let collection = collection(&db, &name)?;
+    let inserted = match body {
+        Value::Array(documents) => {
+            let mut results = Vec::with_capacity(documents.len());
+            for document in documents {
+                results.push(result_body(collection.insert(document, None)?));
+            }
+            Value::Array(results)
+        }
+        document => result_body(collection.insert(document, None)?),
+    };
+    Ok((StatusCode::CREATED, Json(inserted)))
+}
+
+async fn fetch(State(db): Db, Path((name, id)): Path<(String, String)>) -> ApiResult<Json<Value>> {
+    let collection = collection(&db, &name)?;
+    Ok(Json(find_by_id(&collection, &id)?))
+}
+
+async fn replace(State(db): Db, Path((name, id)): Path<(String, String)>, Json(mut body): Json<Value>) -> ApiResult<Json<Value>> {
+    let collection = collection(&db, &name)?;
+    let key_field = collection.key_field.clone().ok_or("Key field is not set.".to_string())?;
+    if !body.is_object() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "Body must be a JSON object."));
+    }
+    body[key_field] = json!(id);
+    Ok(Json(result_body(collection.upsert(body, None)?)))
+}
+
+async fn patch(State(db): Db, Path((name, id)): Path<(String, String)>, Json(body): Json<Value>) -> ApiResult<Json<Value>> {
+    let collection = collection(&db, &name)?;
+    let changes = body.as_object()
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Body must be a JSON object."))?;
+    let mut document = find_by_id(&collection, &id)?;
+    for (field, value) in changes {
+        if Some(field) != collection.key_field.as_ref() {
+            document[field] = value.clone();
+        }
+    }
+    Ok(Json(result_body(collection.update(document)?)))
+}
+
+// The schema is rebuilt per request so collections created after startup are included
+#[cfg(feature = "graphql")]
+async fn graphql(State(db): Db, Json(request): Json<async_graphql::Request>) -> ApiResult<Json<async_graphql::Response>> {
+    let schema = crate::graphql::schema(db)?;
+    Ok(Json(schema.execute(request).await))
+}
+
+// Subscriptions (and queries) over a WebSocket, in whichever protocol the client asks for
+#[cfg(all(feature = "graphql", feature = "websocket"))]
+async fn graphql_ws(
+    State(db): Db,
+    ws: axum::extract::ws::WebSocketUpgrade,
+    headers: axum::http::HeaderMap,
+) -> ApiResult<Response> {
+    use async_graphql::futures_util::StreamExt;
+    use async_graphql::http::{WebSocket, WebSocketProtocols, WsMessage, ALL_WEBSOCKET_PROTOCOLS};
+    use axum::extract::ws::{CloseFrame, Message};
+
+    let protocol = headers.get(axum::http::header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|protocols| protocols.split(',').find_map(|p| p.trim().parse::<WebSocketProtocols>().ok()))
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Sec-WebSocket-Protocol must be graphql-transport-ws or graphql-ws"))?;
+    let schema = crate::graphql::schema(db)?;
+    Ok(ws.protocols(ALL_WEBSOCKET_PROTOCOLS).on_upgrade(move |mut socket| async move {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let incoming = async_graphql::futures_util::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|message| (message, receiver))
+        });
+        let mut outgoing = Box::pin(WebSocket::new(schema, incoming, protocol));
+        loop {
+            tokio::select! {
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let _ = sender.send(text.to_string());
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                },
+                message = outgoing.next() => {
+                    let message = match message {
+                        Some(WsMessage::Text(text)) => Message::Text(text.into()),
+                        Some(WsMessage::Close(code, reason)) => Message::Close(Some(CloseFrame { code, reason: reason.into() })),
+                        None => return,
+                    };
+                    if socket.send(message).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }))
+}
+
+async fn remove(State(db): Db, Path((name, id)): Path<(String, String)>) -> ApiResult<Json<Value>> {
+    let collection = collection(&db, &name)?;
+    Ok(Json(result_body(collection.delete(&id)?)))
+}
+
+#[cfg(all(test, feature = "graphql", feature = "websocket"))]
+mod tests {
+    use super::*;
+    use crate::config::{KeyType, TTL};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    async fn send_text(stream: &mut TcpStream, text: &str) {
+        // Client frames are masked; a zero mask leaves the payload as is
+        let mut frame = vec![0x81, 0x80 | 126];
+        frame.extend_from_slice(&(text.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame.extend_from_slice(text.as_bytes());
+        stream.write_all(&frame).await.unwrap();
+    }
+
+    async fn read_text(stream: &mut TcpStream) -> Value {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).await.unwrap();
+        let len = match header[1] & 0x7f {
+            126 => stream.read_u16().await.unwrap() as usize,
+            127 => stream.read_u64().await.unwrap() as usize,
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).await.unwrap();
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn graphql_subscriptions_stream_changes_over_websocket() {
+        let db = Arc::new(InMemoryDB::new("test", TTL::NoTTL));
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        users.insert(serde_json::json!({"id": "u0", "name": "kim"}), None).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(db)).await });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let handshake = format!(
+            "GET /graphql HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Protocol: graphql-transport-ws\r\n\r\n",
+            addr
+        );
+        stream.write_all(handshake.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(stream.read_u8().await.unwrap());
+        }
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101"), "{}", response);
+        assert!(response.to_lowercase().contains("sec-websocket-protocol: graphql-transport-ws"));
+
+        send_text(&mut stream, r#"{"type": "connection_init"}"#).await;
+        assert_eq!(read_text(&mut stream).await["type"], "connection_ack");
+        send_text(&mut stream, r#"{"id": "1", "type": "subscribe", "payload": {"query": "subscription { users_changes { event id } }"}}"#).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        users.insert(serde_json::json!({"id": "u1"}), None).unwrap();
+
+        let message = tokio::time::timeout(Duration::from_secs(5), read_text(&mut stream)).await.unwrap();
+        assert_eq!(message["type"], "next");
+        assert_eq!(message["payload"]["data"]["users_changes"], serde_json::json!({"event": "insert", "id": "u1"}));
+    }
+}
@@ -0,0 +1,172 @@
+// integrity.rs
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use crate::db::{DocumentEntry, InMemoryDB};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrphanKind {
+    ForeignKey,
+    // {"$ref": "collection", "$id": "key"} link embedded anywhere in a document
+    Ref,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Orphan {
+    pub collection: String,
+    pub document_id: String,
+    // Dotted path of the referencing field (array elements use their index)
+    pub path: String,
+    pub target_collection: String,
+    pub target_field: String,
+    pub target_value: Value,
+    pub kind: OrphanKind,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrphanReport {
+    pub scanned_documents: usize,
+    pub orphans: Vec<Orphan>,
+}
+
+impl OrphanReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphans.is_empty()
+    }
+}
+
+// How fix_orphans repairs a dangling reference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    NullOut,
+    DeleteReferencing,
+    RecreateStub,
+}
+
+fn collect_refs(path: &str, value: &Value, found: &mut Vec<(String, String, Value)>) {
+    match value {
+        Value::Object(map) => {
+            if let (Some(Value::String(target)), Some(id)) = (map.get("$ref"), map.get("$id")) {
+                found.push((path.to_string(), target.clone(), id.clone()));
+                return;
+            }
+            for (key, child) in map {
+                let child_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                collect_refs(&child_path, child, found);
+            }
+        }
+        Value::Array(items) => {
+            for (i, child) in items.iter().enumerate() {
+                collect_refs(&format!("{}.{}", path, i), child, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn pointer(path: &str) -> String {
+    format!("/{}", path.replace('~', "~0").replace('/', "~1").replace('.', "/"))
+}
+
+// Scan every collection for foreign keys and $ref links pointing at missing documents
+pub fn find_orphans(db: &InMemoryDB) -> OrphanReport {
+    let mut report = OrphanReport::default();
+    let mut collections = db.collection_arcs();
+    collections.sort_by(|a, b| a.collection_name.cmp(&b.collection_name));
+
+    for collection in &collections {
+        for entry in collection.documents.iter() {
+            report.scanned_documents += 1;
+            let doc = &entry.value().value;
+
+            for fk in &collection.foreign_keys {
+                let value = match doc.get(&fk.field) {
+                    None | Some(Value::Null) => continue,
+                    Some(value) => value,
+                };
+                let exists = db.collection_arc(&fk.references_collection)
+                    .is_some_and(|target| !target.ids_where(&fk.references_field, value).is_empty());
+                if !exists {
+                    report.orphans.push(Orphan {
+                        collection: collection.collection_name.clone(),
+                        document_id: entry.key().clone(),
+                        path: fk.field.clone(),
+                        target_collection: fk.references_collection.clone(),
+                        target_field: fk.references_field.clone(),
+                        target_value: value.clone(),
+                        kind: OrphanKind::ForeignKey,
+                    });
+                }
+            }
+
+            let mut refs = Vec::new();
+            collect_refs("", doc, &mut refs);
+            for (path, target_collection, id) in refs {
+                let target = db.collection_arc(&target_collection);
+                let exists = target.as_ref().is_some_and(|t| {
+                    id.as_str().is_some_and(|id| t.documents.contains_key(id))
+                });
+                if !exists {
+                    let target_field = target.and_then(|t| t.key_field.clone()).unwrap_or_default();
+                    report.orphans.push(Orphan {
+                        collection: collection.collection_name.clone(),
+                        document_id: entry.key().clone(),
+                        path,
+                        target_collection,
+                        target_field,
+                        target_value: id,
+                        kind: OrphanKind::Ref,
+                    });
+                }
+            }
+        }
+    }
+    report
+}
+
+// Repair the orphans of a report, returning how many were fixed
+pub fn fix_orphans(db: &InMemoryDB, report: &OrphanReport, policy: OrphanPolicy) -> Result<usize, String> {
+    let mut fixed = 0;
+    for orphan in &report.orphans {
+        let collection = db.collection_arc(&orphan.collection)
+            .ok_or_else(|| format!("Collection '{}' not found.", orphan.collection))?;
+        match policy {
+            OrphanPolicy::NullOut => {
+                if let Some(mut entry) = collection.documents.get_mut(&orphan.document_id) {
+                    if let Some(slot) = entry.document_mut().pointer_mut(&pointer(&orphan.path)) {
+                        *slot = Value::Null;
+                        fixed += 1;
+                    }
+                }
+            }
+            OrphanPolicy::DeleteReferencing => {
+                if collection.documents.contains_key(&orphan.document_id) {
+                    collection.remove_document(&orphan.document_id)?;
+                    fixed += 1;
+                }
+            }
+            OrphanPolicy::RecreateStub => {
+                let target = db.collection_arc(&orphan.target_collection)
+                    .ok_or_else(|| format!("Cannot recreate stub: collection '{}' not found.", orphan.target_collection))?;
+                if !target.ids_where(&orphan.target_field, &orphan.target_value).is_empty() {
+                    continue;
+                }
+                let id = match (&orphan.target_value, target.key_field.as_deref() == Some(orphan.target_field.as_str())) {
+                    (Value::String(id), true) => id.clone(),
+                    _ => Uuid::new_v4().to_string(),
+                };
+                let mut stub = json!({ "_stub": true });
+                if !orphan.target_field.is_empty() {
+                    stub[orphan.target_field.as_str()] = orphan.target_value.clone();
+                }
+                if let Some(key_field) = &target.key_field {
+                    stub[key_field.as_str()] = json!(id.clone());
+                }
+                target.reindex(&id, None, Some(&stub));
+                target.documents.insert(id, DocumentEntry::new(stub, None));
+                fixed += 1;
+            }
+        }
+    }
+    Ok(fixed)
+}
@@ -0,0 +1,50 @@
+// keygen.rs
+// Generated document keys for KeyType::ULID and KeyType::NanoId
+use std::sync::Mutex;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const NANOID_ALPHABET: &[u8; 64] = b"useandom-26T198340PX75pxJACKVERYMINDBUSHWOLF_GQZbfghjklqvwyzrict";
+const NANOID_LENGTH: usize = 21;
+
+// Last ULID handed out: (timestamp in ms, 80 random bits)
+static LAST_ULID: Mutex<(u64, u128)> = Mutex::new((0, 0));
+
+// The 14 bytes of a v4 UUID not touched by its version and variant bits
+fn random_bytes() -> Vec<u8> {
+    let bytes = *Uuid::new_v4().as_bytes();
+    bytes[..6].iter().chain(&bytes[7..8]).chain(&bytes[9..]).copied().collect()
+}
+
+// 26-character ULID: a 48-bit millisecond timestamp followed by 80 random bits, in Crockford
+// base32. Keys sort in creation order, including keys created within the same millisecond
+// (the random part is incremented instead of drawn again).
+pub fn ulid() -> String {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+    let (millis, random) = {
+        let mut last = LAST_ULID.lock().unwrap();
+        let random_mask = (1u128 << 80) - 1;
+        if now <= last.0 && last.1 < random_mask {
+            last.1 += 1;
+        } else {
+            let random = random_bytes()[..10].iter().fold(0u128, |acc, b| (acc << 8) | *b as u128);
+            *last = (now.max(last.0), random);
+        }
+        *last
+    };
+    let value = ((millis as u128 & 0xFFFF_FFFF_FFFF) << 80) | random;
+    (0..26).rev()
+        .map(|i| CROCKFORD[((value >> (i * 5)) & 0x1F) as usize] as char)
+        .collect()
+}
+
+// 21-character URL-safe random id, as produced by the nanoid library
+pub fn nanoid() -> String {
+    let mut bytes = random_bytes();
+    bytes.extend(random_bytes());
+    bytes.iter()
+        .take(NANOID_LENGTH)
+        .map(|b| NANOID_ALPHABET[(b & 63) as usize] as char)
+        .collect()
+}
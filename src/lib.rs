@@ -5,10 +5,74 @@ pub mod db;
 pub mod query;
 pub mod config;
 pub mod subscription;
+pub mod validation;
+pub mod metrics;
+pub mod replay;
+pub mod filter;
+pub mod protocol;
+pub mod integrity;
+pub mod sweeper;
+pub mod eviction;
+pub mod view;
+pub mod sql;
+pub mod rest;
+pub mod resp;
+pub mod snapshot;
+pub mod changelog;
+pub mod error;
+pub mod slowlog;
+pub mod audit;
+pub mod access;
+pub mod replication;
+pub mod sink;
+pub mod patch;
+pub mod keygen;
+pub mod bulk;
+pub mod cache;
+pub mod pipeline;
+pub mod text;
+pub mod trigram;
+pub mod hll;
+pub mod bloom;
+#[cfg(feature = "async")]
+pub mod asyncdb;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 // Re-export key items to make them accessible from outside the library
-pub use db::{InMemoryDB, OperationResult,Document,
-Collection};            // Now users can access InMemoryDB from the root
-pub use query::{QueryBuilder, JoinBuilder};       // Now users can access Query from the root
-pub use config::{TTL, KeyType, CollectionConfig};     // Re-export multiple items from config
-pub use subscription::Subscription;
+pub use db::{InMemoryDB, OperationResult, UpdateMode, OnConflict, ReturnDocument, UpsertSummary, Document,
+Collection, ComputedField, KeyGenerator};            // Now users can access InMemoryDB from the root
+pub use query::{QueryBuilder, JoinBuilder, JoinType, Window, WindowFn, Cursor, Page, QueryIter, QueryPlan, ScanStrategy, QueryArena, ArenaValue, QueryOptions, ReadConcern, DistinctMode};       // Now users can access Query from the root
+pub use config::{TTL, KeyType, IncrementKey, CollectionConfig, DbConfig, ForeignKey, OnDelete};     // Re-export multiple items from config
+pub use subscription::{Subscription, EventType, AdminEvent};
+pub use validation::{DocumentValidator, RuleValidator, PiiScanner, SchemaValidator, CheckConstraint};
+pub use metrics::{CollectionStats, FieldStats, HistogramBucket, NumericFieldStats, TtlStats, LatencySummary, OperationKind, MemoryStats, DbMemoryStats, LargeDocument};
+pub use replay::{OperationRecorder, Replayer, ReplayReport, SyncMode};
+pub use filter::{FilterExpr, QuerySpec, SortOrder, CompareFn};
+pub use integrity::{Orphan, OrphanKind, OrphanPolicy, OrphanReport};
+pub use sweeper::Sweeper;
+pub use error::{EmemError, EmemResult};
+pub use slowlog::{SlowQuery, SlowQueryLog};
+pub use audit::{AuditEntry, AUDIT_COLLECTION};
+pub use access::{DbHandle, Permission, Role};
+pub use replication::{Replica, ReplicationStatus};
+pub use patch::PatchOp;
+pub use bulk::{BulkOp, BulkWriteBuilder, BulkWriteSummary};
+pub use cache::{CacheLoader, FnLoader, AsyncFnLoader};
+pub use pipeline::{Pipeline, Stage};
+pub use text::{Analyzer, Tokenizer, ENGLISH_STOP_WORDS};
+pub use trigram::TrigramIndex;
+pub use hll::HyperLogLog;
+pub use bloom::BloomFilter;
+pub use sink::{ChangeSink, FnSink, AsyncFnSink, FileSink, WebhookSink, RetryPolicy, SinkOptions, SinkStatus, SinkConnector};
+pub use resp::RespServer;
+#[cfg(feature = "async")]
+pub use asyncdb::{AsyncDb, AsyncCollection};
+pub use snapshot::{Snapshot, CollectionSnapshot, DocumentSnapshot};
+pub use changelog::{ChangeLog, ChangeEvent, ChangeFilter};
+pub use view::{MaterializedView, SavedView};
+pub use eviction::{EvictionPolicy, LruPolicy, LfuPolicy, FifoPolicy, RandomPolicy, TtlSoonestPolicy, ScoredPolicy};
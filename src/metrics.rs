@@ -0,0 +1,444 @@
+// metrics.rs
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+// Log-linear buckets: 32 sub-buckets per power of two keeps the relative error around 3%
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const MAX_EXPONENT: u32 = 45; // ~9.7 hours in nanoseconds
+const BUCKET_COUNT: usize = (SUB_BUCKETS + (MAX_EXPONENT - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS) as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    Insert,
+    Update,
+    Delete,
+    Query,
+    Join,
+}
+
+// Lock-free HDR-style latency histogram (nanosecond resolution)
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        LatencyHistogram {
+            buckets: (0..BUCKET_COUNT).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    fn bucket_index(value: u64) -> usize {
+        if value < SUB_BUCKETS {
+            return value as usize;
+        }
+        let exponent = (63 - value.leading_zeros()).min(MAX_EXPONENT);
+        let value = value.min((1u64 << (MAX_EXPONENT + 1)) - 1);
+        let sub = (value >> (exponent - SUB_BUCKET_BITS)) - SUB_BUCKETS;
+        (SUB_BUCKETS + (exponent - SUB_BUCKET_BITS) as u64 * SUB_BUCKETS + sub) as usize
+    }
+
+    // Upper bound of the values counted in a bucket
+    fn bucket_value(index: usize) -> u64 {
+        let index = index as u64;
+        if index < SUB_BUCKETS {
+            return index;
+        }
+        let exponent = (index - SUB_BUCKETS) / SUB_BUCKETS + SUB_BUCKET_BITS as u64;
+        let sub = (index - SUB_BUCKETS) % SUB_BUCKETS;
+        let shift = exponent - SUB_BUCKET_BITS as u64;
+        ((SUB_BUCKETS + sub + 1) << shift) - 1
+    }
+
+    pub fn record(&self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[Self::bucket_index(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(nanos, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max.load(Ordering::Relaxed))
+    }
+
+    pub fn mean(&self) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos(self.sum.load(Ordering::Relaxed) / count)
+    }
+
+    // Latency at the given percentile (0.0 - 100.0)
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0) * count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return Duration::from_nanos(Self::bucket_value(index)).min(self.max());
+            }
+        }
+        self.max()
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count(),
+            mean: self.mean(),
+            p50: self.percentile(50.0),
+            p90: self.percentile(90.0),
+            p99: self.percentile(99.0),
+            p999: self.percentile(99.9),
+            max: self.max(),
+        }
+    }
+
+    pub fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+
+// Per-collection latency histograms, one per operation type
+#[derive(Debug, Default)]
+pub struct CollectionMetrics {
+    insert: LatencyHistogram,
+    update: LatencyHistogram,
+    delete: LatencyHistogram,
+    query: LatencyHistogram,
+    join: LatencyHistogram,
+    // Operation counts since the collection was created; not cleared by reset
+    reads: AtomicU64,
+    writes: AtomicU64,
+    // Expiration counters (see TtlStats); also kept across reset
+    expired: AtomicU64,
+    swept: AtomicU64,
+    expired_fields: AtomicU64,
+    ttl_touched: AtomicU64,
+}
+
+impl CollectionMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn histogram(&self, kind: OperationKind) -> &LatencyHistogram {
+        match kind {
+            OperationKind::Insert => &self.insert,
+            OperationKind::Update => &self.update,
+            OperationKind::Delete => &self.delete,
+            OperationKind::Query => &self.query,
+            OperationKind::Join => &self.join,
+        }
+    }
+
+    pub fn record(&self, kind: OperationKind, latency: Duration) {
+        self.histogram(kind).record(latency);
+        match kind {
+            OperationKind::Query | OperationKind::Join => self.reads.fetch_add(1, Ordering::Relaxed),
+            OperationKind::Insert | OperationKind::Update | OperationKind::Delete => self.writes.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn reads(&self) -> u64 {
+        self.reads.load(Ordering::Relaxed)
+    }
+
+    pub fn writes(&self) -> u64 {
+        self.writes.load(Ordering::Relaxed)
+    }
+
+    // A document removed by the sweeper; `expired` when its TTL had passed (rather than the retention)
+    pub fn record_swept(&self, expired: bool) {
+        self.swept.fetch_add(1, Ordering::Relaxed);
+        if expired {
+            self.expired.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_expired_fields(&self, fields: usize) {
+        self.expired_fields.fetch_add(fields as u64, Ordering::Relaxed);
+    }
+
+    // A TTL set or renewed on a stored document
+    pub fn record_ttl_touch(&self) {
+        self.ttl_touched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for kind in [OperationKind::Insert, OperationKind::Update, OperationKind::Delete, OperationKind::Query, OperationKind::Join] {
+            self.histogram(kind).reset();
+        }
+    }
+}
+
+// Presence and distinct-value count of one top-level field
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldStats {
+    pub field: String,
+    // Documents that have the field (null included)
+    pub present: usize,
+    pub distinct: usize,
+}
+
+// One bucket of a NumericFieldStats histogram; `upper` is inclusive for the last bucket only
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistogramBucket {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: usize,
+}
+
+// Distribution of a numeric field over the live documents, from Collection::field_stats
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NumericFieldStats {
+    pub field: String,
+    // Documents whose field holds a number
+    pub count: usize,
+    // Documents whose field holds something else (null included)
+    pub non_numeric: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    // Equal-width buckets from min to max
+    pub histogram: Vec<HistogramBucket>,
+}
+
+impl NumericFieldStats {
+    pub fn from_values<'a, I: IntoIterator<Item = &'a serde_json::Value>>(field: &str, values: I, buckets: usize) -> Self {
+        let mut stats = NumericFieldStats { field: field.to_string(), ..Self::default() };
+        let mut numbers = Vec::new();
+        for value in values {
+            match value.as_f64() {
+                Some(n) => numbers.push(n),
+                None => stats.non_numeric += 1,
+            }
+        }
+        if numbers.is_empty() {
+            return stats;
+        }
+        let min = numbers.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        stats.count = numbers.len();
+        stats.min = Some(min);
+        stats.max = Some(max);
+        stats.mean = Some(numbers.iter().sum::<f64>() / numbers.len() as f64);
+
+        // A field holding a single value gets one bucket
+        let buckets = if min == max { 1 } else { buckets.max(1) };
+        let width = (max - min) / buckets as f64;
+        stats.histogram = (0..buckets)
+            .map(|i| HistogramBucket {
+                lower: min + width * i as f64,
+                upper: if i + 1 == buckets { max } else { min + width * (i + 1) as f64 },
+                count: 0,
+            })
+            .collect();
+        for n in numbers {
+            let i = if width > 0.0 { (((n - min) / width) as usize).min(buckets - 1) } else { 0 };
+            stats.histogram[i].count += 1;
+        }
+        stats
+    }
+
+    // Estimated fraction (0.0 to 1.0) of the numeric values between `lower` and `upper`, taking
+    // values to be spread evenly within each bucket. Used to guess how selective a range filter is.
+    pub fn selectivity(&self, lower: f64, upper: f64) -> f64 {
+        if self.count == 0 || lower > upper {
+            return 0.0;
+        }
+        let matching: f64 = self.histogram.iter()
+            .map(|bucket| {
+                let width = bucket.upper - bucket.lower;
+                if width <= 0.0 {
+                    return if (lower..=upper).contains(&bucket.lower) { bucket.count as f64 } else { 0.0 };
+                }
+                let overlap = (upper.min(bucket.upper) - lower.max(bucket.lower)).max(0.0);
+                bucket.count as f64 * overlap / width
+            })
+            .sum();
+        (matching / self.count as f64).min(1.0)
+    }
+}
+
+// Expiration activity of one collection, from Collection::ttl_stats. The counters run from the
+// collection's creation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TtlStats {
+    // Documents removed by the sweeper because their TTL had passed
+    pub expired: u64,
+    // Documents removed by the sweeper for any reason, the database's retention included
+    pub swept: u64,
+    // Fields removed by their own TTL (see Collection::expire_field)
+    pub expired_fields: u64,
+    // TTLs set or renewed on stored documents
+    pub touched: u64,
+    // Live documents that have a TTL
+    pub expiring: usize,
+    // Documents past their TTL that the sweeper hasn't removed yet
+    pub pending: usize,
+    // Earliest expiration among the live documents
+    pub next_expiration: Option<SystemTime>,
+}
+
+impl TtlStats {
+    pub fn from_metrics(metrics: &CollectionMetrics) -> Self {
+        TtlStats {
+            expired: metrics.expired.load(Ordering::Relaxed),
+            swept: metrics.swept.load(Ordering::Relaxed),
+            expired_fields: metrics.expired_fields.load(Ordering::Relaxed),
+            touched: metrics.ttl_touched.load(Ordering::Relaxed),
+            ..Self::default()
+        }
+    }
+
+    // Time until the next document expires, zero when one is already due
+    pub fn next_expiration_in(&self) -> Option<Duration> {
+        self.next_expiration.map(|at| at.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+}
+
+// Snapshot returned by Collection::stats()
+#[derive(Debug, Clone, Default)]
+pub struct CollectionStats {
+    // Live documents, excluding soft-deleted and expired ones
+    pub documents: usize,
+    // Past their TTL but not yet removed by the sweeper
+    pub expired: usize,
+    pub fields: Vec<FieldStats>,
+    pub index_bytes: usize,
+    // Queries and joins since the collection was created
+    pub reads: u64,
+    // Inserts, updates and deletes since the collection was created
+    pub writes: u64,
+    pub insert_latency: LatencySummary,
+    pub update_latency: LatencySummary,
+    pub delete_latency: LatencySummary,
+    pub query_latency: LatencySummary,
+    pub join_latency: LatencySummary,
+    pub p99_insert_latency: Duration,
+    pub p99_update_latency: Duration,
+    pub p99_delete_latency: Duration,
+    pub p99_query_latency: Duration,
+    pub p99_join_latency: Duration,
+}
+
+impl CollectionStats {
+    pub fn from_metrics(metrics: &CollectionMetrics) -> Self {
+        let insert_latency = metrics.histogram(OperationKind::Insert).summary();
+        let update_latency = metrics.histogram(OperationKind::Update).summary();
+        let delete_latency = metrics.histogram(OperationKind::Delete).summary();
+        let query_latency = metrics.histogram(OperationKind::Query).summary();
+        let join_latency = metrics.histogram(OperationKind::Join).summary();
+        CollectionStats {
+            reads: metrics.reads(),
+            writes: metrics.writes(),
+            p99_insert_latency: insert_latency.p99,
+            p99_update_latency: update_latency.p99,
+            p99_delete_latency: delete_latency.p99,
+            p99_query_latency: query_latency.p99,
+            p99_join_latency: join_latency.p99,
+            insert_latency,
+            update_latency,
+            delete_latency,
+            query_latency,
+            join_latency,
+            ..Default::default()
+        }
+    }
+}
+
+// How many of the biggest documents memory_stats() lists
+pub const LARGEST_DOCUMENTS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LargeDocument {
+    pub collection: String,
+    pub id: String,
+    pub bytes: usize,
+}
+
+// Approximate memory footprint of one collection. Document bytes are the JSON-encoded size of
+// each document; index bytes cover the primary key map (key strings plus per-entry bookkeeping).
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStats {
+    pub documents: usize,
+    pub document_bytes: usize,
+    pub index_bytes: usize,
+    pub total_bytes: usize,
+    pub largest_documents: Vec<LargeDocument>,
+}
+
+impl MemoryStats {
+    pub(crate) fn keep_largest(largest: &mut Vec<LargeDocument>) {
+        largest.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.id.cmp(&b.id)));
+        largest.truncate(LARGEST_DOCUMENTS);
+    }
+}
+
+// Database-wide totals plus the per-collection breakdown
+#[derive(Debug, Clone, Default)]
+pub struct DbMemoryStats {
+    pub collections: Vec<(String, MemoryStats)>,
+    pub documents: usize,
+    pub document_bytes: usize,
+    pub index_bytes: usize,
+    pub total_bytes: usize,
+    pub largest_documents: Vec<LargeDocument>,
+}
+
+impl DbMemoryStats {
+    pub fn from_collections(mut collections: Vec<(String, MemoryStats)>) -> Self {
+        collections.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut stats = DbMemoryStats::default();
+        for (_, collection) in &collections {
+            stats.documents += collection.documents;
+            stats.document_bytes += collection.document_bytes;
+            stats.index_bytes += collection.index_bytes;
+            stats.total_bytes += collection.total_bytes;
+            stats.largest_documents.extend(collection.largest_documents.iter().cloned());
+        }
+        MemoryStats::keep_largest(&mut stats.largest_documents);
+        stats.collections = collections;
+        stats
+    }
+}
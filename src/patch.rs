@@ -0,0 +1,196 @@
+// patch.rs
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// Apply a JSON Merge Patch (RFC 7386): objects merge recursively, null removes a key and any
+// other value replaces the target
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Some(patch) = patch.as_object() else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Some(map) = target.as_object_mut() {
+        for (key, value) in patch {
+            if value.is_null() {
+                map.remove(key);
+            } else {
+                merge_patch(map.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+// One JSON Patch (RFC 6902) operation; paths are JSON Pointers (RFC 6901) such as "/tags/0"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+impl PatchOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PatchOp::Add { .. } => "add",
+            PatchOp::Remove { .. } => "remove",
+            PatchOp::Replace { .. } => "replace",
+            PatchOp::Move { .. } => "move",
+            PatchOp::Copy { .. } => "copy",
+            PatchOp::Test { .. } => "test",
+        }
+    }
+
+    // Parse a JSON array of operations, e.g. [{"op": "add", "path": "/tags/-", "value": "new"}]
+    pub fn parse(ops: &Value) -> Result<Vec<PatchOp>, String> {
+        serde_json::from_value(ops.clone()).map_err(|e| format!("Invalid JSON Patch: {}", e))
+    }
+}
+
+// Apply the operations in order. Stops at the first failing one and returns its position with
+// the reason; the target may then be partially patched, so callers patch a copy.
+pub fn apply_patch(target: &mut Value, ops: &[PatchOp]) -> Result<(), (usize, String)> {
+    for (index, op) in ops.iter().enumerate() {
+        apply_op(target, op).map_err(|message| (index, message))?;
+    }
+    Ok(())
+}
+
+fn apply_op(target: &mut Value, op: &PatchOp) -> Result<(), String> {
+    match op {
+        PatchOp::Add { path, value } => add(target, path, value.clone()),
+        PatchOp::Remove { path } => remove(target, path).map(|_| ()),
+        PatchOp::Replace { path, value } => {
+            let slot = target.pointer_mut(path).ok_or_else(|| format!("path '{}' does not exist", path))?;
+            *slot = value.clone();
+            Ok(())
+        }
+        PatchOp::Move { from, path } => {
+            if path.starts_with(from.as_str()) && path[from.len()..].starts_with('/') {
+                return Err(format!("cannot move '{}' into its own child '{}'", from, path));
+            }
+            let value = remove(target, from)?;
+            add(target, path, value)
+        }
+        PatchOp::Copy { from, path } => {
+            let value = target.pointer(from).cloned().ok_or_else(|| format!("path '{}' does not exist", from))?;
+            add(target, path, value)
+        }
+        PatchOp::Test { path, value } => match target.pointer(path) {
+            Some(actual) if actual == value => Ok(()),
+            Some(actual) => Err(format!("value at '{}' is {} instead of {}", path, actual, value)),
+            None => Err(format!("path '{}' does not exist", path)),
+        },
+    }
+}
+
+// (parent pointer, unescaped last token)
+fn split_pointer(path: &str) -> Result<(&str, String), String> {
+    let (parent, last) = path.rsplit_once('/').ok_or_else(|| format!("invalid JSON Pointer '{}'", path))?;
+    Ok((parent, last.replace("~1", "/").replace("~0", "~")))
+}
+
+fn add(target: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+    let (parent, last) = split_pointer(path)?;
+    match target.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(last, value);
+            Ok(())
+        }
+        Some(Value::Array(array)) => {
+            let index = if last == "-" { array.len() } else { array_index(&last, array.len() + 1)? };
+            array.insert(index, value);
+            Ok(())
+        }
+        Some(_) => Err(format!("parent of '{}' is not an object or array", path)),
+        None => Err(format!("parent of '{}' does not exist", path)),
+    }
+}
+
+fn remove(target: &mut Value, path: &str) -> Result<Value, String> {
+    let (parent, last) = split_pointer(path)?;
+    match target.pointer_mut(parent) {
+        Some(Value::Object(map)) => map.remove(&last).ok_or_else(|| format!("path '{}' does not exist", path)),
+        Some(Value::Array(array)) => {
+            let index = array_index(&last, array.len())?;
+            Ok(array.remove(index))
+        }
+        _ => Err(format!("path '{}' does not exist", path)),
+    }
+}
+
+// Array index below `len`; only plain digits, without leading zeros
+fn array_index(token: &str, len: usize) -> Result<usize, String> {
+    let index = Some(token)
+        .filter(|token| token.bytes().all(|b| b.is_ascii_digit()) && (*token == "0" || !token.starts_with('0')))
+        .and_then(|token| token.parse::<usize>().ok())
+        .ok_or_else(|| format!("invalid array index '{}'", token))?;
+    if index >= len {
+        return Err(format!("array index {} is out of bounds", index));
+    }
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn patch(mut target: Value, ops: Value) -> Result<Value, (usize, String)> {
+        apply_patch(&mut target, &PatchOp::parse(&ops).unwrap())?;
+        Ok(target)
+    }
+
+    #[test]
+    fn pointers_unescape_tilde_and_slash() {
+        let doc = json!({"a/b": 1, "m~n": 2});
+        let patched = patch(doc, json!([
+            {"op": "replace", "path": "/a~1b", "value": 10},
+            {"op": "add", "path": "/x~0~1y", "value": 3},
+            {"op": "remove", "path": "/m~0n"},
+        ]));
+        assert_eq!(patched, Ok(json!({"a/b": 10, "x~/y": 3})));
+    }
+
+    #[test]
+    fn dash_appends_to_arrays() {
+        let patched = patch(json!({"tags": ["a"]}), json!([
+            {"op": "add", "path": "/tags/-", "value": "b"},
+            {"op": "add", "path": "/tags/0", "value": "z"},
+        ]));
+        assert_eq!(patched, Ok(json!({"tags": ["z", "a", "b"]})));
+        assert!(patch(json!({"tags": ["a"]}), json!([{"op": "remove", "path": "/tags/-"}])).is_err());
+    }
+
+    #[test]
+    fn array_indices_are_plain_digits() {
+        let doc = json!({"tags": ["a", "b", "c"]});
+        assert_eq!(patch(doc.clone(), json!([{"op": "remove", "path": "/tags/0"}])), Ok(json!({"tags": ["b", "c"]})));
+        for index in ["01", "00", "+1", "-1", " 1"] {
+            let path = format!("/tags/{}", index);
+            assert!(patch(doc.clone(), json!([{"op": "remove", "path": path}])).is_err(), "{}", index);
+        }
+        assert!(patch(doc, json!([{"op": "remove", "path": "/tags/3"}])).is_err());
+    }
+
+    #[test]
+    fn move_into_own_child_fails() {
+        let doc = json!({"a": {"b": 1}, "ab": 2});
+        let err = patch(doc.clone(), json!([{"op": "move", "from": "/a", "path": "/a/c"}])).unwrap_err();
+        assert_eq!(err.0, 0);
+        assert_eq!(
+            patch(doc, json!([{"op": "move", "from": "/a", "path": "/ab"}])),
+            Ok(json!({"ab": {"b": 1}}))
+        );
+    }
+}
@@ -0,0 +1,225 @@
+// pipeline.rs
+// Aggregation pipelines built in Rust, with the stages of the "aggregate" command (protocol.rs):
+//
+//   let top = orders.pipeline()
+//       .match_(FilterExpr::eq("status", "paid"))
+//       .unwind("items")
+//       .group(json!({"_id": "$items.sku", "sold": {"$sum": "$items.qty"}}))
+//       .sort("sold", SortOrder::Desc)
+//       .limit(10)
+//       .execute()?;
+//
+// Documents stream through match, unwind, project, map, skip and limit one at a time, so a
+// pipeline without group, sort or lookup stops reading the collection once its limit is reached.
+// Group, sort and lookup need every document that reaches them.
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+use crate::db::Collection;
+use crate::error::EmemError;
+use crate::filter::{compare_by, FilterExpr, SortOrder};
+use crate::protocol::{lookup, run_group};
+
+type StageFn = Arc<dyn Fn(Value) -> Value + Send + Sync>;
+type GroupPredicate = Arc<dyn Fn(&Value) -> bool + Send + Sync>;
+type Documents = Box<dyn Iterator<Item = Result<Value, EmemError>>>;
+
+#[derive(Clone)]
+pub enum Stage {
+    Match(FilterExpr),
+    // One document per element of an array field (see unwind); with `preserve_empty`, documents
+    // without elements are kept with the field set to null
+    Unwind { field: String, preserve_empty: bool },
+    // A $group specification, e.g. {"_id": "$city", "total": {"$sum": "$amount"}}
+    Group(Value),
+    // Keeps the groups of the preceding group stage that satisfy the predicate
+    Having(GroupPredicate),
+    Sort(Vec<(String, SortOrder)>),
+    Skip(usize),
+    Limit(usize),
+    Project(Vec<String>),
+    Lookup { from: String, local_field: String, foreign_field: String, as_field: String },
+    Map(StageFn),
+}
+
+impl fmt::Debug for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Match(expr) => write!(f, "Match({:?})", expr),
+            Stage::Unwind { field, preserve_empty } => write!(f, "Unwind({}, preserve_empty: {})", field, preserve_empty),
+            Stage::Group(spec) => write!(f, "Group({})", spec),
+            Stage::Having(_) => write!(f, "Having"),
+            Stage::Sort(keys) => write!(f, "Sort({:?})", keys),
+            Stage::Skip(n) => write!(f, "Skip({})", n),
+            Stage::Limit(n) => write!(f, "Limit({})", n),
+            Stage::Project(fields) => write!(f, "Project({:?})", fields),
+            Stage::Lookup { from, local_field, foreign_field, as_field } => {
+                write!(f, "Lookup({}.{} = {} as {})", from, foreign_field, local_field, as_field)
+            }
+            Stage::Map(_) => write!(f, "Map"),
+        }
+    }
+}
+
+// Copies of `document`, one per element of the array in `field`, with the element in its place
+// and every other field copied from the parent. Documents whose field is missing, null or an
+// empty array produce nothing (or themselves with the field set to null, with `preserve_empty`);
+// any other value is passed through unchanged.
+pub fn unwind(document: Value, field: &str, preserve_empty: bool) -> Vec<Value> {
+    match document.get(field) {
+        Some(Value::Array(items)) if !items.is_empty() => items.iter()
+            .map(|item| {
+                let mut copy = document.clone();
+                copy[field] = item.clone();
+                copy
+            })
+            .collect(),
+        None | Some(Value::Null) | Some(Value::Array(_)) if preserve_empty => {
+            let mut copy = document;
+            copy[field] = Value::Null;
+            vec![copy]
+        }
+        None | Some(Value::Null) | Some(Value::Array(_)) => Vec::new(),
+        Some(_) => vec![document],
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    collection: Arc<Collection>,
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn new(collection: Arc<Collection>) -> Self {
+        Pipeline { collection, stages: Vec::new() }
+    }
+
+    pub fn stage(mut self, stage: Stage) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn match_(self, expr: FilterExpr) -> Self {
+        self.stage(Stage::Match(expr))
+    }
+
+    pub fn unwind(self, field: &str) -> Self {
+        self.stage(Stage::Unwind { field: field.to_string(), preserve_empty: false })
+    }
+
+    // unwind, keeping documents whose array is missing or empty
+    pub fn unwind_preserving(self, field: &str) -> Self {
+        self.stage(Stage::Unwind { field: field.to_string(), preserve_empty: true })
+    }
+
+    pub fn group(self, spec: Value) -> Self {
+        self.stage(Stage::Group(spec))
+    }
+
+    // Filter groups on their aggregate values, e.g.
+    // .group(json!({"_id": "$customer", "sum_amount": {"$sum": "$amount"}}))
+    // .having(|group| group["sum_amount"].as_f64() > Some(1000.0))
+    pub fn having<P>(self, predicate: P) -> Self
+    where
+        P: Fn(&Value) -> bool + Send + Sync + 'static,
+    {
+        self.stage(Stage::Having(Arc::new(predicate)))
+    }
+
+    // Sorts are stable: ties keep the order an earlier sort stage gave them
+    pub fn sort(self, field: &str, order: SortOrder) -> Self {
+        self.stage(Stage::Sort(vec![(field.to_string(), order)]))
+    }
+
+    pub fn skip(self, n: usize) -> Self {
+        self.stage(Stage::Skip(n))
+    }
+
+    pub fn limit(self, n: usize) -> Self {
+        self.stage(Stage::Limit(n))
+    }
+
+    // Keep only the comma-separated `fields`
+    pub fn project(self, fields: &str) -> Self {
+        let fields = fields.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect();
+        self.stage(Stage::Project(fields))
+    }
+
+    // Attach the documents of `from` whose `foreign_field` equals `local_field` as an array
+    pub fn lookup(self, from: &str, local_field: &str, foreign_field: &str, as_field: &str) -> Self {
+        self.stage(Stage::Lookup {
+            from: from.to_string(),
+            local_field: local_field.to_string(),
+            foreign_field: foreign_field.to_string(),
+            as_field: as_field.to_string(),
+        })
+    }
+
+    pub fn map<F>(self, f: F) -> Self
+    where
+        F: Fn(Value) -> Value + Send + Sync + 'static,
+    {
+        self.stage(Stage::Map(Arc::new(f)))
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    pub fn execute(self) -> Result<Vec<Value>, EmemError> {
+        self.iter()?.collect()
+    }
+
+    // Documents coming out of the last stage, produced as the iterator advances
+    pub fn iter(self) -> Result<impl Iterator<Item = Result<Value, EmemError>>, EmemError> {
+        for (i, stage) in self.stages.iter().enumerate() {
+            let after_group = i > 0 && matches!(self.stages[i - 1], Stage::Group(_) | Stage::Having(_));
+            if matches!(stage, Stage::Having(_)) && !after_group {
+                return Err(EmemError::InvalidQuery("having() must directly follow group()".to_string()));
+            }
+        }
+        // Leading match stages become the query's filters, so key lookups still apply
+        let mut query = self.collection.select("*");
+        let mut stages = self.stages.into_iter().peekable();
+        while let Some(Stage::Match(expr)) = stages.next_if(|stage| matches!(stage, Stage::Match(_))) {
+            query = query.where_expr(expr);
+        }
+        let mut documents: Documents = Box::new(query.iter()?);
+        for stage in stages {
+            documents = match stage {
+                Stage::Match(expr) => Box::new(documents.filter(move |doc| doc.as_ref().map_or(true, |doc| expr.matches(doc)))),
+                Stage::Unwind { field, preserve_empty } => Box::new(documents.flat_map(move |doc| match doc {
+                    Ok(doc) => unwind(doc, &field, preserve_empty).into_iter().map(Ok).collect::<Vec<_>>(),
+                    Err(e) => vec![Err(e)],
+                })),
+                Stage::Project(fields) => Box::new(documents.map(move |doc| doc.map(|doc| project(&doc, &fields)))),
+                Stage::Map(f) => Box::new(documents.map(move |doc| doc.map(|doc| f(doc)))),
+                Stage::Having(predicate) => Box::new(documents.filter(move |group| group.as_ref().map_or(true, |group| predicate(group)))),
+                Stage::Skip(n) => Box::new(documents.skip(n)),
+                Stage::Limit(n) => Box::new(documents.take(n)),
+                Stage::Group(spec) => {
+                    let collected = documents.collect::<Result<Vec<_>, _>>()?;
+                    Box::new(run_group(collected, &spec)?.into_iter().map(Ok))
+                }
+                Stage::Sort(keys) => {
+                    let mut collected = documents.collect::<Result<Vec<_>, _>>()?;
+                    collected.sort_by(|a, b| compare_by(a, b, &keys));
+                    Box::new(collected.into_iter().map(Ok))
+                }
+                Stage::Lookup { from, local_field, foreign_field, as_field } => {
+                    let collected = documents.collect::<Result<Vec<_>, _>>()?;
+                    let joined = lookup(&self.collection.parent_db, collected, &from, &local_field, &foreign_field, &as_field)?;
+                    Box::new(joined.into_iter().map(Ok))
+                }
+            };
+        }
+        Ok(documents)
+    }
+}
+
+fn project(document: &Value, fields: &[String]) -> Value {
+    Value::Object(fields.iter()
+        .filter_map(|field| document.get(field).map(|value| (field.clone(), value.clone())))
+        .collect())
+}
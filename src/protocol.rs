@@ -0,0 +1,298 @@
+// protocol.rs
+// Transport-neutral command dialect shared by the network layers.
+//
+//   {"find": "users", "filter": {"age": {"$gte": 18}}, "projection": ["name"], "sort": {"age": -1}, "limit": 10}
+//   {"count": "users", "filter": {...}}
+//   {"aggregate": "orders", "pipeline": [{"$match": {...}}, {"$unwind": "$items"}, {"$lookup": {...}}, {"$group": {...}}]}
+//   {"insert": "users", "documents": [{...}]}
+//   {"view": "adults"}
+use serde_json::{json, Map, Value};
+use crate::db::InMemoryDB;
+use crate::pipeline::unwind;
+use crate::filter::{compare_by, compare_values, FilterExpr, SortOrder};
+
+// Convert a filter document into a FilterExpr
+pub fn parse_filter(filter: &Value) -> Result<Option<FilterExpr>, String> {
+    let map = match filter {
+        Value::Null => return Ok(None),
+        Value::Object(map) => map,
+        _ => return Err("Filter must be a JSON object.".to_string()),
+    };
+    let mut exprs = Vec::new();
+    for (key, value) in map {
+        match key.as_str() {
+            "$and" | "$or" | "$nor" => {
+                let items = value.as_array().ok_or_else(|| format!("{} expects an array", key))?;
+                let mut children = Vec::new();
+                for item in items {
+                    if let Some(expr) = parse_filter(item)? {
+                        children.push(expr);
+                    }
+                }
+                exprs.push(match key.as_str() {
+                    "$and" => FilterExpr::and(children),
+                    "$or" => FilterExpr::or(children),
+                    _ => !FilterExpr::or(children),
+                });
+            }
+            field if field.starts_with('$') => return Err(format!("Unknown top-level operator '{}'", field)),
+            field => exprs.extend(parse_field_condition(field, value)?),
+        }
+    }
+    Ok(match exprs.len() {
+        0 => None,
+        1 => exprs.pop(),
+        _ => Some(FilterExpr::and(exprs)),
+    })
+}
+
+fn is_operator_document(value: &Value) -> bool {
+    value.as_object().map_or(false, |map| !map.is_empty() && map.keys().all(|k| k.starts_with('$')))
+}
+
+fn parse_field_condition(field: &str, condition: &Value) -> Result<Vec<FilterExpr>, String> {
+    if !is_operator_document(condition) {
+        return Ok(vec![FilterExpr::eq(field, condition.clone())]);
+    }
+    let number = |op: &str, v: &Value| v.as_f64().ok_or_else(|| format!("{} on '{}' expects a number", op, field));
+    let operators = condition.as_object().unwrap();
+    let mut exprs = Vec::new();
+    for (op, operand) in operators {
+        exprs.push(match op.as_str() {
+            "$eq" => FilterExpr::eq(field, operand.clone()),
+            "$ne" => FilterExpr::neq(field, operand.clone()),
+            "$gt" => FilterExpr::gt(field, number(op, operand)?),
+            "$gte" => FilterExpr::gte(field, number(op, operand)?),
+            "$lt" => FilterExpr::lt(field, number(op, operand)?),
+            "$lte" => FilterExpr::lte(field, number(op, operand)?),
+            "$in" => FilterExpr::in_(field, operand.as_array().ok_or("$in expects an array")?.clone()),
+            "$nin" => !FilterExpr::in_(field, operand.as_array().ok_or("$nin expects an array")?.clone()),
+            "$regex" => {
+                let pattern = operand.as_str().ok_or("$regex expects a string")?;
+                let pattern = match operators.get("$options") {
+                    Some(options) => {
+                        let flags = options.as_str().ok_or("$options expects a string")?;
+                        if let Some(flag) = flags.chars().find(|c| !"imsx".contains(*c)) {
+                            return Err(format!("Unknown regex option '{}'", flag));
+                        }
+                        format!("(?{}){}", flags, pattern)
+                    }
+                    None => pattern.to_string(),
+                };
+                FilterExpr::regex(field, &pattern)?
+            }
+            "$options" if operators.contains_key("$regex") => continue,
+            "$options" => return Err(format!("$options on '{}' requires $regex", field)),
+            "$exists" => FilterExpr::exists(field, operand.as_bool().ok_or("$exists expects a boolean")?),
+            "$not" => {
+                let inner = parse_field_condition(field, operand)?;
+                !FilterExpr::and(inner)
+            }
+            other => return Err(format!("Unknown operator '{}' on field '{}'", other, field)),
+        });
+    }
+    Ok(exprs)
+}
+
+fn sort_documents(documents: &mut [Value], spec: &Value) -> Result<(), String> {
+    let keys: Vec<(String, SortOrder)> = spec.as_object().ok_or("sort expects an object")?
+        .iter()
+        .map(|(field, dir)| (field.clone(), if dir.as_i64().unwrap_or(1) >= 0 { SortOrder::Asc } else { SortOrder::Desc }))
+        .collect();
+    documents.sort_by(|a, b| compare_by(a, b, &keys));
+    Ok(())
+}
+
+fn project(documents: Vec<Value>, fields: &[String]) -> Vec<Value> {
+    if fields.is_empty() {
+        return documents;
+    }
+    documents.into_iter().map(|doc| {
+        let mut projected = json!({});
+        for field in fields {
+            if let Some(value) = doc.get(field) {
+                projected[field] = value.clone();
+            }
+        }
+        projected
+    }).collect()
+}
+
+// Resolve "$field" and "$nested.field" references; anything else is a literal
+fn resolve(doc: &Value, expr: &Value) -> Value {
+    match expr {
+        Value::String(s) if s.starts_with('$') => doc.get(&s[1..])
+            .or_else(|| s[1..].split('.').try_fold(doc, |value, part| value.get(part)))
+            .cloned()
+            .unwrap_or(Value::Null),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), resolve(doc, v))).collect()),
+        other => other.clone(),
+    }
+}
+
+fn matching_documents(db: &InMemoryDB, collection: &str, filter: &Value) -> Result<Vec<Value>, String> {
+    let collection = db.collection_arc(collection)
+        .ok_or_else(|| format!("Collection '{}' not found.", collection))?;
+    let mut query = collection.select("*");
+    if let Some(expr) = parse_filter(filter)? {
+        query = query.where_expr(expr);
+    }
+    Ok(query.execute()?)
+}
+
+fn string_list(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::Array(items)) => items.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect(),
+        Some(Value::String(s)) => s.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect(),
+        Some(Value::Object(map)) => map.iter()
+            .filter(|(_, v)| v.as_i64().unwrap_or(0) != 0 || v.as_bool().unwrap_or(false))
+            .map(|(k, _)| k.clone())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn run_lookup(db: &InMemoryDB, documents: Vec<Value>, spec: &Value) -> Result<Vec<Value>, String> {
+    let from = spec.get("from").and_then(|v| v.as_str()).ok_or("$lookup requires 'from'")?;
+    let local_field = spec.get("localField").and_then(|v| v.as_str()).ok_or("$lookup requires 'localField'")?;
+    let foreign_field = spec.get("foreignField").and_then(|v| v.as_str()).ok_or("$lookup requires 'foreignField'")?;
+    let as_field = spec.get("as").and_then(|v| v.as_str()).ok_or("$lookup requires 'as'")?;
+    lookup(db, documents, from, local_field, foreign_field, as_field)
+}
+
+pub(crate) fn lookup(db: &InMemoryDB, documents: Vec<Value>, from: &str, local_field: &str, foreign_field: &str, as_field: &str) -> Result<Vec<Value>, String> {
+    let foreign_docs = matching_documents(db, from, &Value::Null)?;
+
+    Ok(documents.into_iter().map(|mut doc| {
+        let local = doc.get(local_field).cloned().unwrap_or(Value::Null);
+        let matches: Vec<Value> = foreign_docs.iter()
+            .filter(|f| f.get(foreign_field).unwrap_or(&Value::Null) == &local)
+            .cloned()
+            .collect();
+        doc[as_field] = Value::Array(matches);
+        doc
+    }).collect())
+}
+
+// "$field", or {"path": "$field", "preserveNullAndEmptyArrays": true}
+fn run_unwind(documents: Vec<Value>, spec: &Value) -> Result<Vec<Value>, String> {
+    let path = spec.as_str().or_else(|| spec.get("path").and_then(|p| p.as_str()));
+    let field = path.and_then(|p| p.strip_prefix('$')).ok_or("$unwind expects a field path like \"$items\"")?;
+    let preserve_empty = spec.get("preserveNullAndEmptyArrays").and_then(|v| v.as_bool()).unwrap_or(false);
+    Ok(documents.into_iter().flat_map(|doc| unwind(doc, field, preserve_empty)).collect())
+}
+
+pub(crate) fn run_group(documents: Vec<Value>, spec: &Value) -> Result<Vec<Value>, String> {
+    let spec = spec.as_object().ok_or("$group expects an object")?;
+    let id_expr = spec.get("_id").cloned().unwrap_or(Value::Null);
+
+    // Keep groups in first-seen order
+    let mut groups: Vec<(Value, Vec<Value>)> = Vec::new();
+    for doc in documents {
+        let key = resolve(&doc, &id_expr);
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, members)) => members.push(doc),
+            None => groups.push((key, vec![doc])),
+        }
+    }
+
+    let mut results = Vec::new();
+    for (key, members) in groups {
+        let mut out = Map::new();
+        out.insert("_id".to_string(), key);
+        for (name, accumulator) in spec {
+            if name == "_id" {
+                continue;
+            }
+            let (op, operand) = accumulator.as_object()
+                .and_then(|m| m.iter().next())
+                .ok_or_else(|| format!("Accumulator for '{}' must be an object like {{\"$sum\": \"$field\"}}", name))?;
+            let values: Vec<Value> = members.iter().map(|doc| resolve(doc, operand)).collect();
+            let numbers: Vec<f64> = values.iter().filter_map(|v| v.as_f64()).collect();
+            let value = match op.as_str() {
+                "$sum" => json!(numbers.iter().sum::<f64>()),
+                "$count" => json!(members.len()),
+                "$avg" => if numbers.is_empty() { Value::Null } else { json!(numbers.iter().sum::<f64>() / numbers.len() as f64) },
+                "$min" => values.iter().filter(|v| !v.is_null()).min_by(|a, b| compare_values(a, b)).cloned().unwrap_or(Value::Null),
+                "$max" => values.iter().filter(|v| !v.is_null()).max_by(|a, b| compare_values(a, b)).cloned().unwrap_or(Value::Null),
+                "$push" => Value::Array(values),
+                "$first" => values.first().cloned().unwrap_or(Value::Null),
+                "$last" => values.last().cloned().unwrap_or(Value::Null),
+                other => return Err(format!("Unknown accumulator '{}'", other)),
+            };
+            out.insert(name.clone(), value);
+        }
+        results.push(Value::Object(out));
+    }
+    Ok(results)
+}
+
+pub fn run_pipeline(db: &InMemoryDB, collection: &str, pipeline: &[Value]) -> Result<Vec<Value>, String> {
+    let mut documents = matching_documents(db, collection, &Value::Null)?;
+    for stage in pipeline {
+        let (name, spec) = stage.as_object()
+            .filter(|m| m.len() == 1)
+            .and_then(|m| m.iter().next())
+            .ok_or("Each pipeline stage must be an object with a single operator")?;
+        documents = match name.as_str() {
+            "$match" => match parse_filter(spec)? {
+                Some(expr) => documents.into_iter().filter(|doc| expr.matches(doc)).collect(),
+                None => documents,
+            },
+            "$lookup" => run_lookup(db, documents, spec)?,
+            "$unwind" => run_unwind(documents, spec)?,
+            "$group" => run_group(documents, spec)?,
+            "$project" => project(documents, &string_list(Some(spec))),
+            "$sort" => {
+                sort_documents(&mut documents, spec)?;
+                documents
+            }
+            "$skip" => documents.into_iter().skip(spec.as_u64().ok_or("$skip expects a number")? as usize).collect(),
+            "$limit" => documents.into_iter().take(spec.as_u64().ok_or("$limit expects a number")? as usize).collect(),
+            other => return Err(format!("Unknown pipeline stage '{}'", other)),
+        };
+    }
+    Ok(documents)
+}
+
+// Execute a single command document against the database
+pub fn execute(db: &InMemoryDB, command: &Value) -> Result<Value, String> {
+    let name = |key: &str| command.get(key).and_then(|v| v.as_str());
+    let empty = Value::Null;
+    let filter = command.get("filter").unwrap_or(&empty);
+
+    if let Some(collection) = name("find") {
+        let mut documents = matching_documents(db, collection, filter)?;
+        if let Some(sort) = command.get("sort") {
+            sort_documents(&mut documents, sort)?;
+        }
+        let skip = command.get("skip").and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+        let limit = command.get("limit").and_then(|v| v.as_u64()).map(|l| l as usize).unwrap_or(usize::MAX);
+        let documents: Vec<Value> = documents.into_iter().skip(skip).take(limit).collect();
+        let documents = project(documents, &string_list(command.get("projection")));
+        Ok(json!({"ok": 1, "documents": documents}))
+    } else if let Some(collection) = name("count") {
+        let count = matching_documents(db, collection, filter)?.len();
+        Ok(json!({"ok": 1, "count": count}))
+    } else if let Some(collection) = name("aggregate") {
+        let pipeline = command.get("pipeline").and_then(|p| p.as_array()).ok_or("aggregate requires a 'pipeline' array")?;
+        let documents = run_pipeline(db, collection, pipeline)?;
+        Ok(json!({"ok": 1, "documents": documents}))
+    } else if let Some(collection) = name("insert") {
+        let target = db.collection_arc(collection)
+            .ok_or_else(|| format!("Collection '{}' not found.", collection))?;
+        let documents = command.get("documents").and_then(|d| d.as_array()).ok_or("insert requires a 'documents' array")?;
+        let mut ids = Vec::new();
+        for document in documents {
+            if let crate::db::OperationResult::Inserted { id, .. } = target.insert(document.clone(), None)? {
+                ids.push(id);
+            }
+        }
+        Ok(json!({"ok": 1, "inserted": ids}))
+    } else if let Some(view) = name("view") {
+        let documents = db.execute_view(view)?;
+        Ok(json!({"ok": 1, "documents": documents}))
+    } else {
+        Err("Unknown command; expected one of find, count, aggregate, insert, view".to_string())
+    }
+}
@@ -1,15 +1,201 @@
+use serde::Serialize;
 use serde_json::{Value, json};
 use uuid::Uuid;
-use std::{convert::Into, sync::Arc};
+use std::{convert::Into, sync::Arc, time::{Duration, Instant, SystemTime}};
 use crate::db::Collection;
-use std::collections::HashMap;
+use crate::metrics::OperationKind;
+use crate::replay::RecordedOp;
+use crate::subscription::AdminEvent;
+use crate::slowlog::SlowQuery;
+use crate::filter::{compare_by, compare_values, CompareFn, FilterExpr, QuerySpec, SortOrder};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
 use crate::db::DocumentEntry;
 use dashmap::DashMap;
+use crate::error::EmemError;
+use crate::access::Permission;
+use crate::pipeline::unwind;
+use crate::trigram::like_literals;
+use crate::hll::HyperLogLog;
 
 type Filter = Box<dyn Fn(&Value) -> bool + Send + Sync>;
-pub type QueryResult = Result<Vec<Value>, String>;
+pub type QueryResult = Result<Vec<Value>, EmemError>;
 pub type SuccessCallback = Box<dyn Fn(&Vec<Value>) + Send + Sync>;
-pub type ErrorCallback = Box<dyn Fn(&String) + Send + Sync>;
+pub type ErrorCallback = Box<dyn Fn(&EmemError) + Send + Sync>;
+pub type QueryArena = bumpalo::Bump;
+pub type ArenaVec<'arena, T> = bumpalo::collections::Vec<'arena, T>;
+
+// A JSON document copied into a QueryArena: its strings, arrays and objects are arena memory, so
+// resetting the arena frees a whole result set at once
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArenaValue<'arena> {
+    Null,
+    Bool(bool),
+    Number(serde_json::Number),
+    String(&'arena str),
+    Array(&'arena [ArenaValue<'arena>]),
+    Object(&'arena [(&'arena str, ArenaValue<'arena>)]),
+}
+
+impl<'arena> ArenaValue<'arena> {
+    pub fn from_value(arena: &'arena QueryArena, value: &Value) -> Self {
+        match value {
+            Value::Null => ArenaValue::Null,
+            Value::Bool(b) => ArenaValue::Bool(*b),
+            Value::Number(n) => ArenaValue::Number(n.clone()),
+            Value::String(s) => ArenaValue::String(arena.alloc_str(s)),
+            Value::Array(items) => {
+                ArenaValue::Array(arena.alloc_slice_fill_iter(items.iter().map(|item| ArenaValue::from_value(arena, item))))
+            }
+            Value::Object(map) => ArenaValue::Object(
+                arena.alloc_slice_fill_iter(map.iter().map(|(k, v)| (&*arena.alloc_str(k), ArenaValue::from_value(arena, v)))),
+            ),
+        }
+    }
+
+    // Copies the value back out of the arena
+    pub fn to_value(&self) -> Value {
+        match self {
+            ArenaValue::Null => Value::Null,
+            ArenaValue::Bool(b) => Value::Bool(*b),
+            ArenaValue::Number(n) => Value::Number(n.clone()),
+            ArenaValue::String(s) => Value::String(s.to_string()),
+            ArenaValue::Array(items) => Value::Array(items.iter().map(ArenaValue::to_value).collect()),
+            ArenaValue::Object(fields) => Value::Object(fields.iter().map(|(k, v)| (k.to_string(), v.to_value())).collect()),
+        }
+    }
+
+    pub fn get(&self, field: &str) -> Option<&ArenaValue<'arena>> {
+        match self {
+            ArenaValue::Object(fields) => fields.iter().find(|(k, _)| *k == field).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'arena str> {
+        match self {
+            ArenaValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for ArenaValue<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeMap, SerializeSeq};
+        match self {
+            ArenaValue::Null => serializer.serialize_unit(),
+            ArenaValue::Bool(b) => serializer.serialize_bool(*b),
+            ArenaValue::Number(n) => n.serialize(serializer),
+            ArenaValue::String(s) => serializer.serialize_str(s),
+            ArenaValue::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items.iter() {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            ArenaValue::Object(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (k, v) in fields.iter() {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+// Value computed per row over its window (see QueryBuilder::window); results go to `output`
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowFn {
+    // 1, 2, 3, ... in window order
+    RowNumber { output: String },
+    // Position of the first row with the same ordering value: 1, 1, 3, ...
+    Rank { output: String },
+    // Rank without gaps: 1, 1, 2, ...
+    DenseRank { output: String },
+    // `field` of the row `offset` rows earlier in the partition, null when there is none
+    Lag { field: String, offset: usize, output: String },
+    // `field` of the row `offset` rows later in the partition, null when there is none
+    Lead { field: String, offset: usize, output: String },
+}
+
+impl WindowFn {
+    fn output(&self) -> &str {
+        match self {
+            WindowFn::RowNumber { output }
+            | WindowFn::Rank { output }
+            | WindowFn::DenseRank { output }
+            | WindowFn::Lag { output, .. }
+            | WindowFn::Lead { output, .. } => output,
+        }
+    }
+}
+
+// Rows are grouped by `partition_by` (one partition when empty) and ordered by `order_by` within
+// each partition
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    pub partition_by: Vec<String>,
+    pub order_by: Vec<(String, SortOrder)>,
+    pub functions: Vec<WindowFn>,
+}
+
+impl Window {
+    fn apply(&self, rows: &mut [Value]) {
+        let mut partitions: Vec<(Vec<Value>, Vec<usize>)> = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            let key: Vec<Value> = self.partition_by.iter().map(|field| row.get(field).cloned().unwrap_or(Value::Null)).collect();
+            match partitions.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, members)) => members.push(i),
+                None => partitions.push((key, vec![i])),
+            }
+        }
+        for (_, mut members) in partitions {
+            members.sort_by(|&a, &b| compare_by(&rows[a], &rows[b], &self.order_by));
+            let (mut rank, mut dense_rank) = (0, 0);
+            let mut computed: Vec<Vec<(String, Value)>> = Vec::with_capacity(members.len());
+            for (position, &i) in members.iter().enumerate() {
+                let tied = position > 0 && compare_by(&rows[members[position - 1]], &rows[i], &self.order_by) == Ordering::Equal;
+                if !tied {
+                    rank = position + 1;
+                    dense_rank += 1;
+                }
+                let neighbour = |field: &str, at: Option<usize>| at
+                    .and_then(|at| members.get(at))
+                    .and_then(|&j| rows[j].get(field).cloned())
+                    .unwrap_or(Value::Null);
+                computed.push(self.functions.iter().map(|function| {
+                    let value = match function {
+                        WindowFn::RowNumber { .. } => json!(position + 1),
+                        WindowFn::Rank { .. } => json!(rank),
+                        WindowFn::DenseRank { .. } => json!(dense_rank),
+                        WindowFn::Lag { field, offset, .. } => neighbour(field, position.checked_sub(*offset)),
+                        WindowFn::Lead { field, offset, .. } => neighbour(field, Some(position + offset)),
+                    };
+                    (function.output().to_string(), value)
+                }).collect());
+            }
+            for (&i, values) in members.iter().zip(computed) {
+                if let Some(row) = rows[i].as_object_mut() {
+                    row.extend(values);
+                }
+            }
+        }
+    }
+}
+
+// Which unmatched rows a join keeps: Left keeps every source document, Right every target document,
+// Full both, Inner neither. Missing sides are filled with nulls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JoinType {
+    Inner,
+    #[default]
+    Left,
+    Right,
+    Full,
+}
 
 pub struct JoinBuilder {
     src_collection: Arc<Collection>,
@@ -19,6 +205,11 @@ pub struct JoinBuilder {
     filters: Vec<Filter>,
     selected_fields: Vec<String>,
     map_function: Option<Box<dyn Fn(Value) -> Value + Send + Sync>>,
+    join_type: JoinType,
+    collect_as: Option<String>,
+    prefix: String,
+    aliases: HashMap<String, String>,
+    nest_as: Option<String>,
 }
 
 impl JoinBuilder {
@@ -31,6 +222,11 @@ impl JoinBuilder {
             filters: vec![],
             selected_fields: vec![],
             map_function: None,
+            join_type: JoinType::default(),
+            collect_as: None,
+            prefix: "joined_".to_string(),
+            aliases: HashMap::new(),
+            nest_as: None,
         }
     }
 
@@ -65,34 +261,112 @@ impl JoinBuilder {
         self
     }
 
-    pub fn execute(self) -> Vec<Value> {
-        let src_docs = self.src_collection.select("*").execute().unwrap();
-        let mut results = Vec::new();
-    
-        for src_doc in src_docs {
-            let mut joined_doc = src_doc.clone();
-    
-            if let Some(src_value) = src_doc.get(&self.src_key) {
-                let src_value_str = src_value.to_string();
-                let mut query = self.target_collection.select("*");
-                let target_docs = query
-                    .eq(&self.target_key, src_value_str) // Remove the & before src_value_str
-                    .execute()
-                    .unwrap();
-    
-                if let Some(target_doc) = target_docs.first() {
-                    for (key, value) in target_doc.as_object().unwrap() {
-                        if self.selected_fields.is_empty() || self.selected_fields.contains(key) {
-                            joined_doc[format!("joined_{}", key)] = value.clone();
-                        }
+    pub fn join_type(mut self, join_type: JoinType) -> Self {
+        self.join_type = join_type;
+        self
+    }
+
+    // Prefix for target fields on the result row (default "joined_")
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    // Output name for one target field; takes precedence over the prefix
+    pub fn alias(mut self, field: &str, new_name: &str) -> Self {
+        self.aliases.insert(field.to_string(), new_name.to_string());
+        self
+    }
+
+    // Put the target fields in a nested object under `field` (null when nothing matched)
+    pub fn nest_as(mut self, field: &str) -> Self {
+        self.nest_as = Some(field.to_string());
+        self
+    }
+
+    // Attach every matching target document as an array under `field` instead of one row per match
+    pub fn collect_as(mut self, field: &str) -> Self {
+        self.collect_as = Some(field.to_string());
+        self
+    }
+
+    // collect_as using the target collection's name
+    pub fn many(self) -> Self {
+        let field = self.target_collection.collection_name.clone();
+        self.collect_as(&field)
+    }
+
+    // Fails when reading either collection fails, e.g. past its max_scan or timeout
+    pub fn execute(self) -> Result<Vec<Value>, EmemError> {
+        let started = Instant::now();
+        let src_docs = self.src_collection.select("*").execute()?;
+        let target_docs = self.target_collection.select("*").execute()?;
+
+        // Fields null-filled when a row has no partner on one side
+        let target_fields = if self.selected_fields.is_empty() {
+            field_names(&target_docs)
+        } else {
+            self.selected_fields.clone()
+        };
+        let src_fields = match self.join_type {
+            JoinType::Right | JoinType::Full => field_names(&src_docs),
+            JoinType::Inner | JoinType::Left => vec![],
+        };
+
+        let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, target_doc) in target_docs.iter().enumerate() {
+            if let Some(key) = target_doc.get(&self.target_key).and_then(join_key) {
+                index.entry(key).or_default().push(i);
+            }
+        }
+
+        let mut rows = Vec::new();
+        let mut target_matched = vec![false; target_docs.len()];
+        for src_doc in &src_docs {
+            let matches: Vec<usize> = src_doc.get(&self.src_key)
+                .and_then(join_key)
+                .and_then(|key| index.get(&key).cloned())
+                .unwrap_or_default();
+            for &i in &matches {
+                target_matched[i] = true;
+            }
+
+            if matches.is_empty() && !matches!(self.join_type, JoinType::Left | JoinType::Full) {
+                continue;
+            }
+            match &self.collect_as {
+                Some(field) => {
+                    let collected = matches.iter().map(|&i| self.project_target(&target_docs[i])).collect();
+                    let mut joined_doc = src_doc.clone();
+                    joined_doc[field.as_str()] = Value::Array(collected);
+                    rows.push(joined_doc);
+                }
+                None if matches.is_empty() => rows.push(self.combine(Some(src_doc), None, &src_fields, &target_fields)),
+                None => {
+                    for &i in &matches {
+                        rows.push(self.combine(Some(src_doc), Some(&target_docs[i]), &src_fields, &target_fields));
                     }
-                } else {
-                    for field in &self.selected_fields {
-                        joined_doc[format!("joined_{}", field)] = Value::Null;
+                }
+            }
+        }
+        if matches!(self.join_type, JoinType::Right | JoinType::Full) {
+            for (target_doc, matched) in target_docs.iter().zip(target_matched) {
+                if matched {
+                    continue;
+                }
+                match &self.collect_as {
+                    Some(field) => {
+                        let mut joined_doc: Value = Value::Object(src_fields.iter().map(|f| (f.clone(), Value::Null)).collect());
+                        joined_doc[field.as_str()] = json!([self.project_target(target_doc)]);
+                        rows.push(joined_doc);
                     }
+                    None => rows.push(self.combine(None, Some(target_doc), &src_fields, &target_fields)),
                 }
             }
-    
+        }
+
+        let mut results = Vec::new();
+        for mut joined_doc in rows {
             if self.filters.iter().all(|filter| filter(&joined_doc)) {
                 if let Some(map_fn) = &self.map_function {
                     joined_doc = map_fn(joined_doc);
@@ -100,8 +374,441 @@ impl JoinBuilder {
                 results.push(joined_doc);
             }
         }
-    
-        results
+
+        self.src_collection.metrics.record(OperationKind::Join, started.elapsed());
+        Ok(results)
+    }
+
+    // Target document restricted to the selected fields, with aliases applied
+    fn project_target(&self, target_doc: &Value) -> Value {
+        let fields: Vec<(String, Value)> = if self.selected_fields.is_empty() {
+            target_doc.as_object().map_or(vec![], |map| map.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+        } else {
+            self.selected_fields.iter()
+                .map(|field| (field.clone(), target_doc.get(field).cloned().unwrap_or(Value::Null)))
+                .collect()
+        };
+        Value::Object(fields.into_iter()
+            .map(|(field, value)| (self.aliases.get(&field).cloned().unwrap_or(field), value))
+            .collect())
+    }
+
+    // Name of a target field on a flat result row: its alias, or the prefix followed by the field
+    fn output_name(&self, field: &str) -> String {
+        match self.aliases.get(field) {
+            Some(alias) => alias.clone(),
+            None => format!("{}{}", self.prefix, field),
+        }
+    }
+
+    // One output row: source fields as-is, target fields prefixed (or nested), missing sides null-filled
+    fn combine(&self, src_doc: Option<&Value>, target_doc: Option<&Value>, src_fields: &[String], target_fields: &[String]) -> Value {
+        let mut joined_doc = match src_doc {
+            Some(src_doc) => src_doc.clone(),
+            None => Value::Object(src_fields.iter().map(|f| (f.clone(), Value::Null)).collect()),
+        };
+        if let Some(nest_as) = &self.nest_as {
+            joined_doc[nest_as.as_str()] = target_doc.map_or(Value::Null, |doc| self.project_target(doc));
+            return joined_doc;
+        }
+        match target_doc {
+            Some(target_doc) => {
+                let fields: Vec<String> = if self.selected_fields.is_empty() {
+                    target_doc.as_object().map_or(vec![], |map| map.keys().cloned().collect())
+                } else {
+                    self.selected_fields.clone()
+                };
+                for field in fields {
+                    joined_doc[self.output_name(&field)] = target_doc.get(&field).cloned().unwrap_or(Value::Null);
+                }
+            }
+            None => {
+                for field in target_fields {
+                    joined_doc[self.output_name(field)] = Value::Null;
+                }
+            }
+        }
+        joined_doc
+    }
+}
+
+// Normalized form of a join key. Numbers compare by value (1 == 1.0) and numeric strings match
+// the number they spell ("42" == 42); null never matches anything.
+fn join_key(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Bool(b) => Some(format!("b:{}", b)),
+        Value::Number(n) => Some(number_key(n)),
+        Value::String(s) => {
+            let text = s.trim();
+            if let Some(key) = integer_key(text) {
+                return Some(key);
+            }
+            match text.parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+                Some(n) => Some(number_key(&n)),
+                None => Some(format!("s:{}", s)),
+            }
+        }
+        other => Some(format!("j:{}", other)),
+    }
+}
+
+// Integer strings compare exactly, digit for digit; going through f64 would match
+// "1234567890123456789" with "1234567890123456788"
+fn integer_key(text: &str) -> Option<String> {
+    let digits = text.strip_prefix(['-', '+']).unwrap_or(text);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Some("n:0".to_string());
+    }
+    Some(if text.starts_with('-') { format!("n:-{}", digits) } else { format!("n:{}", digits) })
+}
+
+fn number_key(n: &serde_json::Number) -> String {
+    if let Some(i) = n.as_i64() {
+        return format!("n:{}", i);
+    }
+    if let Some(u) = n.as_u64() {
+        return format!("n:{}", u);
+    }
+    match n.as_f64() {
+        Some(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => format!("n:{}", f as i64),
+        Some(f) => format!("n:{}", f),
+        None => format!("n:{}", n),
+    }
+}
+
+// Keys looked up as the iterator advances, or entries copied up front for snapshot reads
+enum IterSource {
+    Keys(std::vec::IntoIter<String>),
+    Entries(std::vec::IntoIter<DocumentEntry>),
+}
+
+// Lazy query results returned by QueryBuilder::iter. Yields an error (and then stops) when the
+// scan hits max_scan or the timeout.
+pub struct QueryIter {
+    query: QueryBuilder,
+    source: IterSource,
+    // Rows of the current document not handed out yet (joins can produce several)
+    pending: VecDeque<Value>,
+    scanned: usize,
+    emitted: usize,
+    started: Instant,
+    now: SystemTime,
+    done: bool,
+}
+
+impl QueryIter {
+    // The next stored document that passes the filters, as its joined rows
+    fn next_rows(&mut self) -> Result<Option<Vec<Value>>, EmemError> {
+        loop {
+            let entry = match &mut self.source {
+                IterSource::Keys(keys) => match keys.next() {
+                    Some(key) => self.query.collection.documents.get(&key).map(|r| r.value().clone()),
+                    None => return Ok(None),
+                },
+                IterSource::Entries(entries) => match entries.next() {
+                    Some(entry) => Some(entry),
+                    None => return Ok(None),
+                },
+            };
+            // Removed since the keys were collected
+            let Some(entry) = entry else { continue };
+            self.scanned += 1;
+            self.query.check_scan(self.scanned, self.started)?;
+            if !self.query.visible(&entry, self.now) {
+                continue;
+            }
+            if let Some(rows) = self.query.matching_rows(Value::clone(&entry.value))? {
+                entry.touch();
+                return Ok(Some(rows));
+            }
+        }
+    }
+}
+
+impl Iterator for QueryIter {
+    type Item = Result<Value, EmemError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                self.emitted += 1;
+                // Rows of paged queries were projected when they were collected
+                return Some(Ok(match self.query.is_paged() {
+                    true => row,
+                    false => self.query.project(row),
+                }));
+            }
+            if self.done {
+                return None;
+            }
+            match self.next_rows() {
+                Ok(Some(rows)) => self.pending.extend(rows),
+                Ok(None) => {
+                    self.done = true;
+                    self.query.finish(self.started, self.emitted);
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+// Position after the last document of a page. Pass it back to paginate for the next page, or
+// hand clients the opaque `token()` and rebuild it with `Cursor::from_token`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    // Sort key values of the last document, ending with its collection key
+    values: Vec<Value>,
+}
+
+impl Cursor {
+    pub fn token(&self) -> String {
+        Value::Array(self.values.clone()).to_string().bytes().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn from_token(token: &str) -> Result<Self, EmemError> {
+        let invalid = || EmemError::InvalidQuery(format!("Invalid cursor token '{}'", token));
+        if !token.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let bytes = (0..token.len()).step_by(2)
+            .map(|i| token.get(i..i + 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        match serde_json::from_slice(&bytes) {
+            Ok(Value::Array(values)) => Ok(Cursor { values }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub documents: Vec<Value>,
+    // None on the last page
+    pub next: Option<Cursor>,
+}
+
+// An order_by key with its custom comparator resolved (None for the default ordering)
+type SortKey = (String, SortOrder, Option<CompareFn>);
+
+fn sort_key(doc: &Value, keys: &[SortKey]) -> Vec<Value> {
+    keys.iter().map(|(field, _, _)| doc.get(field).cloned().unwrap_or(Value::Null)).collect()
+}
+
+fn compare_keys(a: &[Value], b: &[Value], keys: &[SortKey]) -> Ordering {
+    a.iter().zip(b).zip(keys)
+        .map(|((x, y), key)| compare_field(x, y, key))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+fn compare_documents(a: &Value, b: &Value, keys: &[SortKey]) -> Ordering {
+    keys.iter()
+        .map(|key| compare_field(a.get(&key.0).unwrap_or(&Value::Null), b.get(&key.0).unwrap_or(&Value::Null), key))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+fn compare_field(x: &Value, y: &Value, (_, order, comparator): &SortKey) -> Ordering {
+    let ordering = match comparator {
+        Some(compare) => compare(x, y),
+        None => compare_values(x, y),
+    };
+    match order {
+        SortOrder::Asc => ordering,
+        SortOrder::Desc => ordering.reverse(),
+    }
+}
+
+// xorshift64* seeded from a random UUID; good enough for sampling, not for anything secret
+struct SampleRng(u64);
+
+impl SampleRng {
+    fn new() -> Self {
+        let seed = Uuid::new_v4().as_u128();
+        SampleRng((seed as u64 ^ (seed >> 64) as u64) | 1)
+    }
+
+    // Uniform in 0..bound
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) % bound
+    }
+}
+
+// Split a projection like "name as user_name" into the source field and the output name
+fn parse_projection(spec: &str) -> (&str, &str) {
+    let spec = spec.trim();
+    let lower = spec.to_ascii_lowercase();
+    match lower.find(" as ") {
+        Some(at) => (spec[..at].trim(), spec[at + 4..].trim()),
+        None => (spec, spec),
+    }
+}
+
+// Value of a projected field. A dotted path walks nested objects (and array indexes) unless the
+// document has a top-level field with that exact name.
+fn value_at_path<'a>(doc: &'a Value, path: &str) -> Option<&'a Value> {
+    if let Some(value) = doc.get(path) {
+        return Some(value);
+    }
+    if !path.contains('.') {
+        return None;
+    }
+    path.split('.').try_fold(doc, |current, segment| match current {
+        Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => current.get(segment),
+    })
+}
+
+// Set `path` in `doc`, creating the intermediate objects
+fn insert_at_path(doc: &mut Value, path: &str, value: Value) {
+    let mut current = doc;
+    let mut segments = path.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        if segments.peek().is_none() {
+            current[segment] = value;
+            return;
+        }
+        if !current.get(segment).map_or(false, Value::is_object) {
+            current[segment] = json!({});
+        }
+        current = &mut current[segment];
+    }
+}
+
+fn remove_at_path(doc: &mut Value, path: &str) {
+    if doc.as_object_mut().and_then(|map| map.remove(path)).is_some() {
+        return;
+    }
+    if let Some((parent, last)) = path.rsplit_once('.') {
+        let parent = path_pointer(parent);
+        if let Some(map) = doc.pointer_mut(&parent).and_then(Value::as_object_mut) {
+            map.remove(last);
+        }
+    }
+}
+
+fn path_pointer(path: &str) -> String {
+    format!("/{}", path.replace('~', "~0").replace('/', "~1").replace('.', "/"))
+}
+
+// Every top-level field seen across the documents, in first-seen order
+fn field_names(docs: &[Value]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for doc in docs {
+        if let Some(map) = doc.as_object() {
+            for key in map.keys() {
+                if !names.contains(key) {
+                    names.push(key.clone());
+                }
+            }
+        }
+    }
+    names
+}
+
+// How QueryBuilder::count_distinct counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DistinctMode {
+    // Every distinct value is kept in a set: exact, but memory grows with the count
+    #[default]
+    Exact,
+    // HyperLogLog estimate, usually within a few percent, in 4 KiB however many values there are
+    Approximate,
+}
+
+// How a query reads the collection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadConcern {
+    // Scan the live map; concurrent writes may or may not be visible
+    #[default]
+    Local,
+    // Copy the documents first so the whole query sees one point-in-time view
+    Snapshot,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScanStrategy {
+    FullScan,
+    KeyLookup { key: String },
+    // Only documents holding every trigram of a contains or like filter on `field`
+    TrigramIndex { field: String },
+}
+
+// Returned by QueryBuilder::explain
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryPlan {
+    pub collection: String,
+    pub strategy: ScanStrategy,
+    pub filter: Option<FilterExpr>,
+    // Closure filters can't be inspected; they run after the structured filter
+    pub closure_filters: usize,
+    pub documents_to_scan: usize,
+    pub fields: Vec<String>,
+    pub joins: usize,
+    #[serde(skip)]
+    pub options: QueryOptions,
+}
+
+// Limits and defaults applied to queries; a Collection handle carries a default set
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    pub max_scan: Option<usize>,
+    pub timeout: Option<Duration>,
+    pub include_expired: bool,
+    // Return documents of soft-delete collections that carry `_deleted_at`
+    pub include_deleted: bool,
+    pub read_concern: ReadConcern,
+    // Filter on all shards of the collection at once (see Collection::parallel_scan)
+    pub parallel: bool,
+}
+
+impl QueryOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_scan(mut self, max_scan: usize) -> Self {
+        self.max_scan = Some(max_scan);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn include_expired(mut self, include_expired: bool) -> Self {
+        self.include_expired = include_expired;
+        self
+    }
+
+    pub fn include_deleted(mut self, include_deleted: bool) -> Self {
+        self.include_deleted = include_deleted;
+        self
+    }
+
+    pub fn read_concern(mut self, read_concern: ReadConcern) -> Self {
+        self.read_concern = read_concern;
+        self
+    }
+
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
     }
 }
 
@@ -109,20 +816,42 @@ pub struct QueryBuilder {
     collection: Arc<Collection>,
     filters: Vec<Filter>,
     selected_fields: Vec<String>,
+    flatten_separator: Option<String>,
     success_callback: Option<SuccessCallback>,
     error_callback: Option<ErrorCallback>,
-    joins: Vec<(String, String, Arc<Collection>, Arc<Collection>, Box<dyn Fn(String, String, Arc<Collection>, Arc<Collection>, Filter) -> Vec<Value> + Send + Sync>)>,
+    joins: Vec<(String, String, Arc<Collection>, Arc<Collection>, Box<dyn Fn(String, String, Arc<Collection>, Arc<Collection>, Filter) -> Result<Vec<Value>, EmemError> + Send + Sync>)>,
+    exprs: Vec<FilterExpr>,
+    opaque_filters: usize,
+    options: QueryOptions,
+    order_by: Vec<(String, SortOrder)>,
+    // Custom comparator name per order_by field (see order_by_with)
+    order_comparators: HashMap<String, String>,
+    offset: usize,
+    limit: Option<usize>,
+    windows: Vec<Window>,
+    // Array fields expanded into one row per element (see unwind)
+    unwind: Vec<String>,
 }
 
 impl QueryBuilder {
     pub fn new(collection: Arc<Collection>) -> Self {
         QueryBuilder {
+            options: collection.query_options.clone(),
             collection,
             filters: vec![],
             selected_fields: vec![],
+            flatten_separator: None,
             success_callback: None,
             error_callback: None,
             joins: vec![],
+            exprs: vec![],
+            opaque_filters: 0,
+            order_by: vec![],
+            order_comparators: HashMap::new(),
+            offset: 0,
+            limit: None,
+            windows: vec![],
+            unwind: vec![],
         }
     }
 
@@ -131,80 +860,409 @@ impl QueryBuilder {
         self
     }
 
-    pub fn in_<T: Into<Value> + Clone>(mut self, key: &str, values: Vec<T>) -> Self {
-        let values: Vec<Value> = values.into_iter().map(|v| v.into()).collect();
-        let key = key.to_string(); // Convert &str to String
-        self.filters.push(Box::new(move |doc| {
-            if let Some(val) = doc.get(&key) {
-                values.iter().any(|v| v == val)
-            } else {
-                false
-            }
-        }));
+    // Return `field` as `new_name`, like "field as new_name" in select. Adds the field to the
+    // projection if it isn't selected yet; on a "*" query the other fields are kept.
+    pub fn alias(mut self, field: &str, new_name: &str) -> Self {
+        let renamed = format!("{} as {}", field, new_name);
+        if self.selected_fields.is_empty() {
+            self.selected_fields.push("*".to_string());
+        }
+        match self.selected_fields.iter_mut().find(|spec| parse_projection(spec).0 == field) {
+            Some(spec) => *spec = renamed,
+            None => self.selected_fields.push(renamed),
+        }
         self
     }
-    pub fn eq<T: Into<Value>>(mut self, key: &str, value: T) -> Self {
-        let value = value.into();
-        let key = key.to_string();
-        self.filters.push(Box::new(move |doc| {
-            doc.get(&key).map_or(false, |val| val == &value)
-        }));
+
+    // Drop the comma-separated `fields` (dot paths allowed) from the results, keeping every
+    // other field; stored in the projection as "-field"
+    pub fn except(mut self, fields: &str) -> Self {
+        if self.selected_fields.is_empty() {
+            self.selected_fields.push("*".to_string());
+        }
+        self.selected_fields.extend(fields.split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(|field| format!("-{}", field)));
         self
     }
-    
-    pub fn neq<T: Into<Value>>(mut self, key: &str, value: T) -> Self {
-        let value = value.into();
-        let key = key.to_string();
-        self.filters.push(Box::new(move |doc| {
-            doc.get(&key).map_or(true, |val| val != &value)
-        }));
+
+    // Write projected dot paths like "address.city" as flat keys joined by `separator` (e.g.
+    // "address_city" for "_") instead of rebuilding the nested objects
+    pub fn flatten(mut self, separator: &str) -> Self {
+        self.flatten_separator = Some(separator.to_string());
         self
     }
 
-    pub fn gte<T: Into<f64>>(mut self, key: &str, value: T) -> Self {
-        let value_f64: f64 = value.into();
-        let key = key.to_string();
-        self.filters.push(Box::new(move |doc| {
-            doc.get(&key)
-                .and_then(|val| val.as_f64())
-                .map_or(false, |doc_val| doc_val >= value_f64)
-        }));
+    // Sort the results by `field`; later calls add tie-breaking keys
+    pub fn order_by(mut self, field: &str, order: SortOrder) -> Self {
+        self.order_by.push((field.to_string(), order));
         self
     }
 
-    pub fn gt<T: Into<f64>>(mut self, key: &str, value: T) -> Self {
-        let value_f64: f64 = value.into();
-        let key = key.to_string();
-        self.filters.push(Box::new(move |doc| {
-            doc.get(&key)
-                .and_then(|val| val.as_f64())
-                .map_or(false, |doc_val| doc_val > value_f64)
-        }));
+    // Sort ascending by `field` using a comparator registered with
+    // InMemoryDB::register_comparator; the query fails if no comparator has that name
+    pub fn order_by_with(mut self, field: &str, comparator: &str) -> Self {
+        self.order_by.push((field.to_string(), SortOrder::Asc));
+        self.order_comparators.insert(field.to_string(), comparator.to_string());
         self
     }
 
-    pub fn lte<T: Into<f64>>(mut self, key: &str, value: T) -> Self {
-        let value_f64: f64 = value.into();
-        let key = key.to_string();
-        self.filters.push(Box::new(move |doc| {
-            doc.get(&key)
-                .and_then(|val| val.as_f64())
-                .map_or(false, |doc_val| doc_val <= value_f64)
-        }));
+    // order_by keys with their custom comparators looked up
+    fn sort_keys(&self) -> Result<Vec<SortKey>, EmemError> {
+        self.order_by.iter().map(|(field, order)| {
+            let comparator = match self.order_comparators.get(field) {
+                Some(name) => Some(self.collection.parent_db.comparator(name)
+                    .ok_or_else(|| EmemError::InvalidQuery(format!("Comparator '{}' is not registered", name)))?),
+                None => None,
+            };
+            Ok((field.clone(), *order, comparator))
+        }).collect()
+    }
+
+    // Skip the first `offset` results
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
         self
     }
 
-    pub fn lt<T: Into<f64>>(mut self, key: &str, value: T) -> Self {
-        let value_f64: f64 = value.into();
-        let key = key.to_string();
-        self.filters.push(Box::new(move |doc| {
-            doc.get(&key)
-                .and_then(|val| val.as_f64())
-                .map_or(false, |doc_val| doc_val < value_f64)
-        }));
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
         self
     }
 
+    fn is_paged(&self) -> bool {
+        !self.order_by.is_empty() || self.offset > 0 || self.limit.is_some() || !self.windows.is_empty()
+    }
+
+    // One result row per element of the array in `field`, with the other fields copied from the
+    // document; documents with a missing or empty array produce no rows. Applied after joins,
+    // before ordering, so offset and limit count rows.
+    pub fn unwind(mut self, field: &str) -> Self {
+        self.unwind.push(field.to_string());
+        self
+    }
+
+    // Start a window for the window functions that follow, e.g.
+    // .window("region", "score", SortOrder::Desc).rank("rank").lag("score", 1, "previous_score").
+    // `partition_by` is a comma-separated field list; empty puts every row in one partition.
+    // Window functions see every match, before offset and limit apply.
+    pub fn window(mut self, partition_by: &str, order_by: &str, order: SortOrder) -> Self {
+        self.windows.push(Window {
+            partition_by: partition_by.split(',').map(str::trim).filter(|f| !f.is_empty()).map(str::to_string).collect(),
+            order_by: vec![(order_by.to_string(), order)],
+            functions: vec![],
+        });
+        self
+    }
+
+    // Add a function to the last window (a window over all rows in stored order if none was
+    // started); its output is added to an explicit field selection
+    pub fn window_fn(mut self, function: WindowFn) -> Self {
+        if self.windows.is_empty() {
+            self.windows.push(Window { partition_by: vec![], order_by: vec![], functions: vec![] });
+        }
+        if !self.selected_fields.is_empty() && !self.selected_fields.iter().any(|spec| spec == "*" || spec == function.output()) {
+            self.selected_fields.push(function.output().to_string());
+        }
+        if let Some(window) = self.windows.last_mut() {
+            window.functions.push(function);
+        }
+        self
+    }
+
+    pub fn row_number(self, output: &str) -> Self {
+        self.window_fn(WindowFn::RowNumber { output: output.to_string() })
+    }
+
+    pub fn rank(self, output: &str) -> Self {
+        self.window_fn(WindowFn::Rank { output: output.to_string() })
+    }
+
+    pub fn dense_rank(self, output: &str) -> Self {
+        self.window_fn(WindowFn::DenseRank { output: output.to_string() })
+    }
+
+    pub fn lag(self, field: &str, offset: usize, output: &str) -> Self {
+        self.window_fn(WindowFn::Lag { field: field.to_string(), offset, output: output.to_string() })
+    }
+
+    pub fn lead(self, field: &str, offset: usize, output: &str) -> Self {
+        self.window_fn(WindowFn::Lead { field: field.to_string(), offset, output: output.to_string() })
+    }
+
+    // Apply order_by, offset and limit to rows that have not been projected yet
+    fn page<T, F: Fn(&T) -> &Value>(&self, mut rows: Vec<T>, value: F) -> Result<Vec<T>, EmemError> {
+        if !self.order_by.is_empty() {
+            let keys = self.sort_keys()?;
+            rows.sort_by(|a, b| compare_documents(value(a), value(b), &keys));
+        }
+        Ok(rows.into_iter().skip(self.offset).take(self.limit.unwrap_or(usize::MAX)).collect())
+    }
+
+    // Override the handle's default query options for this query
+    pub fn options(mut self, options: QueryOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn max_scan(mut self, max_scan: usize) -> Self {
+        self.options.max_scan = Some(max_scan);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn include_expired(mut self, include_expired: bool) -> Self {
+        self.options.include_expired = include_expired;
+        self
+    }
+
+    pub fn include_deleted(mut self, include_deleted: bool) -> Self {
+        self.options.include_deleted = include_deleted;
+        self
+    }
+
+    pub fn read_concern(mut self, read_concern: ReadConcern) -> Self {
+        self.options.read_concern = read_concern;
+        self
+    }
+
+    // Filter the shards of the collection in parallel; pays off on large, sharded collections
+    pub fn parallel(mut self) -> Self {
+        self.options.parallel = true;
+        self
+    }
+
+    pub fn in_<T: Into<Value> + Clone>(self, key: &str, values: Vec<T>) -> Self {
+        self.where_expr(FilterExpr::in_(key, values))
+    }
+
+    pub fn eq<T: Into<Value>>(self, key: &str, value: T) -> Self {
+        self.where_expr(FilterExpr::eq(key, value))
+    }
+
+    pub fn neq<T: Into<Value>>(self, key: &str, value: T) -> Self {
+        self.where_expr(FilterExpr::neq(key, value))
+    }
+
+    pub fn gte<T: Into<f64>>(self, key: &str, value: T) -> Self {
+        self.where_expr(FilterExpr::gte(key, value))
+    }
+
+    pub fn gt<T: Into<f64>>(self, key: &str, value: T) -> Self {
+        self.where_expr(FilterExpr::gt(key, value))
+    }
+
+    pub fn lte<T: Into<f64>>(self, key: &str, value: T) -> Self {
+        self.where_expr(FilterExpr::lte(key, value))
+    }
+
+    pub fn lt<T: Into<f64>>(self, key: &str, value: T) -> Self {
+        self.where_expr(FilterExpr::lt(key, value))
+    }
+
+    // Keyset pagination: documents whose `field` sorts strictly after `last_seen`. Together with
+    // order_by(field, Asc) and limit, pass the last value of the previous page to get the next
+    // one; unlike offset, pages don't shift when documents are inserted or removed.
+    pub fn after<T: Into<Value>>(self, field: &str, last_seen: T) -> Self {
+        self.where_expr(FilterExpr::after(field, last_seen))
+    }
+
+    // Counterpart of after for descending orderings (or paging backwards)
+    pub fn before<T: Into<Value>>(self, field: &str, first_seen: T) -> Self {
+        self.where_expr(FilterExpr::before(field, first_seen))
+    }
+
+    // Date/time filters on fields holding RFC 3339 strings or epoch milliseconds, compared
+    // chronologically (so "2024-01-01T09:00:00+09:00" equals "2024-01-01T00:00:00Z"). Fail when
+    // the given date can't be parsed.
+    pub fn after_date<T: Into<Value>>(self, field: &str, date: T) -> Result<Self, EmemError> {
+        Ok(self.where_expr(FilterExpr::after_date(field, date).map_err(EmemError::InvalidQuery)?))
+    }
+
+    pub fn before_date<T: Into<Value>>(self, field: &str, date: T) -> Result<Self, EmemError> {
+        Ok(self.where_expr(FilterExpr::before_date(field, date).map_err(EmemError::InvalidQuery)?))
+    }
+
+    // Both bounds included
+    pub fn date_between<T: Into<Value>, U: Into<Value>>(self, field: &str, from: T, to: U) -> Result<Self, EmemError> {
+        Ok(self.where_expr(FilterExpr::date_between(field, from, to).map_err(EmemError::InvalidQuery)?))
+    }
+
+    // Match documents satisfying any of the given expressions
+    pub fn or(self, exprs: Vec<FilterExpr>) -> Self {
+        self.where_expr(FilterExpr::or(exprs))
+    }
+
+    // Add a structured filter expression (e.g. one loaded from a saved query)
+    pub fn where_expr(mut self, expr: FilterExpr) -> Self {
+        self.exprs.push(expr);
+        self
+    }
+
+    // Full-text match: `field` contains every token of `query`, both analyzed with the field's
+    // analyzer (see CollectionBuilder::text_analyzer)
+    pub fn text_search(self, field: &str, query: &str) -> Self {
+        let analyzer = self.collection.analyzer(field);
+        self.where_expr(FilterExpr::text(field, query, analyzer))
+    }
+
+    // Substring match on a string field; uses the field's trigram index when it has one
+    pub fn contains(self, field: &str, value: &str) -> Self {
+        self.where_expr(FilterExpr::contains(field, value))
+    }
+
+    // SQL LIKE pattern match, e.g. .like("email", "%@example.com"); uses the field's trigram index
+    // when it has one and the pattern has three or more characters between wildcards
+    pub fn like(self, field: &str, pattern: &str) -> Self {
+        self.where_expr(FilterExpr::like(field, pattern))
+    }
+
+    // Typo-tolerant match: `field`, or one of its words, is within `max_distance` insertions,
+    // deletions, substitutions or adjacent transpositions of `value` ("Jhon" finds "John Smith")
+    pub fn fuzzy(self, field: &str, value: &str, max_distance: usize) -> Self {
+        self.where_expr(FilterExpr::fuzzy(field, value, max_distance))
+    }
+
+    // Phonetic match on Soundex codes, e.g. .sounds_like("surname", "Smith") also finds "Smyth"
+    // and "Schmidt"
+    pub fn sounds_like(self, field: &str, value: &str) -> Self {
+        self.where_expr(FilterExpr::sounds_like(field, value))
+    }
+
+    // A top-level equality on a value missing from the field's bloom filter means nothing matches
+    fn ruled_out(&self) -> bool {
+        let mut exprs: Vec<&FilterExpr> = self.exprs.iter().collect();
+        while let Some(expr) = exprs.pop() {
+            match expr {
+                FilterExpr::Eq { field, value } if !self.collection.may_contain(field, value) => return true,
+                FilterExpr::And { exprs: nested } => exprs.extend(nested.iter()),
+                _ => {}
+            }
+        }
+        false
+    }
+
+    // Keys of the documents that can pass the top-level contains and like filters on fields with
+    // a trigram index, and the field of the first such filter; None when no filter can use one
+    fn index_candidates(&self) -> Option<(String, Vec<String>)> {
+        if self.collection.trigram_indexes.is_empty() {
+            return None;
+        }
+        let mut exprs: Vec<&FilterExpr> = self.exprs.iter().collect();
+        let mut narrowed: Option<(String, HashSet<String>)> = None;
+        while let Some(expr) = exprs.pop() {
+            let (field, literals) = match expr {
+                FilterExpr::And { exprs: nested } => {
+                    exprs.extend(nested.iter());
+                    continue;
+                }
+                FilterExpr::Contains { field, value } => (field, vec![value.clone()]),
+                FilterExpr::Like { field, pattern } => (field, like_literals(pattern)),
+                _ => continue,
+            };
+            let Some(ids) = self.collection.trigram_index(field).and_then(|index| index.candidates(&literals)) else { continue };
+            narrowed = Some(match narrowed {
+                Some((first, mut candidates)) => {
+                    candidates.retain(|id| ids.contains(id));
+                    (first, candidates)
+                }
+                None => (field.clone(), ids),
+            });
+        }
+        narrowed.map(|(field, ids)| (field, ids.into_iter().collect()))
+    }
+
+    // Structured filters first, then closure filters
+    fn passes(&self, doc: &Value) -> bool {
+        self.exprs.iter().all(|expr| expr.matches(doc)) && self.filters.iter().all(|filter| filter(doc))
+    }
+
+    // A top-level equality on the key field lets the query read one document instead of scanning
+    fn key_lookup(&self) -> Option<String> {
+        let key_field = self.collection.key_field.as_deref()?;
+        let mut candidates: Vec<&FilterExpr> = self.exprs.iter().collect();
+        while let Some(expr) = candidates.pop() {
+            match expr {
+                FilterExpr::Eq { field, value: Value::String(id) } if field == key_field => return Some(id.clone()),
+                FilterExpr::And { exprs } => candidates.extend(exprs.iter()),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    pub(crate) fn source(&self) -> &Arc<Collection> {
+        &self.collection
+    }
+
+    pub(crate) fn join_targets(&self) -> Vec<Arc<Collection>> {
+        self.joins.iter().map(|join| join.3.clone()).collect()
+    }
+
+    // Rows this query produces for a single document (empty when it is filtered out)
+    pub(crate) fn evaluate(&self, document: Value) -> Result<Vec<Value>, EmemError> {
+        let mut rows = Vec::new();
+        self.emit_matches(document, &mut |row| rows.push(row))?;
+        Ok(rows)
+    }
+
+    // How the query would run, without running it
+    pub fn explain(&self) -> QueryPlan {
+        let candidates = self.index_candidates();
+        let strategy = match (self.key_lookup(), &candidates) {
+            (Some(key), _) => ScanStrategy::KeyLookup { key },
+            (None, Some((field, _))) => ScanStrategy::TrigramIndex { field: field.clone() },
+            (None, None) => ScanStrategy::FullScan,
+        };
+        let documents_to_scan = match &strategy {
+            ScanStrategy::KeyLookup { key } => usize::from(self.collection.documents.contains_key(key)),
+            ScanStrategy::TrigramIndex { .. } => candidates.map_or(0, |(_, ids)| ids.len()),
+            ScanStrategy::FullScan => self.collection.documents.len(),
+        };
+        QueryPlan {
+            collection: self.collection.collection_name.clone(),
+            strategy,
+            filter: match self.exprs.len() {
+                0 => None,
+                1 => Some(self.exprs[0].clone()),
+                _ => Some(FilterExpr::and(self.exprs.clone())),
+            },
+            closure_filters: self.opaque_filters,
+            documents_to_scan,
+            fields: self.selected_fields.clone(),
+            joins: self.joins.len(),
+            options: self.options.clone(),
+        }
+    }
+
+    // The combined filter of this query; fails if a closure filter was added since closures can't be serialized
+    pub fn filter_expr(&self) -> Result<Option<FilterExpr>, EmemError> {
+        if self.opaque_filters > 0 {
+            return Err(EmemError::InvalidQuery(format!("Query contains {} closure filter(s) that cannot be serialized", self.opaque_filters)));
+        }
+        Ok(match self.exprs.len() {
+            0 => None,
+            1 => Some(self.exprs[0].clone()),
+            _ => Some(FilterExpr::and(self.exprs.clone())),
+        })
+    }
+
+    pub fn to_spec(&self) -> Result<QuerySpec, EmemError> {
+        Ok(QuerySpec {
+            collection: self.collection.collection_name.clone(),
+            fields: self.selected_fields.clone(),
+            filter: self.filter_expr()?,
+            order_by: self.order_by.clone(),
+            offset: self.offset,
+            limit: self.limit,
+        })
+    }
+
     pub fn on_success<F>(mut self, callback: F) -> Self
     where
         F: Fn(&Vec<Value>) + Send + Sync + 'static,
@@ -215,7 +1273,7 @@ impl QueryBuilder {
 
     pub fn on_fail<F>(mut self, callback: F) -> Self
     where
-        F: Fn(&String) + Send + Sync + 'static,
+        F: Fn(&EmemError) + Send + Sync + 'static,
     {
         self.error_callback = Some(Box::new(callback));
         self
@@ -238,6 +1296,7 @@ impl QueryBuilder {
         F: Fn(&Value) -> bool + Send + Sync + 'static,
     {
         self.filters.push(Box::new(filter));
+        self.opaque_filters += 1;
         self
     }
 
@@ -260,54 +1319,566 @@ impl QueryBuilder {
         self
     }
 
-    pub fn execute(self) -> Result<Vec<Value>, String> {
+    pub fn execute(self) -> Result<Vec<Value>, EmemError> {
+        self.record();
+        let started = Instant::now();
         let mut results = vec![];
+        self.for_each_match(|doc| {
+            results.push(doc);
+            true
+        })?;
+
+        self.finish(started, results.len());
+        Ok(results)
+    }
+
+    // execute on tokio's blocking thread pool, so a long scan doesn't hold up the runtime's
+    // worker threads. Must be awaited inside a tokio runtime.
+    #[cfg(feature = "async")]
+    pub async fn execute_async(self) -> Result<Vec<Value>, EmemError> {
+        crate::asyncdb::blocking(move || self.execute()).await
+    }
+
+    // Copies the results into a bump arena, documents included, so the whole result set is freed
+    // by resetting the arena. Matches are gathered as shared references first (see execute_ref),
+    // so the arena holds the only copies and its buffer is sized to the matches.
+    pub fn execute_in<'arena>(self, arena: &'arena QueryArena) -> Result<ArenaVec<'arena, ArenaValue<'arena>>, EmemError> {
+        let rows = self.execute_ref()?;
+        let mut results = ArenaVec::with_capacity_in(rows.len(), arena);
+        results.extend(rows.iter().map(|doc| ArenaValue::from_value(arena, doc)));
+        Ok(results)
+    }
+
+    // The first matching document (in order_by order when one is set). Without an ordering the
+    // scan stops at the first match.
+    pub fn first(self) -> Result<Option<Value>, EmemError> {
+        self.record();
+        let started = Instant::now();
+        let mut first = None;
+        self.for_each_match(|doc| {
+            first = Some(doc);
+            false
+        })?;
+        self.finish(started, usize::from(first.is_some()));
+        Ok(first)
+    }
+
+    // The only matching document, None when nothing matches. Fails when more than one document
+    // matches; the scan stops at the second match.
+    pub fn single(self) -> Result<Option<Value>, EmemError> {
+        self.record();
+        let started = Instant::now();
+        let mut matches = Vec::with_capacity(2);
+        self.for_each_match(|doc| {
+            matches.push(doc);
+            matches.len() < 2
+        })?;
+        self.finish(started, matches.len());
+        if matches.len() > 1 {
+            return Err(EmemError::MultipleMatches(self.collection.collection_name.clone()));
+        }
+        Ok(matches.pop())
+    }
 
-        for doc in self.collection.documents.iter() {
-            let doc_value = doc.value().value.clone();
-
-            if self.filters.iter().all(|filter| filter(&doc_value)) {
-                let mut joined_docs = vec![doc_value];
-                for (src_key, target_key, src_collection, target_collection, join_function) in &self.joins {
-                    let new_joined_docs = join_function(
-                        src_key.to_string(),
-                        target_key.to_string(),
-                        Arc::clone(src_collection),
-                        Arc::clone(target_collection),
-                        Box::new(|_| true)
-                    );
-                    
-                    joined_docs = joined_docs.into_iter().flat_map(|existing_doc| {
-                        if new_joined_docs.is_empty() {
-                            vec![existing_doc]
-                        } else {
-                            new_joined_docs.iter().map(|joined_doc| {
-                                let mut combined_doc = existing_doc.clone();
-                                for (k, v) in joined_doc.as_object().unwrap() {
-                                    combined_doc[k] = v.clone();
-                                }
-                                combined_doc
-                            }).collect()
+    // Whether any document matches. Stops at the first match and checks the stored documents in
+    // place, so nothing is copied or projected.
+    pub fn exists(self) -> Result<bool, EmemError> {
+        self.record();
+        let started = Instant::now();
+        let mut found = false;
+        self.scan(|entry| {
+            found = self.passes(&entry.value);
+            !found
+        })?;
+        self.finish(started, usize::from(found));
+        Ok(found)
+    }
+
+    // Number of distinct values of `field` among the matching documents; documents without the
+    // field aren't counted. Like exists, only the filters apply (no joins or unwinding).
+    pub fn count_distinct(self, field: &str) -> Result<u64, EmemError> {
+        self.count_distinct_with(field, DistinctMode::Exact)
+    }
+
+    pub fn count_distinct_with(self, field: &str, mode: DistinctMode) -> Result<u64, EmemError> {
+        self.record();
+        let started = Instant::now();
+        let mut exact = HashSet::new();
+        let mut sketch = HyperLogLog::new();
+        self.scan(|entry| {
+            if self.passes(&entry.value) {
+                if let Some(value) = value_at_path(&entry.value, field) {
+                    match mode {
+                        DistinctMode::Exact => {
+                            exact.insert(value.to_string());
                         }
-                    }).collect();
+                        DistinctMode::Approximate => sketch.insert_value(value),
+                    }
                 }
+            }
+            true
+        })?;
+        let count = match mode {
+            DistinctMode::Exact => exact.len() as u64,
+            DistinctMode::Approximate => sketch.estimate(),
+        };
+        self.finish(started, 1);
+        Ok(count)
+    }
 
-                if !self.selected_fields.is_empty() {
-                    joined_docs = joined_docs.into_iter().map(|doc| {
-                        let mut selected_doc = json!({});
-                        for field in &self.selected_fields {
-                            if let Some(value) = doc.get(field) {
-                                selected_doc[field] = value.clone();
-                            }
-                        }
-                        selected_doc
-                    }).collect();
+    // Up to `n` matching documents chosen uniformly at random. Uses reservoir sampling over the
+    // matches, so only `n` documents are kept in memory however many match.
+    pub fn sample(self, n: usize) -> Result<Vec<Value>, EmemError> {
+        self.record();
+        let started = Instant::now();
+        let mut rng = SampleRng::new();
+        let mut reservoir = Vec::with_capacity(n.min(self.collection.documents.len()));
+        let mut seen = 0u64;
+        self.for_each_match(|doc| {
+            seen += 1;
+            if reservoir.len() < n {
+                reservoir.push(doc);
+            } else {
+                let slot = rng.below(seen) as usize;
+                if slot < n {
+                    reservoir[slot] = doc;
                 }
+            }
+            true
+        })?;
+        self.finish(started, reservoir.len());
+        Ok(reservoir)
+    }
+
+    // One page of results in order_by order, starting after `cursor` (the first page for None).
+    // The collection key breaks ties, so the cursor pins an exact position: documents inserted
+    // or removed between requests never shift later pages. offset and limit are ignored.
+    pub fn paginate(self, cursor: Option<Cursor>, page_size: usize) -> Result<Page, EmemError> {
+        self.record();
+        let started = Instant::now();
+        let mut orders = self.sort_keys()?;
+        if let Some(key_field) = &self.collection.key_field {
+            if !orders.iter().any(|(field, _, _)| field == key_field) {
+                orders.push((key_field.clone(), SortOrder::Asc, None));
+            }
+        }
+        if let Some(cursor) = &cursor {
+            if cursor.values.len() != orders.len() {
+                return Err(EmemError::InvalidQuery("Cursor does not belong to this query's ordering.".to_string()));
+            }
+        }
+
+        let mut rows = Vec::new();
+        self.try_scan(|entry| {
+            if let Some(matched) = self.matching_rows(Value::clone(&entry.value))? {
+                rows.extend(matched.into_iter().map(|row| (sort_key(&row, &orders), row)));
+            }
+            Ok(true)
+        })?;
+        if let Some(cursor) = &cursor {
+            rows.retain(|(key, _)| compare_keys(key, &cursor.values, &orders) == Ordering::Greater);
+        }
+        rows.sort_by(|(a, _), (b, _)| compare_keys(a, b, &orders));
+
+        let has_more = rows.len() > page_size;
+        rows.truncate(page_size);
+        let next = if has_more {
+            rows.last().map(|(key, _)| Cursor { values: key.clone() })
+        } else {
+            None
+        };
+        let documents: Vec<Value> = rows.into_iter().map(|(_, row)| self.project(row)).collect();
+        self.finish(started, documents.len());
+        Ok(Page { documents, next })
+    }
+
+    // Iterate over the results lazily: documents are read, filtered and projected one at a time
+    // as the iterator advances, so stopping early (e.g. with take) skips the rest of the scan.
+    // Only the keys are collected up front; documents removed before they are reached are
+    // skipped. Sorted or paged queries still have to read every match first.
+    pub fn iter(self) -> Result<QueryIter, EmemError> {
+        self.collection.authorize(Permission::Read)?;
+        self.record();
+        let started = Instant::now();
+        let mut pending = VecDeque::new();
+        let source = if self.is_paged() {
+            self.for_each_match(|doc| {
+                pending.push_back(doc);
+                true
+            })?;
+            IterSource::Keys(Vec::new().into_iter())
+        } else if self.ruled_out() {
+            IterSource::Keys(Vec::new().into_iter())
+        } else if let Some(key) = self.key_lookup() {
+            IterSource::Keys(vec![key].into_iter())
+        } else if let Some((_, ids)) = self.index_candidates() {
+            IterSource::Keys(ids.into_iter())
+        } else {
+            match self.options.read_concern {
+                ReadConcern::Local => IterSource::Keys(self.collection.documents.iter()
+                    .map(|r| r.key().clone())
+                    .collect::<Vec<_>>()
+                    .into_iter()),
+                ReadConcern::Snapshot => IterSource::Entries(self.collection.documents.iter()
+                    .map(|r| r.value().clone())
+                    .collect::<Vec<_>>()
+                    .into_iter()),
+            }
+        };
+        Ok(QueryIter {
+            query: self,
+            source,
+            pending,
+            scanned: 0,
+            emitted: 0,
+            started,
+            now: SystemTime::now(),
+            done: false,
+        })
+    }
+
+    // Like single, but a query matching nothing fails with DocumentNotFound
+    pub fn one(self) -> Result<Value, EmemError> {
+        self.single()?.ok_or(EmemError::DocumentNotFound)
+    }
+
+    fn finish(&self, started: Instant, results: usize) {
+        let elapsed = started.elapsed();
+        self.collection.metrics.record(OperationKind::Query, elapsed);
+        let db = &self.collection.parent_db;
+        if let Some(threshold) = db.config().slow_query_threshold {
+            if elapsed > threshold {
+                db.record_slow_query(SlowQuery {
+                    collection: self.collection.collection_name.clone(),
+                    elapsed,
+                    threshold,
+                    results,
+                    plan: self.explain(),
+                    at: SystemTime::now(),
+                });
+                db.emit_admin_event(AdminEvent::SlowQuery {
+                    collection: self.collection.collection_name.clone(),
+                    elapsed,
+                    threshold,
+                });
+            }
+        }
+    }
 
-                results.extend(joined_docs);
+    // Like execute, but hands out the stored documents without copying them. Documents are only
+    // copied when a projection or join has to build a new one.
+    pub fn execute_ref(self) -> Result<Vec<Arc<Value>>, EmemError> {
+        self.record();
+        let started = Instant::now();
+        let mut results = vec![];
+        if self.selected_fields.is_empty() && self.joins.is_empty() && self.unwind.is_empty() && self.windows.is_empty() {
+            self.scan(|entry| {
+                if self.passes(&entry.value) {
+                    entry.touch();
+                    results.push(entry.value.clone());
+                }
+                true
+            })?;
+            if self.is_paged() {
+                results = self.page(results, |doc| doc.as_ref())?;
             }
+        } else {
+            self.for_each_match(|doc| {
+                results.push(Arc::new(doc));
+                true
+            })?;
         }
 
+        self.finish(started, results.len());
         Ok(results)
     }
-}
\ No newline at end of file
+
+    fn record(&self) {
+        self.collection.parent_db.record(RecordedOp::Query {
+            collection: self.collection.collection_name.clone(),
+            fields: self.selected_fields.clone(),
+            filter: self.filter_expr().ok().flatten(),
+        });
+    }
+
+    // Hand the projected results to `emit` until it returns false
+    fn for_each_match<F: FnMut(Value) -> bool>(&self, mut emit: F) -> Result<(), EmemError> {
+        if !self.is_paged() {
+            return self.try_scan(|entry| match self.matching_rows(Value::clone(&entry.value))? {
+                Some(rows) => {
+                    entry.touch();
+                    Ok(rows.into_iter().all(|row| emit(self.project(row))))
+                }
+                None => Ok(true),
+            });
+        }
+
+        // Sorting needs every row, and the sort keys may not survive the projection
+        let mut rows = Vec::new();
+        self.try_scan(|entry| {
+            if let Some(matched) = self.matching_rows(Value::clone(&entry.value))? {
+                entry.touch();
+                rows.extend(matched);
+            }
+            Ok(true)
+        })?;
+        for window in &self.windows {
+            window.apply(&mut rows);
+        }
+        for row in self.page(rows, |doc| doc)? {
+            if !emit(self.project(row)) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Enforce max_scan and timeout after `scanned` entries
+    fn check_scan(&self, scanned: usize, started: Instant) -> Result<(), EmemError> {
+        if let Some(max_scan) = self.options.max_scan {
+            if scanned > max_scan {
+                return Err(EmemError::ScanLimitExceeded(max_scan));
+            }
+        }
+        if let Some(timeout) = self.options.timeout {
+            if scanned.is_multiple_of(64) && started.elapsed() > timeout {
+                return Err(EmemError::Timeout(timeout));
+            }
+        }
+        Ok(())
+    }
+
+    // Expired and soft-deleted entries are hidden unless the options include them
+    fn visible(&self, entry: &DocumentEntry, now: SystemTime) -> bool {
+        (self.options.include_expired || entry.expiration.is_none_or(|expiration| expiration > now))
+            && (self.options.include_deleted || !self.collection.is_deleted(&entry.value))
+    }
+
+    // Visit every live entry, enforcing max_scan, timeout, expiry and soft-delete visibility.
+    // Stops early once `on_entry` returns false.
+    fn scan<F: FnMut(&DocumentEntry) -> bool>(&self, mut on_entry: F) -> Result<(), EmemError> {
+        self.collection.authorize(Permission::Read)?;
+        let started = Instant::now();
+        let now = SystemTime::now();
+        let mut scanned = 0usize;
+
+        let mut visit = |entry: &DocumentEntry| -> Result<bool, EmemError> {
+            scanned += 1;
+            self.check_scan(scanned, started)?;
+            if !self.visible(entry, now) {
+                return Ok(true);
+            }
+            Ok(on_entry(entry))
+        };
+
+        if self.ruled_out() {
+            return Ok(());
+        }
+
+        if let Some(key) = self.key_lookup() {
+            let entry = self.collection.documents.get(&key).map(|r| r.value().clone());
+            if let Some(entry) = entry {
+                visit(&entry)?;
+            }
+            return Ok(());
+        }
+
+        if let Some((_, ids)) = self.index_candidates() {
+            for id in ids {
+                let entry = self.collection.documents.get(&id).map(|r| r.value().clone());
+                if let Some(entry) = entry {
+                    if !visit(&entry)? {
+                        break;
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        // Filtered on every shard at once; the matches are then visited in a single thread. Like a
+        // snapshot read, documents written meanwhile may be missed.
+        if self.options.parallel {
+            self.check_scan(self.collection.documents.len(), started)?;
+            let matches = self.collection.parallel_scan(|_, entry| {
+                (self.visible(entry, now) && self.passes(&entry.value)).then(|| entry.clone())
+            })?;
+            self.check_scan(0, started)?;
+            for entry in &matches {
+                if !on_entry(entry) {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        match self.options.read_concern {
+            ReadConcern::Local => {
+                for doc in self.collection.documents.iter() {
+                    if !visit(doc.value())? {
+                        break;
+                    }
+                }
+            }
+            ReadConcern::Snapshot => {
+                let snapshot: Vec<DocumentEntry> = self.collection.documents.iter().map(|r| r.value().clone()).collect();
+                for entry in &snapshot {
+                    if !visit(entry)? {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // scan with a fallible visitor; the first error stops the scan and is returned
+    fn try_scan<F: FnMut(&DocumentEntry) -> Result<bool, EmemError>>(&self, mut on_entry: F) -> Result<(), EmemError> {
+        let mut failed = None;
+        self.scan(|entry| match on_entry(entry) {
+            Ok(more) => more,
+            Err(error) => {
+                failed = Some(error);
+                false
+            }
+        })?;
+        failed.map_or(Ok(()), Err)
+    }
+
+    // Returns whether the document passed the filters
+    fn emit_matches<F: FnMut(Value)>(&self, doc_value: Value, emit: &mut F) -> Result<bool, EmemError> {
+        match self.matching_rows(doc_value)? {
+            Some(rows) => {
+                for row in rows {
+                    emit(self.project(row));
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn project(&self, doc: Value) -> Value {
+        if self.selected_fields.is_empty() {
+            return doc;
+        }
+        let projections: Vec<(&str, &str)> = self.selected_fields.iter().map(|spec| parse_projection(spec)).collect();
+        let mut selected_doc = json!({});
+        for &(field, output) in &projections {
+            if let Some(excluded) = field.strip_prefix('-') {
+                remove_at_path(&mut selected_doc, excluded);
+            } else if field == "*" {
+                // Every field, except the ones renamed by another projection
+                for (name, value) in doc.as_object().into_iter().flatten() {
+                    if !projections.iter().any(|&(source, renamed)| source == name && renamed != name) {
+                        selected_doc[name] = value.clone();
+                    }
+                }
+            } else if let Some(value) = value_at_path(&doc, field) {
+                // Aliased paths and plain fields are written under their output name
+                let nested = output == field && doc.get(field).is_none();
+                match &self.flatten_separator {
+                    Some(separator) if nested => selected_doc[field.replace('.', separator)] = value.clone(),
+                    None if nested => insert_at_path(&mut selected_doc, field, value.clone()),
+                    _ => selected_doc[output] = value.clone(),
+                }
+            }
+        }
+        selected_doc
+    }
+
+    // The filtered and joined (but not yet projected) rows for one document
+    fn matching_rows(&self, doc_value: Value) -> Result<Option<Vec<Value>>, EmemError> {
+        if !self.passes(&doc_value) {
+            return Ok(None);
+        }
+        let mut joined_docs = vec![doc_value];
+        for (src_key, target_key, src_collection, target_collection, join_function) in &self.joins {
+            let new_joined_docs = join_function(
+                src_key.to_string(),
+                target_key.to_string(),
+                Arc::clone(src_collection),
+                Arc::clone(target_collection),
+                Box::new(|_| true)
+            )?;
+            
+            joined_docs = joined_docs.into_iter().flat_map(|existing_doc| {
+                if new_joined_docs.is_empty() {
+                    vec![existing_doc]
+                } else {
+                    new_joined_docs.iter().map(|joined_doc| {
+                        let mut combined_doc = existing_doc.clone();
+                        for (k, v) in joined_doc.as_object().unwrap() {
+                            combined_doc[k] = v.clone();
+                        }
+                        combined_doc
+                    }).collect()
+                }
+            }).collect();
+        }
+        for field in &self.unwind {
+            joined_docs = joined_docs.into_iter().flat_map(|doc| unwind(doc, field, false)).collect();
+        }
+        Ok(Some(joined_docs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{KeyType, TTL};
+    use crate::db::InMemoryDB;
+
+    #[test]
+    fn join_reports_query_option_failures() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        let orders = db.create::<Value>().name("orders").key("id").key_type(KeyType::String).build();
+        for i in 0..5 {
+            users.insert(json!({"id": format!("u{}", i)}), None).unwrap();
+            orders.insert(json!({"id": format!("o{}", i), "uid": format!("u{}", i)}), None).unwrap();
+        }
+
+        let limited = users.with_query_options(QueryOptions::new().max_scan(2));
+        assert!(JoinBuilder::new(limited.clone(), orders.clone()).on("id", "uid").execute().is_err());
+        let limited_orders = orders.with_query_options(QueryOptions::new().max_scan(2));
+        assert!(users.select("*").eq("id", "u0").join("id", "uid", limited_orders, JoinBuilder::new).execute().is_err());
+        assert_eq!(JoinBuilder::new(users, orders).on("id", "uid").execute().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn execute_in_copies_documents_into_the_arena() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        for i in 0..10 {
+            users.insert(json!({"id": format!("u{}", i), "age": i, "tags": ["a", i]}), None).unwrap();
+        }
+
+        let arena = QueryArena::new();
+        let rows = users.select("*").gte("age", 7).execute_in(&arena).unwrap();
+        assert_eq!(rows.capacity(), 3);
+        let mut ids: Vec<&str> = rows.iter().map(|row| row.get("id").and_then(ArenaValue::as_str).unwrap()).collect();
+        ids.sort();
+        assert_eq!(ids, ["u7", "u8", "u9"]);
+        let row = rows.iter().find(|row| row.get("id").and_then(ArenaValue::as_str) == Some("u8")).unwrap();
+        assert_eq!(row.to_value(), json!({"id": "u8", "age": 8, "tags": ["a", 8]}));
+        assert_eq!(serde_json::to_value(row).unwrap(), row.to_value());
+    }
+
+    #[test]
+    fn integer_join_keys_compare_exactly() {
+        assert_ne!(join_key(&json!("1234567890123456789")), join_key(&json!("1234567890123456788")));
+        assert_eq!(join_key(&json!("1234567890123456789")), join_key(&json!(1234567890123456789u64)));
+        assert_eq!(join_key(&json!(" 42 ")), join_key(&json!(42.0)));
+        assert_eq!(join_key(&json!("-007")), join_key(&json!(-7)));
+        assert_eq!(join_key(&json!("1.5")), join_key(&json!(1.5)));
+
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        let orders = db.create::<Value>().name("orders").key("oid").key_type(KeyType::String).build();
+        users.insert(json!({"id": "1234567890123456789"}), None).unwrap();
+        orders.insert(json!({"oid": "o1", "uid": "1234567890123456788"}), None).unwrap();
+        let rows = JoinBuilder::new(users, orders).on("id", "uid").join_type(JoinType::Inner).execute().unwrap();
+        assert!(rows.is_empty());
+    }
+}
+
@@ -0,0 +1,379 @@
+// replay.rs
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::config::{IncrementKey, KeyType, TTL};
+use crate::db::{Collection, InMemoryDB};
+use crate::filter::FilterExpr;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum RecordedOp {
+    CreateCollection {
+        collection: String,
+        key_field: Option<String>,
+        key_type: KeyType,
+        unique_keys: Vec<String>,
+        #[serde(default)]
+        soft_delete: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        increment: Option<IncrementKey>,
+    },
+    DropCollection {
+        collection: String,
+    },
+    RenameCollection {
+        collection: String,
+        to: String,
+    },
+    CopyCollection {
+        collection: String,
+        to: String,
+    },
+    Truncate {
+        collection: String,
+    },
+    Insert {
+        collection: String,
+        document: Value,
+        ttl: Option<TTL>,
+    },
+    Upsert {
+        collection: String,
+        document: Value,
+        ttl: Option<TTL>,
+    },
+    Update {
+        collection: String,
+        document: Value,
+    },
+    Replace {
+        collection: String,
+        id: String,
+        document: Value,
+        #[serde(default)]
+        ttl: Option<TTL>,
+    },
+    Delete {
+        collection: String,
+        id: String,
+    },
+    Query {
+        collection: String,
+        fields: Vec<String>,
+        #[serde(default)]
+        filter: Option<FilterExpr>,
+    },
+}
+
+// One line of a recording file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEntry {
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub op: RecordedOp,
+}
+
+// When recorded operations reach the disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    // Buffered in memory and written when the buffer fills, on flush() and when recording stops
+    #[default]
+    Buffered,
+    // Written and fsynced with every operation
+    EveryWrite,
+    // Group commit: operations collect in the buffer and a background thread writes and fsyncs
+    // them together once per window. A crash loses at most the last window; call flush() where a
+    // write has to be durable before continuing.
+    GroupCommit(Duration),
+}
+
+// Appends every operation to a JSON-lines file, timestamped relative to the start of the recording
+#[derive(Debug)]
+pub struct OperationRecorder {
+    started: Instant,
+    writer: Mutex<BufWriter<File>>,
+    sync_mode: SyncMode,
+    // Operations appended since the last sync
+    pending: AtomicBool,
+    // First write or sync failure since the last flush(), which returns it
+    error: Mutex<Option<String>>,
+}
+
+impl OperationRecorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Self::open(path.as_ref(), SyncMode::Buffered)
+    }
+
+    // A recorder that syncs as `sync_mode` says; GroupCommit starts its commit thread, which
+    // stops when the recorder is dropped
+    pub fn create_with<P: AsRef<Path>>(path: P, sync_mode: SyncMode) -> Result<Arc<Self>, String> {
+        // Checked before the file is created so a bad window leaves an existing recording alone
+        if sync_mode == SyncMode::GroupCommit(Duration::ZERO) {
+            return Err("Group commit window must be greater than zero".to_string());
+        }
+        let recorder = Arc::new(Self::open(path.as_ref(), sync_mode)?);
+        if let SyncMode::GroupCommit(window) = sync_mode {
+            let weak = Arc::downgrade(&recorder);
+            thread::spawn(move || loop {
+                thread::sleep(window);
+                let Some(recorder) = Weak::upgrade(&weak) else { break };
+                if recorder.pending.swap(false, Ordering::SeqCst) {
+                    if let Err(e) = recorder.sync() {
+                        recorder.keep_error(format!("Failed to commit operation recording: {}", e));
+                    }
+                }
+            });
+        }
+        Ok(recorder)
+    }
+
+    fn open(path: &Path, sync_mode: SyncMode) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create recording file {}: {}", path.display(), e))?;
+        Ok(OperationRecorder {
+            started: Instant::now(),
+            writer: Mutex::new(BufWriter::new(file)),
+            sync_mode,
+            pending: AtomicBool::new(false),
+            error: Mutex::new(None),
+        })
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    pub fn record(&self, op: RecordedOp) {
+        let entry = RecordedEntry {
+            at_ms: self.started.elapsed().as_millis() as u64,
+            op,
+        };
+        let mut writer = self.writer.lock().unwrap();
+        let written = serde_json::to_string(&entry)
+            .map_err(|e| e.to_string())
+            .and_then(|line| writeln!(writer, "{}", line).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            self.keep_error(format!("Failed to write operation recording: {}", e));
+        }
+        match self.sync_mode {
+            SyncMode::Buffered => {}
+            SyncMode::EveryWrite => {
+                if let Err(e) = Self::sync_writer(&mut writer) {
+                    self.keep_error(format!("Failed to sync operation recording: {}", e));
+                }
+            }
+            SyncMode::GroupCommit(_) => self.pending.store(true, Ordering::SeqCst),
+        }
+    }
+
+    // Write out buffered operations and fsync the file: everything recorded before the call is
+    // durable once it returns. Also fails with the first error a recording or background commit
+    // hit since the previous flush.
+    pub fn flush(&self) -> Result<(), String> {
+        self.pending.store(false, Ordering::SeqCst);
+        let synced = self.sync();
+        match self.error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => synced,
+        }
+    }
+
+    fn keep_error(&self, error: String) {
+        self.error.lock().unwrap().get_or_insert(error);
+    }
+
+    fn sync(&self) -> Result<(), String> {
+        Self::sync_writer(&mut self.writer.lock().unwrap())
+    }
+
+    fn sync_writer(writer: &mut BufWriter<File>) -> Result<(), String> {
+        writer.flush().map_err(|e| e.to_string())?;
+        writer.get_ref().sync_data().map_err(|e| e.to_string())
+    }
+}
+
+impl Drop for OperationRecorder {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub applied: usize,
+    pub failed: Vec<(usize, String)>,
+    pub elapsed: Duration,
+}
+
+// Re-applies a recording against a (fresh) database
+pub struct Replayer {
+    entries: Vec<RecordedEntry>,
+    speed: Option<f64>,
+}
+
+impl Replayer {
+    pub fn new(entries: Vec<RecordedEntry>) -> Self {
+        Replayer { entries, speed: Some(1.0) }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let file = File::open(path.as_ref())
+            .map_err(|e| format!("Failed to open recording file {}: {}", path.as_ref().display(), e))?;
+        let mut entries = Vec::new();
+        for (line_no, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: RecordedEntry = serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid recording at line {}: {}", line_no + 1, e))?;
+            entries.push(entry);
+        }
+        Ok(Replayer::new(entries))
+    }
+
+    // Playback speed multiplier: 2.0 replays twice as fast as recorded
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = if speed > 0.0 { Some(speed) } else { None };
+        self
+    }
+
+    // Ignore recorded timing and apply operations back to back
+    pub fn max_speed(mut self) -> Self {
+        self.speed = None;
+        self
+    }
+
+    pub fn entries(&self) -> &[RecordedEntry] {
+        &self.entries
+    }
+
+    pub fn run(&self, db: &InMemoryDB) -> ReplayReport {
+        let started = Instant::now();
+        let mut collections: HashMap<String, Collection> = HashMap::new();
+        let mut report = ReplayReport::default();
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            if let Some(speed) = self.speed {
+                let due = Duration::from_secs_f64(entry.at_ms as f64 / 1000.0 / speed);
+                let elapsed = started.elapsed();
+                if due > elapsed {
+                    thread::sleep(due - elapsed);
+                }
+            }
+
+            match Self::apply(db, &mut collections, &entry.op) {
+                Ok(()) => report.applied += 1,
+                Err(e) => report.failed.push((index, e)),
+            }
+        }
+
+        report.elapsed = started.elapsed();
+        report
+    }
+
+    fn apply(db: &InMemoryDB, collections: &mut HashMap<String, Collection>, op: &RecordedOp) -> Result<(), String> {
+        if let RecordedOp::CreateCollection { collection, key_field, key_type, unique_keys, soft_delete, increment } = op {
+            let mut builder = db.create::<Value>()
+                .name(collection)
+                .key_type(key_type.clone())
+                .unique_keys(unique_keys.iter().map(|s| s.as_str()).collect());
+            if *soft_delete {
+                builder = builder.soft_delete();
+            }
+            if let Some(key_field) = key_field {
+                builder = builder.key(key_field);
+            }
+            if let Some(increment) = increment {
+                builder = builder.increment(increment.clone());
+            }
+            let created = builder.try_build()?;
+            collections.insert(collection.clone(), (*created).clone());
+            return Ok(());
+        }
+        match op {
+            RecordedOp::DropCollection { collection } => {
+                collections.remove(collection);
+                return Ok(db.drop_collection(collection)?);
+            }
+            RecordedOp::RenameCollection { collection, to } => {
+                db.rename_collection(collection, to)?;
+                collections.remove(collection);
+                collections.insert(to.clone(), db.get(to)?);
+                return Ok(());
+            }
+            RecordedOp::CopyCollection { collection, to } => {
+                let copy = db.copy_collection(collection, to)?;
+                collections.insert(to.clone(), (*copy).clone());
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let name = match op {
+            RecordedOp::Insert { collection, .. }
+            | RecordedOp::Upsert { collection, .. }
+            | RecordedOp::Update { collection, .. }
+            | RecordedOp::Replace { collection, .. }
+            | RecordedOp::Delete { collection, .. }
+            | RecordedOp::Truncate { collection }
+            | RecordedOp::Query { collection, .. } => collection,
+            RecordedOp::CreateCollection { .. }
+            | RecordedOp::DropCollection { .. }
+            | RecordedOp::RenameCollection { .. }
+            | RecordedOp::CopyCollection { .. } => unreachable!(),
+        };
+        let target = collections.get(name)
+            .ok_or_else(|| format!("Collection '{}' was not created in this replay", name))?;
+
+        let result = match op {
+            RecordedOp::Insert { document, ttl, .. } => target.insert(document.clone(), ttl.clone()).map(|_| ()),
+            RecordedOp::Upsert { document, ttl, .. } => target.upsert(document.clone(), ttl.clone()).map(|_| ()),
+            RecordedOp::Update { document, .. } => target.update(document.clone()).map(|_| ()),
+            RecordedOp::Replace { id, document, ttl, .. } => target.replace(id, document.clone(), ttl.clone()).map(|_| ()),
+            RecordedOp::Delete { id, .. } => target.delete(id).map(|_| ()),
+            RecordedOp::Truncate { .. } => target.truncate().map(|_| ()),
+            RecordedOp::Query { fields, filter, .. } => {
+                let mut query = target.select(&fields.join(","));
+                if let Some(filter) = filter {
+                    query = query.where_expr(filter.clone());
+                }
+                query.execute().map(|_| ())
+            }
+            RecordedOp::CreateCollection { .. }
+            | RecordedOp::DropCollection { .. }
+            | RecordedOp::RenameCollection { .. }
+            | RecordedOp::CopyCollection { .. } => unreachable!(),
+        };
+        Ok(result?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_group_commit_window_keeps_the_existing_recording() {
+        let path = std::env::temp_dir().join(format!("ememdb-replay-{}.jsonl", std::process::id()));
+        std::fs::write(&path, "kept\n").unwrap();
+        assert!(OperationRecorder::create_with(&path, SyncMode::GroupCommit(Duration::ZERO)).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "kept\n");
+
+        let recorder = OperationRecorder::create_with(&path, SyncMode::EveryWrite).unwrap();
+        recorder.keep_error("first".to_string());
+        recorder.keep_error("second".to_string());
+        assert_eq!(recorder.flush(), Err("first".to_string()));
+        assert_eq!(recorder.flush(), Ok(()));
+        drop(recorder);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+// replication.rs
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::SystemTime;
+use crate::changelog::{ChangeEvent, ChangeLog, DEFAULT_CHANGE_LOG_CAPACITY};
+use crate::config::KeyType;
+use crate::db::{DocumentEntry, InMemoryDB};
+use crate::error::EmemError;
+use crate::subscription::EventType;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicationStatus {
+    // Last primary sequence number applied on the replica
+    pub applied_seq: u64,
+    // Latest sequence number of the primary (None when following a remote feed)
+    pub primary_seq: Option<u64>,
+    // Events the replica is behind the primary
+    pub lag: u64,
+    pub last_applied_at: Option<SystemTime>,
+    pub promoted: bool,
+}
+
+// Keeps a secondary database in sync with a primary's change stream. Events are applied in
+// sequence order; documents are written as the primary stored them, without validators or TTLs.
+#[derive(Debug)]
+pub struct Replica {
+    db: Arc<InMemoryDB>,
+    primary: Option<Weak<ChangeLog>>,
+    applied_seq: AtomicU64,
+    last_applied_at: Mutex<Option<SystemTime>>,
+    promoted: AtomicBool,
+    // Serializes the initial copy with events arriving while it runs
+    apply_lock: Mutex<()>,
+}
+
+impl Replica {
+    // A replica fed by calling `apply` with events from a remote transport (e.g. the WebSocket
+    // change feed), starting after `applied_seq`
+    pub fn new(db: Arc<InMemoryDB>, applied_seq: u64) -> Arc<Self> {
+        Arc::new(Replica {
+            db,
+            primary: None,
+            applied_seq: AtomicU64::new(applied_seq),
+            last_applied_at: Mutex::new(None),
+            promoted: AtomicBool::new(false),
+            apply_lock: Mutex::new(()),
+        })
+    }
+
+    // Copy every collection of `primary` into `db`, then apply its writes as they happen.
+    // Enables the primary's change log if it is not enabled yet.
+    pub fn follow(primary: &InMemoryDB, db: Arc<InMemoryDB>) -> Arc<Self> {
+        let change_log = primary.enable_change_log(DEFAULT_CHANGE_LOG_CAPACITY);
+        let replica = Arc::new(Replica {
+            db,
+            primary: Some(Arc::downgrade(&change_log)),
+            applied_seq: AtomicU64::new(0),
+            last_applied_at: Mutex::new(None),
+            promoted: AtomicBool::new(false),
+            apply_lock: Mutex::new(()),
+        });
+
+        // Writes made before the listener is registered are covered by the copy below, and
+        // events appended while the copy runs wait for it; applying those again is harmless
+        let guard = replica.apply_lock.lock().unwrap();
+        replica.applied_seq.store(change_log.last_seq(), Ordering::SeqCst);
+        let weak = Arc::downgrade(&replica);
+        change_log.listen(move |event| match Weak::upgrade(&weak) {
+            Some(replica) if !replica.is_promoted() => {
+                let _guard = replica.apply_lock.lock().unwrap();
+                replica.apply_event(event);
+                true
+            }
+            _ => false,
+        });
+        primary.snapshot().restore(&replica.db);
+        *replica.last_applied_at.lock().unwrap() = Some(SystemTime::now());
+        drop(guard);
+        replica
+    }
+
+    pub fn db(&self) -> &Arc<InMemoryDB> {
+        &self.db
+    }
+
+    pub fn is_promoted(&self) -> bool {
+        self.promoted.load(Ordering::SeqCst)
+    }
+
+    // Apply one event from a remote feed. Returns false for events already applied; fails on a
+    // gap in the sequence, in which case the replica has to be rebuilt.
+    pub fn apply(&self, event: &ChangeEvent) -> Result<bool, EmemError> {
+        if self.is_promoted() {
+            return Err(EmemError::Other("Replica has been promoted and no longer applies changes".to_string()));
+        }
+        let _guard = self.apply_lock.lock().unwrap();
+        let applied = self.applied_seq.load(Ordering::SeqCst);
+        if event.seq <= applied {
+            return Ok(false);
+        }
+        if applied > 0 && event.seq > applied + 1 {
+            return Err(EmemError::Other(format!("Replication gap: expected sequence {} but received {}", applied + 1, event.seq)));
+        }
+        self.apply_event(event);
+        Ok(true)
+    }
+
+    fn apply_event(&self, event: &ChangeEvent) {
+        let collection = match self.db.collection_arc(&event.collection) {
+            Some(collection) => collection,
+            None => self.db.create::<Value>()
+                .name(&event.collection)
+                .key_type(KeyType::Custom)
+                .build(),
+        };
+        match event.event.as_str() {
+            "insert" | "update" => {
+                let replaced = collection.documents.insert(event.id.clone(), DocumentEntry::new(event.document.clone(), None));
+                collection.reindex(&event.id, replaced.as_ref().map(|entry| entry.value.as_ref()), Some(&event.document));
+                let kind = if replaced.is_some() { EventType::Update } else { EventType::Insert };
+                collection.notify(kind, &event.id, &event.document);
+            }
+            "delete" | "evicted" => {
+                if let Some((_, entry)) = collection.documents.remove(&event.id) {
+                    collection.reindex(&event.id, Some(&entry.value), None);
+                    collection.notify(EventType::Delete, &event.id, &entry.value);
+                }
+            }
+            _ => {}
+        }
+        self.applied_seq.fetch_max(event.seq, Ordering::SeqCst);
+        *self.last_applied_at.lock().unwrap() = Some(SystemTime::now());
+    }
+
+    pub fn status(&self) -> ReplicationStatus {
+        let applied_seq = self.applied_seq.load(Ordering::SeqCst);
+        let primary_seq = self.primary.as_ref()
+            .and_then(Weak::upgrade)
+            .map(|change_log| change_log.last_seq());
+        ReplicationStatus {
+            applied_seq,
+            primary_seq,
+            lag: primary_seq.map_or(0, |seq| seq.saturating_sub(applied_seq)),
+            last_applied_at: *self.last_applied_at.lock().unwrap(),
+            promoted: self.is_promoted(),
+        }
+    }
+
+    pub fn lag(&self) -> u64 {
+        self.status().lag
+    }
+
+    // Stop following the primary and hand out the replica database for writes
+    pub fn promote(&self) -> Arc<InMemoryDB> {
+        let _guard = self.apply_lock.lock().unwrap();
+        self.promoted.store(true, Ordering::SeqCst);
+        self.db.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TTL;
+    use serde_json::json;
+
+    fn event(seq: u64, kind: &str, id: &str, document: Value) -> ChangeEvent {
+        ChangeEvent { seq, collection: "users".to_string(), event: kind.to_string(), id: id.to_string(), document, at_ms: 0 }
+    }
+
+    #[test]
+    fn follow_copies_existing_documents_then_applies_new_writes() {
+        let primary = InMemoryDB::new("primary", TTL::NoTTL);
+        let users = primary.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        users.insert(json!({"id": "u1"}), None).unwrap();
+
+        let replica = Replica::follow(&primary, Arc::new(InMemoryDB::new("replica", TTL::NoTTL)));
+        users.insert(json!({"id": "u2"}), None).unwrap();
+        users.delete("u1").unwrap();
+
+        let copy = replica.db().collection_arc("users").unwrap();
+        assert!(!copy.exists("u1"));
+        assert!(copy.exists("u2"));
+        let status = replica.status();
+        assert_eq!(status.primary_seq, Some(2));
+        assert_eq!(status.applied_seq, 2);
+        assert_eq!(status.lag, 0);
+    }
+
+    #[test]
+    fn remote_events_skip_duplicates_and_reject_gaps() {
+        let replica = Replica::new(Arc::new(InMemoryDB::new("replica", TTL::NoTTL)), 0);
+        assert_eq!(replica.apply(&event(1, "insert", "u1", json!({"id": "u1"}))), Ok(true));
+        assert_eq!(replica.apply(&event(1, "insert", "u1", json!({"id": "u1"}))), Ok(false));
+        assert!(matches!(replica.apply(&event(3, "insert", "u3", json!({"id": "u3"}))), Err(EmemError::Other(_))));
+        assert_eq!(replica.apply(&event(2, "delete", "u1", json!({"id": "u1"}))), Ok(true));
+
+        assert!(!replica.db().collection_arc("users").unwrap().exists("u1"));
+        assert_eq!(replica.status().applied_seq, 2);
+        assert_eq!(replica.status().primary_seq, None);
+    }
+
+    #[test]
+    fn promoted_replica_stops_following() {
+        let primary = InMemoryDB::new("primary", TTL::NoTTL);
+        let users = primary.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        let replica = Replica::follow(&primary, Arc::new(InMemoryDB::new("replica", TTL::NoTTL)));
+        users.insert(json!({"id": "u1"}), None).unwrap();
+
+        let db = replica.promote();
+        users.insert(json!({"id": "u2"}), None).unwrap();
+
+        let copy = db.collection_arc("users").unwrap();
+        assert!(copy.exists("u1"));
+        assert!(!copy.exists("u2"));
+        assert!(replica.status().promoted);
+        assert_eq!(replica.lag(), 1);
+        assert!(replica.apply(&event(3, "insert", "u3", json!({"id": "u3"}))).is_err());
+    }
+}
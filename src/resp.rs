@@ -0,0 +1,443 @@
+// resp.rs
+// Subset of the Redis wire protocol (RESP2) mapped onto a key-value collection, so redis-cli
+// and Redis client libraries can be pointed at ememdb during development.
+//
+// Each Redis key is stored as {"key": <key>, "value": <string>} in the backing collection.
+// Supported: PING ECHO GET SET(EX/PX/NX/XX) DEL EXISTS EXPIRE PEXPIRE PERSIST TTL PTTL KEYS SCAN DBSIZE FLUSHDB
+use regex::Regex;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use crate::config::KeyType;
+use crate::db::{Collection, InMemoryDB};
+
+pub const KEY_FIELD: &str = "key";
+pub const VALUE_FIELD: &str = "value";
+// Largest accepted bulk string and argument count, as in Redis (proto-max-bulk-len 512mb)
+pub const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+pub const MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Reply::Simple("OK".to_string())
+    }
+
+    fn bulk(text: &str) -> Self {
+        Reply::Bulk(Some(text.as_bytes().to_vec()))
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(s) => out.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+            Reply::Error(e) => out.extend_from_slice(format!("-{}\r\n", e).as_bytes()),
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Bulk(Some(bytes)) => {
+                out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            }
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+        }
+    }
+}
+
+// Read one command: a RESP array of bulk strings, or an inline space separated line.
+// Returns None at end of stream.
+fn read_command<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let line = line.trim_end();
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let count = match line.strip_prefix('*') {
+        Some(count) => count.parse::<usize>().ok()
+            .filter(|count| *count <= MAX_MULTIBULK_LEN)
+            .ok_or_else(|| invalid("invalid multibulk length"))?,
+        None => return Ok(Some(line.split_whitespace().map(|s| s.as_bytes().to_vec()).collect())),
+    };
+    // Lengths come from the client, so buffers grow with the data actually received
+    let mut args = Vec::with_capacity(count.min(64));
+    for _ in 0..count {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let len = header.trim_end().strip_prefix('$')
+            .and_then(|len| len.parse::<usize>().ok())
+            .ok_or_else(|| invalid("expected a bulk string"))?;
+        if len > MAX_BULK_LEN {
+            return Err(invalid("invalid bulk length"));
+        }
+        let mut data = Vec::with_capacity(len.min(64 * 1024) + 2);
+        reader.by_ref().take(len as u64 + 2).read_to_end(&mut data)?;
+        if data.len() != len + 2 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated bulk string"));
+        }
+        data.truncate(len);
+        args.push(data);
+    }
+    Ok(Some(args))
+}
+
+fn glob_to_regex(pattern: &str) -> Result<Regex, String> {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => regex.push('['),
+            ']' => regex.push(']'),
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    regex.push_str(&regex::escape(&escaped.to_string()));
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(|e| format!("invalid pattern: {}", e))
+}
+
+pub struct RespServer {
+    collection: Arc<Collection>,
+}
+
+impl RespServer {
+    // Serve `collection`, creating it as a key-value collection when it does not exist yet
+    pub fn new(db: &InMemoryDB, collection: &str) -> Result<Self, String> {
+        let collection = match db.collection_arc(collection) {
+            Some(existing) => {
+                if existing.key_field.is_none() {
+                    return Err(format!("Collection '{}' has no key field.", collection));
+                }
+                existing
+            }
+            None => db.create::<Value>().name(collection).key(KEY_FIELD).key_type(KeyType::String).build(),
+        };
+        Ok(RespServer { collection })
+    }
+
+    // Accept connections on `addr` forever, one thread per client
+    pub fn listen<A: ToSocketAddrs>(self: Arc<Self>, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        for stream in listener.incoming() {
+            let server = self.clone();
+            let stream = stream?;
+            thread::spawn(move || {
+                let _ = server.handle_connection(stream);
+            });
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        while let Some(args) = read_command(&mut reader)? {
+            if args.is_empty() {
+                continue;
+            }
+            let quit = args[0].eq_ignore_ascii_case(b"QUIT");
+            let reply = if quit { Reply::ok() } else { self.execute(&args) };
+            let mut out = Vec::new();
+            reply.encode(&mut out);
+            writer.write_all(&out)?;
+            if quit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Run one command; usable without a socket
+    pub fn execute(&self, args: &[Vec<u8>]) -> Reply {
+        let args: Vec<String> = args.iter().map(|a| String::from_utf8_lossy(a).into_owned()).collect();
+        let name = match args.first() {
+            Some(name) => name.to_ascii_uppercase(),
+            None => return Reply::Error("ERR empty command".to_string()),
+        };
+        match self.dispatch(&name, &args[1..]) {
+            Ok(reply) => reply,
+            Err(e) => Reply::Error(format!("ERR {}", e)),
+        }
+    }
+
+    fn dispatch(&self, name: &str, args: &[String]) -> Result<Reply, String> {
+        let arity = |min: usize| {
+            if args.len() < min {
+                Err(format!("wrong number of arguments for '{}' command", name.to_lowercase()))
+            } else {
+                Ok(())
+            }
+        };
+        match name {
+            "PING" => Ok(args.first().map_or(Reply::Simple("PONG".to_string()), |msg| Reply::bulk(msg))),
+            "ECHO" => {
+                arity(1)?;
+                Ok(Reply::bulk(&args[0]))
+            }
+            // Connection setup commands sent by common clients
+            "SELECT" | "CLIENT" => Ok(Reply::ok()),
+            "COMMAND" => Ok(Reply::Array(vec![])),
+            "GET" => {
+                arity(1)?;
+                Ok(Reply::Bulk(self.get(&args[0]).map(|v| match v {
+                    Value::String(s) => s.into_bytes(),
+                    other => other.to_string().into_bytes(),
+                })))
+            }
+            "SET" => {
+                arity(2)?;
+                self.set(&args[0], &args[1], &args[2..])
+            }
+            "DEL" => {
+                arity(1)?;
+                Ok(Reply::Integer(args.iter().filter(|key| self.remove(key)).count() as i64))
+            }
+            "EXISTS" => {
+                arity(1)?;
+                Ok(Reply::Integer(args.iter().filter(|key| self.get(key).is_some()).count() as i64))
+            }
+            "EXPIRE" | "PEXPIRE" => {
+                arity(2)?;
+                let amount = args[1].parse::<u64>().map_err(|_| "value is not an integer or out of range".to_string())?;
+                let ttl = if name == "EXPIRE" { Duration::from_secs(amount) } else { Duration::from_millis(amount) };
+                let expiration = SystemTime::now().checked_add(ttl).ok_or("invalid expire time")?;
+                Ok(Reply::Integer(self.set_expiration(&args[0], Some(expiration)) as i64))
+            }
+            "PERSIST" => {
+                arity(1)?;
+                let had_ttl = matches!(self.remaining(&args[0]), Some(Some(_)));
+                Ok(Reply::Integer((had_ttl && self.set_expiration(&args[0], None)) as i64))
+            }
+            "TTL" | "PTTL" => {
+                arity(1)?;
+                Ok(Reply::Integer(match self.remaining(&args[0]) {
+                    None => -2,
+                    Some(None) => -1,
+                    Some(Some(left)) if name == "TTL" => (left.as_millis() as i64 + 999) / 1000,
+                    Some(Some(left)) => left.as_millis() as i64,
+                }))
+            }
+            "KEYS" => {
+                arity(1)?;
+                let pattern = glob_to_regex(&args[0])?;
+                Ok(Reply::Array(self.keys().into_iter().filter(|k| pattern.is_match(k)).map(|k| Reply::bulk(&k)).collect()))
+            }
+            "SCAN" => {
+                arity(1)?;
+                self.scan(args)
+            }
+            "DBSIZE" => Ok(Reply::Integer(self.keys().len() as i64)),
+            "FLUSHDB" | "FLUSHALL" => {
+                for key in self.keys() {
+                    self.remove(&key);
+                }
+                Ok(Reply::ok())
+            }
+            other => Err(format!("unknown command '{}'", other.to_lowercase())),
+        }
+    }
+
+    fn live(&self, key: &str) -> bool {
+        let now = SystemTime::now();
+        self.collection.documents.get(key)
+            .is_some_and(|entry| entry.expiration.is_none_or(|at| at > now) && !self.collection.is_deleted(&entry.value))
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        if !self.live(key) {
+            return None;
+        }
+        self.collection.documents.get(key).map(|entry| {
+            entry.touch();
+            entry.value.get(VALUE_FIELD).cloned().unwrap_or(Value::Null)
+        })
+    }
+
+    fn set(&self, key: &str, value: &str, options: &[String]) -> Result<Reply, String> {
+        let mut expiration = None;
+        let mut only_if_missing = false;
+        let mut only_if_present = false;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_str() {
+                unit @ ("EX" | "PX") => {
+                    let amount = options.next()
+                        .and_then(|n| n.parse::<u64>().ok())
+                        .ok_or("value is not an integer or out of range")?;
+                    let ttl = if unit == "EX" { Duration::from_secs(amount) } else { Duration::from_millis(amount) };
+                    expiration = Some(SystemTime::now().checked_add(ttl).ok_or("invalid expire time in 'set' command")?);
+                }
+                "NX" => only_if_missing = true,
+                "XX" => only_if_present = true,
+                _ => return Err("syntax error".to_string()),
+            }
+        }
+        let exists = self.live(key);
+        if (only_if_missing && exists) || (only_if_present && !exists) {
+            return Ok(Reply::Bulk(None));
+        }
+        let key_field = self.collection.key_field.as_deref().unwrap_or(KEY_FIELD);
+        let mut document = json!({ VALUE_FIELD: value });
+        document[key_field] = json!(key);
+        self.collection.upsert(document, None)?;
+        if expiration.is_some() {
+            self.set_expiration(key, expiration);
+        }
+        Ok(Reply::ok())
+    }
+
+    fn remove(&self, key: &str) -> bool {
+        self.live(key) && self.collection.delete(key).is_ok()
+    }
+
+    fn set_expiration(&self, key: &str, expiration: Option<SystemTime>) -> bool {
+        if !self.live(key) {
+            return false;
+        }
+        let set = self.collection.documents.get_mut(key).map(|mut entry| entry.expiration = expiration).is_some();
+        if set && expiration.is_some() {
+            self.collection.metrics.record_ttl_touch();
+        }
+        set
+    }
+
+    // None when the key is missing, Some(None) when it has no TTL
+    fn remaining(&self, key: &str) -> Option<Option<Duration>> {
+        if !self.live(key) {
+            return None;
+        }
+        let now = SystemTime::now();
+        self.collection.documents.get(key)
+            .map(|entry| entry.expiration.map(|at| at.duration_since(now).unwrap_or_default()))
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.collection.documents.iter()
+            .map(|r| r.key().clone())
+            .filter(|key| self.live(key))
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    // The cursor is an offset into the sorted key list
+    fn scan(&self, args: &[String]) -> Result<Reply, String> {
+        let cursor = args[0].parse::<usize>().map_err(|_| "invalid cursor".to_string())?;
+        let mut pattern = None;
+        let mut count = 10;
+        let mut options = args[1..].iter();
+        while let Some(option) = options.next() {
+            let operand = options.next().ok_or("syntax error")?;
+            match option.to_ascii_uppercase().as_str() {
+                "MATCH" => pattern = Some(glob_to_regex(operand)?),
+                "COUNT" => count = operand.parse::<usize>().ok().filter(|c| *c > 0).ok_or("syntax error")?,
+                _ => return Err("syntax error".to_string()),
+            }
+        }
+        let keys = self.keys();
+        let end = cursor.saturating_add(count).min(keys.len());
+        let batch = keys.get(cursor..end).unwrap_or(&[]);
+        let next = if end >= keys.len() { 0 } else { end };
+        let matched = batch.iter()
+            .filter(|k| pattern.as_ref().is_none_or(|p| p.is_match(k)))
+            .map(|k| Reply::bulk(k))
+            .collect();
+        Ok(Reply::Array(vec![Reply::bulk(&next.to_string()), Reply::Array(matched)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TTL;
+
+    fn run(server: &RespServer, command: &str) -> Reply {
+        let args: Vec<Vec<u8>> = command.split_whitespace().map(|a| a.as_bytes().to_vec()).collect();
+        server.execute(&args)
+    }
+
+    fn read(input: &[u8]) -> io::Result<Option<Vec<Vec<u8>>>> {
+        read_command(&mut io::Cursor::new(input.to_vec()))
+    }
+
+    #[test]
+    fn reads_multibulk_and_inline_commands() {
+        assert_eq!(read(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n").unwrap(), Some(vec![b"GET".to_vec(), b"k".to_vec()]));
+        assert_eq!(read(b"PING hello\r\n").unwrap(), Some(vec![b"PING".to_vec(), b"hello".to_vec()]));
+        assert_eq!(read(b"").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_oversized_and_truncated_lengths() {
+        assert!(read(b"*99999999999\r\n").is_err());
+        assert!(read(b"*1\r\n$18446744073709551615\r\n").is_err());
+        assert!(read(format!("*1\r\n${}\r\n", MAX_BULK_LEN + 1).as_bytes()).is_err());
+        assert_eq!(read(b"*1\r\n$1000\r\nshort\r\n").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn set_get_and_conditions() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let server = RespServer::new(&db, "kv").unwrap();
+        assert_eq!(run(&server, "SET a 1"), Reply::ok());
+        assert_eq!(run(&server, "GET a"), Reply::bulk("1"));
+        assert_eq!(run(&server, "SET a 2 NX"), Reply::Bulk(None));
+        assert_eq!(run(&server, "SET b 2 XX"), Reply::Bulk(None));
+        assert_eq!(run(&server, "EXISTS a b"), Reply::Integer(1));
+        assert_eq!(run(&server, "DEL a b"), Reply::Integer(1));
+        assert_eq!(run(&server, "GET a"), Reply::Bulk(None));
+    }
+
+    #[test]
+    fn expirations() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let server = RespServer::new(&db, "kv").unwrap();
+        run(&server, "SET a 1 EX 100");
+        assert_eq!(run(&server, "TTL a"), Reply::Integer(100));
+        assert_eq!(run(&server, "PERSIST a"), Reply::Integer(1));
+        assert_eq!(run(&server, "TTL a"), Reply::Integer(-1));
+        assert_eq!(run(&server, "TTL missing"), Reply::Integer(-2));
+        assert!(matches!(run(&server, "EXPIRE a 18446744073709551615"), Reply::Error(_)));
+        assert!(matches!(run(&server, "SET a 1 EX 18446744073709551615"), Reply::Error(_)));
+        run(&server, "PEXPIRE a 0");
+        assert_eq!(run(&server, "GET a"), Reply::Bulk(None));
+    }
+
+    #[test]
+    fn scan_walks_sorted_keys() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let server = RespServer::new(&db, "kv").unwrap();
+        for key in ["a", "b", "c"] {
+            run(&server, &format!("SET {} 1", key));
+        }
+        let page = |reply: Reply| match reply {
+            Reply::Array(parts) => parts,
+            other => panic!("unexpected reply {:?}", other),
+        };
+        assert_eq!(page(run(&server, "SCAN 0 COUNT 2")), vec![Reply::bulk("2"), Reply::Array(vec![Reply::bulk("a"), Reply::bulk("b")])]);
+        assert_eq!(page(run(&server, "SCAN 2 COUNT 2")), vec![Reply::bulk("0"), Reply::Array(vec![Reply::bulk("c")])]);
+        assert_eq!(page(run(&server, "SCAN 1 COUNT 18446744073709551615"))[0], Reply::bulk("0"));
+        assert_eq!(run(&server, "DBSIZE"), Reply::Integer(3));
+    }
+}
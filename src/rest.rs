@@ -0,0 +1,244 @@
+// rest.rs
+// PostgREST / Supabase style query-string filters, so HTTP layers can pass them straight through:
+//
+//   age=gte.18&name=eq.Alice&select=name,age&order=age.desc,name&limit=10&offset=20
+//   or=(age.lt.18,and(vip.is.true,city.in.(Seoul,Busan)))&name=not.ilike.j*
+//
+// Operators: eq, neq, gt, gte, lt, lte, in.(a,b), is.null|true|false, like, ilike, each optionally
+// prefixed with `not.`. Values that look like numbers match both the number and the string form.
+use serde_json::Value;
+use crate::filter::{FilterExpr, SortOrder};
+use crate::query::QueryBuilder;
+
+// Decode %XX escapes and '+' as used in URL query strings
+fn decode(text: &str) -> Result<String, String> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = text.get(i + 1..i + 3).ok_or_else(|| format!("Truncated escape in '{}'", text))?;
+                out.push(u8::from_str_radix(hex, 16).map_err(|_| format!("Invalid escape '%{}'", hex))?);
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(out).map_err(|_| format!("Invalid UTF-8 in '{}'", text))
+}
+
+// Split on commas that are not inside parentheses or double quotes
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth = depth.saturating_sub(1),
+            ',' if !quoted && depth == 0 => {
+                parts.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+// Every JSON form a textual value may be stored as
+fn candidates(raw: &str) -> Vec<Value> {
+    if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') {
+        return vec![Value::String(raw[1..raw.len() - 1].to_string())];
+    }
+    let mut values = Vec::new();
+    if let Ok(n) = raw.parse::<i64>() {
+        values.push(Value::from(n));
+    } else if let Some(n) = raw.parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+        values.push(Value::Number(n));
+    }
+    match raw {
+        "true" => values.push(Value::Bool(true)),
+        "false" => values.push(Value::Bool(false)),
+        _ => {}
+    }
+    values.push(Value::String(raw.to_string()));
+    values
+}
+
+// like/ilike pattern ('*' or '%' as wildcard) to an anchored regex
+fn like_to_regex(pattern: &str, case_insensitive: bool) -> String {
+    let mut regex = String::from(if case_insensitive { "(?i)^" } else { "^" });
+    for c in pattern.chars() {
+        match c {
+            '*' | '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+// `op.value` (optionally `not.op.value`) applied to `field`
+fn parse_condition(field: &str, condition: &str) -> Result<FilterExpr, String> {
+    if let Some(rest) = condition.strip_prefix("not.") {
+        return Ok(!parse_condition(field, rest)?);
+    }
+    let (op, raw) = condition.split_once('.')
+        .ok_or_else(|| format!("Expected operator.value for '{}' but found '{}'", field, condition))?;
+    let number = || raw.parse::<f64>().map_err(|_| format!("{} on '{}' expects a number", op, field));
+    Ok(match op {
+        "eq" => FilterExpr::in_(field, candidates(raw)),
+        "neq" => !FilterExpr::in_(field, candidates(raw)),
+        "gt" => FilterExpr::gt(field, number()?),
+        "gte" => FilterExpr::gte(field, number()?),
+        "lt" => FilterExpr::lt(field, number()?),
+        "lte" => FilterExpr::lte(field, number()?),
+        "in" => {
+            let list = raw.strip_prefix('(').and_then(|r| r.strip_suffix(')'))
+                .ok_or_else(|| format!("in on '{}' expects (a,b,...)", field))?;
+            let values = if list.is_empty() {
+                vec![]
+            } else {
+                split_top_level(list).into_iter().flat_map(candidates).collect()
+            };
+            FilterExpr::in_(field, values)
+        }
+        "is" => match raw {
+            "null" => FilterExpr::or(vec![FilterExpr::exists(field, false), FilterExpr::eq(field, Value::Null)]),
+            "true" => FilterExpr::eq(field, true),
+            "false" => FilterExpr::eq(field, false),
+            other => return Err(format!("is on '{}' expects null, true or false, found '{}'", field, other)),
+        },
+        "like" => FilterExpr::regex(field, &like_to_regex(raw, false))?,
+        "ilike" => FilterExpr::regex(field, &like_to_regex(raw, true))?,
+        other => return Err(format!("Unknown operator '{}' on field '{}'", other, field)),
+    })
+}
+
+// `(a.eq.1,or(b.gt.2,c.is.null))` as used by the or= and and= parameters
+fn parse_logic(is_or: bool, group: &str) -> Result<FilterExpr, String> {
+    let inner = group.strip_prefix('(').and_then(|g| g.strip_suffix(')'))
+        .ok_or_else(|| format!("Expected a parenthesised list but found '{}'", group))?;
+    let mut exprs = Vec::new();
+    for item in split_top_level(inner) {
+        let (negated, item) = match item.strip_prefix("not.") {
+            Some(rest) => (true, rest),
+            None => (false, item),
+        };
+        let expr = if let Some(nested) = item.strip_prefix("or(") {
+            parse_logic(true, &format!("({}", nested))?
+        } else if let Some(nested) = item.strip_prefix("and(") {
+            parse_logic(false, &format!("({}", nested))?
+        } else {
+            let (field, condition) = item.split_once('.')
+                .ok_or_else(|| format!("Expected field.operator.value but found '{}'", item))?;
+            parse_condition(field, condition)?
+        };
+        exprs.push(if negated { !expr } else { expr });
+    }
+    Ok(if is_or { FilterExpr::or(exprs) } else { FilterExpr::and(exprs) })
+}
+
+fn parse_order(spec: &str) -> Result<Vec<(String, SortOrder)>, String> {
+    spec.split(',').map(|item| {
+        let mut parts = item.split('.');
+        let field = parts.next().filter(|f| !f.is_empty())
+            .ok_or_else(|| format!("Empty field in order '{}'", spec))?;
+        let mut order = SortOrder::Asc;
+        for modifier in parts {
+            order = match modifier {
+                "asc" => SortOrder::Asc,
+                "desc" => SortOrder::Desc,
+                other => return Err(format!("Unknown order modifier '{}'", other)),
+            };
+        }
+        Ok((field.to_string(), order))
+    }).collect()
+}
+
+// Apply every parameter of a query string to `query`
+pub fn apply(mut query: QueryBuilder, params: &str) -> Result<QueryBuilder, String> {
+    for pair in params.trim_start_matches('?').split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=')
+            .ok_or_else(|| format!("Expected key=value but found '{}'", pair))?;
+        let key = decode(key)?;
+        let value = decode(value)?;
+        query = match key.as_str() {
+            "select" => {
+                let fields = if value == "*" {
+                    vec![]
+                } else {
+                    // PostgREST renames with "alias:field"
+                    value.split(',').map(|f| match f.trim().split_once(':') {
+                        Some((alias, field)) => format!("{} as {}", field, alias),
+                        None => f.trim().to_string(),
+                    }).collect()
+                };
+                query.select(fields)
+            }
+            "order" => parse_order(&value)?.into_iter().fold(query, |q, (field, order)| q.order_by(&field, order)),
+            "limit" => query.limit(value.parse().map_err(|_| format!("limit expects a number, found '{}'", value))?),
+            "offset" => query.offset(value.parse().map_err(|_| format!("offset expects a number, found '{}'", value))?),
+            "or" => query.where_expr(parse_logic(true, &value)?),
+            "and" => query.where_expr(parse_logic(false, &value)?),
+            "not.or" => query.where_expr(!parse_logic(true, &value)?),
+            "not.and" => query.where_expr(!parse_logic(false, &value)?),
+            field => query.where_expr(parse_condition(field, &value)?),
+        };
+    }
+    Ok(query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{KeyType, TTL};
+    use crate::db::InMemoryDB;
+    use serde_json::json;
+
+    #[test]
+    fn conditions_match_every_stored_form_of_a_value() {
+        assert_eq!(parse_condition("age", "eq.18").unwrap(), FilterExpr::in_("age", vec![json!(18), json!("18")]));
+        assert_eq!(parse_condition("vip", "neq.true").unwrap(), !FilterExpr::in_("vip", vec![json!(true), json!("true")]));
+        assert_eq!(parse_condition("code", "in.(\"7\",x)").unwrap(), FilterExpr::in_("code", vec![json!("7"), json!("x")]));
+        assert!(parse_condition("age", "gt.old").is_err());
+        assert!(parse_condition("age", "between.1").is_err());
+        assert!(parse_condition("age", "18").is_err());
+    }
+
+    #[test]
+    fn decodes_query_string_escapes() {
+        assert_eq!(decode("new+york%2C%20ny").unwrap(), "new york, ny");
+        assert!(decode("100%").is_err());
+        assert!(decode("%zz").is_err());
+        assert_eq!(split_top_level("a.eq.1,or(b.gt.2,c.is.null),d.eq.\"x,y\""), ["a.eq.1", "or(b.gt.2,c.is.null)", "d.eq.\"x,y\""]);
+    }
+
+    #[test]
+    fn applies_filters_order_and_paging_to_a_query() {
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        for (id, name, age, city) in [("1", "Jane", 17, "Seoul"), ("2", "john", 30, "Busan"), ("3", "Kim", 25, "Seoul"), ("4", "Lee", 40, "Jeju")] {
+            users.insert(json!({"id": id, "name": name, "age": age, "city": city}), None).unwrap();
+        }
+        let names = |params: &str| -> Vec<Value> {
+            apply(users.select("*"), params).unwrap().execute().unwrap().into_iter().map(|doc| doc["name"].clone()).collect()
+        };
+
+        assert_eq!(names("age=gte.18&order=age.desc"), [json!("Lee"), json!("john"), json!("Kim")]);
+        assert_eq!(names("name=not.ilike.j*&order=name"), [json!("Kim"), json!("Lee")]);
+        assert_eq!(names("or=(age.lt.18,and(city.in.(Busan,Jeju),age.gt.35))&order=id"), [json!("Jane"), json!("Lee")]);
+        assert_eq!(names("not.or=(city.eq.Seoul,age.gt.35)"), [json!("john")]);
+        assert_eq!(names("order=age&limit=2&offset=1"), [json!("Kim"), json!("john")]);
+        assert!(apply(users.select("*"), "order=age.sideways").is_err());
+        assert!(apply(users.select("*"), "limit").is_err());
+    }
+}
@@ -0,0 +1,510 @@
+// sink.rs
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{BufWriter, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, JoinHandle, Thread};
+use std::time::Duration;
+use crate::changelog::{ChangeEvent, ChangeFilter, ChangeLog};
+
+// Upper bound on how long the connector thread blocks, so stop() takes effect quickly
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Destination for change events. Batches are delivered in sequence order from a single thread;
+// an error makes the connector retry the same batch according to its RetryPolicy.
+pub trait ChangeSink: Send {
+    fn name(&self) -> &str;
+    fn send(&mut self, events: &[ChangeEvent]) -> Result<(), String>;
+}
+
+// Forwards batches to a closure
+pub struct FnSink<F> {
+    name: String,
+    send: F,
+}
+
+impl<F> FnSink<F>
+where
+    F: FnMut(&[ChangeEvent]) -> Result<(), String> + Send,
+{
+    pub fn new(name: &str, send: F) -> Self {
+        FnSink { name: name.to_string(), send }
+    }
+}
+
+impl<F> ChangeSink for FnSink<F>
+where
+    F: FnMut(&[ChangeEvent]) -> Result<(), String> + Send,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, events: &[ChangeEvent]) -> Result<(), String> {
+        (self.send)(events)
+    }
+}
+
+// Forwards batches to an async closure, polled to completion on the connector thread. Futures
+// that need a tokio runtime should spawn their work on a captured runtime Handle and await the
+// JoinHandle.
+pub struct AsyncFnSink<F> {
+    name: String,
+    send: F,
+}
+
+impl<F, Fut> AsyncFnSink<F>
+where
+    F: FnMut(Vec<ChangeEvent>) -> Fut + Send,
+    Fut: Future<Output = Result<(), String>>,
+{
+    pub fn new(name: &str, send: F) -> Self {
+        AsyncFnSink { name: name.to_string(), send }
+    }
+}
+
+impl<F, Fut> ChangeSink for AsyncFnSink<F>
+where
+    F: FnMut(Vec<ChangeEvent>) -> Fut + Send,
+    Fut: Future<Output = Result<(), String>>,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, events: &[ChangeEvent]) -> Result<(), String> {
+        block_on((self.send)(events.to_vec()))
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+// Appends every event as a JSON line
+pub struct FileSink {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)
+            .map_err(|e| format!("Failed to open sink file {}: {}", path.display(), e))?;
+        Ok(FileSink { path, writer: BufWriter::new(file) })
+    }
+}
+
+impl ChangeSink for FileSink {
+    fn name(&self) -> &str {
+        self.path.to_str().unwrap_or("file")
+    }
+
+    fn send(&mut self, events: &[ChangeEvent]) -> Result<(), String> {
+        for event in events {
+            let line = serde_json::to_string(event).map_err(|e| e.to_string())?;
+            writeln!(self.writer, "{}", line).map_err(|e| e.to_string())?;
+        }
+        self.writer.flush().map_err(|e| e.to_string())
+    }
+}
+
+// POSTs each batch as a JSON array to a plain-HTTP endpoint; any non-2xx response is an error
+pub struct WebhookSink {
+    url: String,
+    host: String,
+    port: u16,
+    path: String,
+    timeout: Duration,
+}
+
+impl WebhookSink {
+    // `url` must look like http://host[:port]/path
+    pub fn new(url: &str) -> Result<Self, String> {
+        let rest = url.strip_prefix("http://")
+            .ok_or_else(|| format!("Unsupported webhook URL '{}': only http:// is supported", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("Invalid port in webhook URL '{}'", url))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in webhook URL '{}'", url));
+        }
+        Ok(WebhookSink {
+            url: url.to_string(),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            timeout: Duration::from_secs(10),
+        })
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn post(&self, body: &[u8]) -> Result<u16, String> {
+        let address = (self.host.as_str(), self.port).to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("Could not resolve {}", self.host))?;
+        let mut stream = TcpStream::connect_timeout(&address, self.timeout).map_err(|e| e.to_string())?;
+        stream.set_read_timeout(Some(self.timeout)).map_err(|e| e.to_string())?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(|e| e.to_string())?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path, self.host, body.len()
+        ).map_err(|e| e.to_string())?;
+        stream.write_all(body).map_err(|e| e.to_string())?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map_err(|e| e.to_string())?;
+        let status_line = response.split(|&b| b == b'\n').next().unwrap_or_default();
+        String::from_utf8_lossy(status_line).split_whitespace().nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| "Invalid HTTP response".to_string())
+    }
+}
+
+impl ChangeSink for WebhookSink {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn send(&mut self, events: &[ChangeEvent]) -> Result<(), String> {
+        let body = serde_json::to_vec(events).map_err(|e| e.to_string())?;
+        match self.post(&body)? {
+            status if (200..300).contains(&status) => Ok(()),
+            status => Err(format!("Webhook {} responded with status {}", self.url, status)),
+        }
+    }
+}
+
+// Exponential backoff between attempts to deliver the same batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    // Retries after the first attempt; None retries until delivery succeeds
+    pub max_retries: Option<u32>,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: Some(5),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff.saturating_mul(2u32.saturating_pow(retry)).min(self.max_backoff)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SinkOptions {
+    pub filter: ChangeFilter,
+    pub batch_size: usize,
+    pub retry: RetryPolicy,
+    // Deliver retained events after this sequence number before continuing live
+    pub since: Option<u64>,
+}
+
+impl Default for SinkOptions {
+    fn default() -> Self {
+        SinkOptions {
+            filter: ChangeFilter::default(),
+            batch_size: 100,
+            retry: RetryPolicy::default(),
+            since: None,
+        }
+    }
+}
+
+impl SinkOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn filter(mut self, filter: ChangeFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn since(mut self, seq: u64) -> Self {
+        self.since = Some(seq);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SinkStatus {
+    pub delivered: u64,
+    // Events given up on after the retry policy was exhausted
+    pub dropped: u64,
+    pub retries: u64,
+    // Sequence number of the last event delivered or dropped
+    pub last_seq: u64,
+    pub last_error: Option<String>,
+}
+
+// Background thread forwarding a change log to a sink; stops when the handle is dropped
+#[derive(Debug)]
+pub struct SinkConnector {
+    name: String,
+    stop: Arc<AtomicBool>,
+    status: Arc<Mutex<SinkStatus>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SinkConnector {
+    pub(crate) fn start<S: ChangeSink + 'static>(change_log: &ChangeLog, sink: S, options: SinkOptions) -> Result<Self, String> {
+        let (sender, receiver) = mpsc::channel();
+        let filter = options.filter.clone();
+        change_log.listen(move |event| !filter.matches(event) || sender.send(event.clone()).is_ok());
+        let backlog = match options.since {
+            Some(seq) => change_log.since(seq)?.into_iter().filter(|e| options.filter.matches(e)).collect(),
+            None => Vec::new(),
+        };
+
+        let name = sink.name().to_string();
+        let stop = Arc::new(AtomicBool::new(false));
+        let status = Arc::new(Mutex::new(SinkStatus {
+            last_seq: options.since.unwrap_or(0),
+            ..Default::default()
+        }));
+        let handle = {
+            let stop = stop.clone();
+            let status = status.clone();
+            thread::spawn(move || Self::run(sink, options, backlog, receiver, stop, status))
+        };
+        Ok(SinkConnector { name, stop, status, handle: Some(handle) })
+    }
+
+    fn run<S: ChangeSink>(mut sink: S, options: SinkOptions, backlog: Vec<ChangeEvent>, receiver: Receiver<ChangeEvent>, stop: Arc<AtomicBool>, status: Arc<Mutex<SinkStatus>>) {
+        let mut pending = backlog;
+        while !stop.load(Ordering::SeqCst) {
+            if pending.is_empty() {
+                match receiver.recv_timeout(POLL_INTERVAL) {
+                    Ok(event) => pending.push(event),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            while pending.len() < options.batch_size {
+                match receiver.try_recv() {
+                    Ok(event) => pending.push(event),
+                    Err(_) => break,
+                }
+            }
+            // Events from `since` may also have arrived live
+            let last_seq = status.lock().unwrap().last_seq;
+            pending.retain(|e| e.seq > last_seq);
+            let batch: Vec<ChangeEvent> = pending.drain(..pending.len().min(options.batch_size)).collect();
+            if let Some(last) = batch.last() {
+                let seq = last.seq;
+                let delivered = Self::deliver(&mut sink, &batch, &options.retry, &stop, &status);
+                let mut status = status.lock().unwrap();
+                if delivered {
+                    status.delivered += batch.len() as u64;
+                } else if stop.load(Ordering::SeqCst) {
+                    break;
+                } else {
+                    status.dropped += batch.len() as u64;
+                }
+                status.last_seq = seq;
+            }
+        }
+    }
+
+    fn deliver<S: ChangeSink>(sink: &mut S, batch: &[ChangeEvent], retry: &RetryPolicy, stop: &AtomicBool, status: &Mutex<SinkStatus>) -> bool {
+        let mut attempt = 0;
+        loop {
+            match sink.send(batch) {
+                Ok(()) => return true,
+                Err(e) => status.lock().unwrap().last_error = Some(e),
+            }
+            if retry.max_retries.is_some_and(|max| attempt >= max) {
+                return false;
+            }
+            let mut remaining = retry.backoff(attempt);
+            while remaining > Duration::ZERO {
+                if stop.load(Ordering::SeqCst) {
+                    return false;
+                }
+                let step = remaining.min(POLL_INTERVAL);
+                thread::sleep(step);
+                remaining -= step;
+            }
+            attempt += 1;
+            status.lock().unwrap().retries += 1;
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn status(&self) -> SinkStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for SinkConnector {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    fn quick_retry(max_retries: Option<u32>) -> RetryPolicy {
+        RetryPolicy { max_retries, initial_backoff: Duration::from_millis(1), max_backoff: Duration::from_millis(4) }
+    }
+
+    fn wait_for(connector: &SinkConnector, done: impl Fn(&SinkStatus) -> bool) -> SinkStatus {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let status = connector.status();
+            if done(&status) || Instant::now() > deadline {
+                return status;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let retry = RetryPolicy { max_retries: None, initial_backoff: Duration::from_millis(100), max_backoff: Duration::from_millis(500) };
+        assert_eq!(retry.backoff(0), Duration::from_millis(100));
+        assert_eq!(retry.backoff(2), Duration::from_millis(400));
+        assert_eq!(retry.backoff(3), Duration::from_millis(500));
+        assert_eq!(retry.backoff(u32::MAX), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn failed_batches_are_retried_until_delivered() {
+        let log = ChangeLog::new(10);
+        let attempts = Arc::new(Mutex::new(0));
+        let sink = {
+            let attempts = attempts.clone();
+            FnSink::new("flaky", move |_: &[ChangeEvent]| {
+                let mut attempts = attempts.lock().unwrap();
+                *attempts += 1;
+                if *attempts < 3 { Err(format!("attempt {}", attempts)) } else { Ok(()) }
+            })
+        };
+        let connector = SinkConnector::start(&log, sink, SinkOptions::new().retry(quick_retry(Some(5)))).unwrap();
+        log.append("users", "insert", "1", &json!({"id": "1"}));
+
+        let status = wait_for(&connector, |s| s.delivered == 1);
+        assert_eq!(status.delivered, 1);
+        assert_eq!(status.retries, 2);
+        assert_eq!(status.dropped, 0);
+        assert_eq!(status.last_seq, 1);
+        assert_eq!(status.last_error.as_deref(), Some("attempt 2"));
+    }
+
+    #[test]
+    fn batches_are_dropped_once_retries_run_out() {
+        let log = ChangeLog::new(10);
+        let sink = FnSink::new("down", |_: &[ChangeEvent]| Err("unavailable".to_string()));
+        let connector = SinkConnector::start(&log, sink, SinkOptions::new().retry(quick_retry(Some(1)))).unwrap();
+        log.append("users", "insert", "1", &json!({"id": "1"}));
+
+        let status = wait_for(&connector, |s| s.dropped == 1);
+        assert_eq!(status.dropped, 1);
+        assert_eq!(status.retries, 1);
+        assert_eq!(status.delivered, 0);
+        assert_eq!(status.last_seq, 1);
+    }
+
+    #[test]
+    fn since_replays_retained_events_before_live_ones_without_duplicates() {
+        let log = ChangeLog::new(10);
+        for id in ["1", "2", "3"] {
+            log.append("users", "insert", id, &json!({"id": id}));
+        }
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let received = received.clone();
+            FnSink::new("collect", move |events: &[ChangeEvent]| {
+                received.lock().unwrap().extend(events.iter().map(|e| e.seq));
+                Ok(())
+            })
+        };
+        let connector = SinkConnector::start(&log, sink, SinkOptions::new().since(1).batch_size(1)).unwrap();
+        log.append("users", "insert", "4", &json!({"id": "4"}));
+
+        let status = wait_for(&connector, |s| s.last_seq == 4);
+        assert_eq!(status.delivered, 3);
+        assert_eq!(*received.lock().unwrap(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn since_fails_when_events_were_already_dropped() {
+        let log = ChangeLog::new(2);
+        for id in ["1", "2", "3", "4"] {
+            log.append("users", "insert", id, &json!({"id": id}));
+        }
+        let sink = FnSink::new("late", |_: &[ChangeEvent]| Ok(()));
+        assert!(SinkConnector::start(&log, sink, SinkOptions::new().since(1)).is_err());
+    }
+}
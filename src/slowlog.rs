@@ -0,0 +1,80 @@
+// slowlog.rs
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use crate::query::QueryPlan;
+
+// Number of slow queries kept when no capacity is given
+pub const DEFAULT_SLOW_QUERY_LOG_CAPACITY: usize = 100;
+
+// A query that ran longer than DbConfig::slow_query_threshold
+#[derive(Debug, Clone)]
+pub struct SlowQuery {
+    pub collection: String,
+    pub elapsed: Duration,
+    pub threshold: Duration,
+    // Rows returned after paging and joins
+    pub results: usize,
+    pub plan: QueryPlan,
+    pub at: SystemTime,
+}
+
+type SlowQueryCallback = Arc<dyn Fn(&SlowQuery) + Send + Sync>;
+
+// The most recent slow queries of a database, oldest first
+pub struct SlowQueryLog {
+    capacity: usize,
+    entries: Mutex<VecDeque<SlowQuery>>,
+    listeners: RwLock<Vec<SlowQueryCallback>>,
+}
+
+impl fmt::Debug for SlowQueryLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SlowQueryLog(capacity: {}, entries: {})", self.capacity, self.entries.lock().unwrap().len())
+    }
+}
+
+impl Default for SlowQueryLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOW_QUERY_LOG_CAPACITY)
+    }
+}
+
+impl SlowQueryLog {
+    pub fn new(capacity: usize) -> Self {
+        SlowQueryLog {
+            capacity: capacity.max(1),
+            entries: Mutex::new(VecDeque::new()),
+            listeners: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn record(&self, query: SlowQuery) {
+        let listeners = self.listeners.read().unwrap().clone();
+        for listener in listeners {
+            listener(&query);
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(query);
+    }
+
+    pub fn entries(&self) -> Vec<SlowQuery> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub fn listen(&self, callback: impl Fn(&SlowQuery) + Send + Sync + 'static) {
+        self.listeners.write().unwrap().push(Arc::new(callback));
+    }
+}
@@ -0,0 +1,179 @@
+// snapshot.rs
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+use crate::config::{IncrementKey, KeyType, TTL};
+use crate::db::{Collection, DocumentEntry, InMemoryDB};
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentSnapshot {
+    pub id: String,
+    pub document: Value,
+    // Expiration in milliseconds since the epoch
+    #[serde(default)]
+    pub expires_at_ms: Option<u64>,
+    // Field-level TTLs (see Collection::expire_field), same unit
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub field_expires_at_ms: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionSnapshot {
+    pub name: String,
+    pub key_field: Option<String>,
+    pub key_type: KeyType,
+    #[serde(default)]
+    pub unique_keys: Vec<String>,
+    #[serde(default)]
+    pub soft_delete: bool,
+    #[serde(default)]
+    pub memory_limit: Option<usize>,
+    #[serde(default)]
+    pub max_documents: Option<usize>,
+    #[serde(default)]
+    pub max_document_size: Option<usize>,
+    #[serde(default)]
+    pub field_types: Vec<(String, String)>,
+    #[serde(default)]
+    pub default_ttl: Option<TTL>,
+    // Next value handed out by KeyType::Increment, and how it is formatted
+    #[serde(default)]
+    pub next_id: u64,
+    #[serde(default)]
+    pub increment: IncrementKey,
+    pub documents: Vec<DocumentSnapshot>,
+}
+
+impl CollectionSnapshot {
+    pub fn capture(collection: &Collection) -> Self {
+        let now = SystemTime::now();
+        let mut documents: Vec<DocumentSnapshot> = collection.documents.iter()
+            .filter(|r| r.value().expiration.map_or(true, |at| at > now))
+            .map(|r| DocumentSnapshot {
+                id: r.key().clone(),
+                document: Value::clone(&r.value().value),
+                expires_at_ms: r.value().expiration.map(to_millis),
+                field_expires_at_ms: r.value().field_expirations.iter()
+                    .map(|(field, at)| (field.clone(), to_millis(*at)))
+                    .collect(),
+            })
+            .collect();
+        documents.sort_by(|a, b| a.id.cmp(&b.id));
+        CollectionSnapshot {
+            name: collection.collection_name.clone(),
+            key_field: collection.key_field.clone(),
+            key_type: collection.key_type.clone(),
+            unique_keys: collection.unique_keys.clone(),
+            soft_delete: collection.soft_delete,
+            memory_limit: collection.memory_limit,
+            max_documents: collection.max_documents,
+            max_document_size: collection.max_document_size,
+            field_types: collection.field_types.clone(),
+            default_ttl: collection.default_ttl.clone(),
+            next_id: collection.next_id.load(Ordering::SeqCst),
+            increment: collection.increment.clone(),
+            documents,
+        }
+    }
+}
+
+// Point-in-time copy of every collection, written as a single JSON document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub db_name: String,
+    pub created_at_ms: u64,
+    pub collections: Vec<CollectionSnapshot>,
+}
+
+impl Snapshot {
+    pub fn capture(db: &InMemoryDB) -> Self {
+        let mut names = db.collection_names();
+        names.sort();
+        Snapshot {
+            db_name: db.name().to_string(),
+            created_at_ms: to_millis(SystemTime::now()),
+            collections: names.iter()
+                .filter_map(|name| db.collection_arc(name))
+                .map(|collection| CollectionSnapshot::capture(&collection))
+                .collect(),
+        }
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|e| format!("Failed to open snapshot {}: {}", path.display(), e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))
+    }
+
+    // Written to a temporary file first so a crash never leaves a truncated snapshot behind
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let file = File::create(&tmp)
+            .map_err(|e| format!("Failed to create snapshot {}: {}", tmp.display(), e))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)
+            .map_err(|e| format!("Failed to write snapshot {}: {}", tmp.display(), e))?;
+        writer.flush().map_err(|e| format!("Failed to write snapshot {}: {}", tmp.display(), e))?;
+        fs::rename(&tmp, path).map_err(|e| format!("Failed to move snapshot to {}: {}", path.display(), e))
+    }
+
+    // Recreate every collection in `db`, replacing collections with the same name.
+    // Documents are restored as stored, without running validators or computed fields.
+    pub fn restore(&self, db: &InMemoryDB) -> usize {
+        let now = SystemTime::now();
+        let mut restored = 0;
+        for snapshot in &self.collections {
+            let mut builder = db.create::<Value>()
+                .name(&snapshot.name)
+                .key_type(snapshot.key_type.clone())
+                .unique_keys(snapshot.unique_keys.iter().map(|s| s.as_str()).collect());
+            if let Some(key_field) = &snapshot.key_field {
+                builder = builder.key(key_field);
+            }
+            if snapshot.soft_delete {
+                builder = builder.soft_delete();
+            }
+            if let Some(limit) = snapshot.memory_limit {
+                builder = builder.memory_limit(limit);
+            }
+            if let Some(max) = snapshot.max_documents {
+                builder = builder.max_documents(max);
+            }
+            if let Some(max) = snapshot.max_document_size {
+                builder = builder.max_document_size(max);
+            }
+            builder = builder.increment(snapshot.increment.clone());
+            if let Some(ttl) = &snapshot.default_ttl {
+                builder = builder.ttl(ttl.clone());
+            }
+            // Collections whose settings no longer validate are left out
+            let Ok(collection) = builder.field_types(snapshot.field_types.clone()).try_build() else { continue };
+            collection.next_id.store(snapshot.next_id, Ordering::SeqCst);
+            for document in &snapshot.documents {
+                let expiration = document.expires_at_ms.map(|ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms));
+                if expiration.map_or(false, |at| at <= now) {
+                    continue;
+                }
+                let mut entry = DocumentEntry::new(document.document.clone(), expiration);
+                entry.field_expirations = document.field_expires_at_ms.iter()
+                    .map(|(field, ms)| (field.clone(), SystemTime::UNIX_EPOCH + Duration::from_millis(*ms)))
+                    .collect();
+                collection.documents.insert(document.id.clone(), entry);
+                restored += 1;
+            }
+        }
+        restored
+    }
+}
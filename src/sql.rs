@@ -0,0 +1,389 @@
+// sql.rs
+// Small SQL subset mapped onto QueryBuilder / FilterExpr:
+//
+//   SELECT name, age AS years FROM users WHERE age >= 18 AND (city = 'Seoul' OR vip = true)
+//   ORDER BY age DESC, name LIMIT 10 OFFSET 20
+//
+// Conditions support = != <> < <= > >=, IN (...), NOT IN (...), NOT, AND, OR and parentheses.
+use serde_json::{json, Value};
+use crate::db::InMemoryDB;
+use crate::filter::{FilterExpr, SortOrder};
+use crate::query::QueryBuilder;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(f64, String),
+    Symbol(String),
+}
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err("Unterminated string literal".to_string()),
+                    Some('\'') if chars.get(i + 1) == Some(&'\'') => {
+                        text.push('\'');
+                        i += 2;
+                    }
+                    Some('\'') => {
+                        i += 1;
+                        break;
+                    }
+                    Some(ch) => {
+                        text.push(*ch);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(Token::Str(text));
+        } else if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).map_or(false, |n| n.is_ascii_digit())) {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let number = text.parse::<f64>().map_err(|_| format!("Invalid number '{}'", text))?;
+            tokens.push(Token::Number(number, text));
+        } else if c.is_alphanumeric() || c == '_' || c == '"' {
+            let quoted = c == '"';
+            if quoted {
+                i += 1;
+            }
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.' || (quoted && chars[i] != '"')) {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            if quoted {
+                if chars.get(i) != Some(&'"') {
+                    return Err("Unterminated quoted identifier".to_string());
+                }
+                i += 1;
+            }
+        } else {
+            let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            if ["!=", "<>", "<=", ">="].contains(&two.as_str()) {
+                tokens.push(Token::Symbol(two));
+                i += 2;
+            } else if "=<>(),*".contains(c) {
+                tokens.push(Token::Symbol(c.to_string()));
+                i += 1;
+            } else {
+                return Err(format!("Unexpected character '{}'", c));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// A parsed SELECT statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectStatement {
+    pub fields: Vec<String>,
+    pub collection: String,
+    pub filter: Option<FilterExpr>,
+    pub order_by: Vec<(String, SortOrder)>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        if self.accept_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(format!("Expected {} but found {:?}", keyword, self.peek()))
+        }
+    }
+
+    fn accept_symbol(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if s == symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+        if self.accept_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' but found {:?}", symbol, self.peek()))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(name),
+            other => Err(format!("Expected an identifier but found {:?}", other)),
+        }
+    }
+
+    fn unsigned(&mut self, clause: &str) -> Result<usize, String> {
+        match self.next() {
+            Some(Token::Number(n, _)) if n >= 0.0 && n.fract() == 0.0 => Ok(n as usize),
+            other => Err(format!("{} expects a non-negative integer but found {:?}", clause, other)),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Value, String> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(Value::String(s)),
+            Some(Token::Number(n, text)) => Ok(text.parse::<i64>().map(Value::from).unwrap_or_else(|_| json!(n))),
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("true") => Ok(Value::Bool(true)),
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("false") => Ok(Value::Bool(false)),
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("null") => Ok(Value::Null),
+            other => Err(format!("Expected a literal but found {:?}", other)),
+        }
+    }
+
+    fn select(&mut self) -> Result<SelectStatement, String> {
+        self.expect_keyword("SELECT")?;
+        let mut fields = Vec::new();
+        if !self.accept_symbol("*") {
+            loop {
+                let field = self.identifier()?;
+                if self.accept_keyword("AS") {
+                    fields.push(format!("{} as {}", field, self.identifier()?));
+                } else {
+                    fields.push(field);
+                }
+                if !self.accept_symbol(",") {
+                    break;
+                }
+            }
+        }
+        self.expect_keyword("FROM")?;
+        let collection = self.identifier()?;
+
+        let filter = if self.accept_keyword("WHERE") { Some(self.or_expr()?) } else { None };
+
+        let mut order_by = Vec::new();
+        if self.accept_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            loop {
+                let field = self.identifier()?;
+                let order = if self.accept_keyword("DESC") {
+                    SortOrder::Desc
+                } else {
+                    self.accept_keyword("ASC");
+                    SortOrder::Asc
+                };
+                order_by.push((field, order));
+                if !self.accept_symbol(",") {
+                    break;
+                }
+            }
+        }
+
+        let mut limit = None;
+        let mut offset = 0;
+        loop {
+            if self.accept_keyword("LIMIT") {
+                limit = Some(self.unsigned("LIMIT")?);
+            } else if self.accept_keyword("OFFSET") {
+                offset = self.unsigned("OFFSET")?;
+            } else {
+                break;
+            }
+        }
+
+        if let Some(token) = self.peek() {
+            return Err(format!("Unexpected {:?} after the end of the statement", token));
+        }
+        Ok(SelectStatement { fields, collection, filter, order_by, limit, offset })
+    }
+
+    fn or_expr(&mut self) -> Result<FilterExpr, String> {
+        let mut exprs = vec![self.and_expr()?];
+        while self.accept_keyword("OR") {
+            exprs.push(self.and_expr()?);
+        }
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { FilterExpr::or(exprs) })
+    }
+
+    fn and_expr(&mut self) -> Result<FilterExpr, String> {
+        let mut exprs = vec![self.not_expr()?];
+        while self.accept_keyword("AND") {
+            exprs.push(self.not_expr()?);
+        }
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { FilterExpr::and(exprs) })
+    }
+
+    fn not_expr(&mut self) -> Result<FilterExpr, String> {
+        if self.accept_keyword("NOT") {
+            return Ok(!self.not_expr()?);
+        }
+        if self.accept_symbol("(") {
+            let expr = self.or_expr()?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<FilterExpr, String> {
+        let field = self.identifier()?;
+        let negated = self.accept_keyword("NOT");
+        if self.accept_keyword("IN") {
+            self.expect_symbol("(")?;
+            let mut values = Vec::new();
+            if !self.accept_symbol(")") {
+                loop {
+                    values.push(self.literal()?);
+                    if !self.accept_symbol(",") {
+                        break;
+                    }
+                }
+                self.expect_symbol(")")?;
+            }
+            let expr = FilterExpr::in_(&field, values);
+            return Ok(if negated { !expr } else { expr });
+        }
+        if negated {
+            return Err(format!("Expected IN after NOT for field '{}'", field));
+        }
+
+        let op = match self.next() {
+            Some(Token::Symbol(op)) => op,
+            other => return Err(format!("Expected a comparison operator after '{}' but found {:?}", field, other)),
+        };
+        let value = self.literal()?;
+        let number = || value.as_f64().ok_or_else(|| format!("Operator {} on '{}' requires a number", op, field));
+        Ok(match op.as_str() {
+            "=" => FilterExpr::eq(&field, value.clone()),
+            "!=" | "<>" => FilterExpr::neq(&field, value.clone()),
+            ">" => FilterExpr::gt(&field, number()?),
+            ">=" => FilterExpr::gte(&field, number()?),
+            "<" => FilterExpr::lt(&field, number()?),
+            "<=" => FilterExpr::lte(&field, number()?),
+            other => return Err(format!("Unknown operator '{}'", other)),
+        })
+    }
+}
+
+pub fn parse(sql: &str) -> Result<SelectStatement, String> {
+    let mut parser = Parser { tokens: tokenize(sql)?, pos: 0 };
+    parser.select()
+}
+
+// Build the QueryBuilder for a SELECT statement against the database
+pub fn prepare(db: &InMemoryDB, sql: &str) -> Result<QueryBuilder, String> {
+    let statement = parse(sql)?;
+    let collection = db.collection_arc(&statement.collection)
+        .ok_or_else(|| format!("Collection '{}' not found.", statement.collection))?;
+    let mut query = QueryBuilder::new(collection).select(statement.fields);
+    if let Some(filter) = statement.filter {
+        query = query.where_expr(filter);
+    }
+    for (field, order) in statement.order_by {
+        query = query.order_by(&field, order);
+    }
+    if let Some(limit) = statement.limit {
+        query = query.limit(limit);
+    }
+    Ok(query.offset(statement.offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{KeyType, TTL};
+
+    fn filter(sql: &str) -> FilterExpr {
+        parse(sql).unwrap().filter.unwrap()
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_and_and_tighter_than_or() {
+        assert_eq!(
+            filter("SELECT * FROM t WHERE NOT a = 1 AND b = 2 OR c = 3"),
+            FilterExpr::or(vec![
+                FilterExpr::and(vec![!FilterExpr::eq("a", json!(1)), FilterExpr::eq("b", json!(2))]),
+                FilterExpr::eq("c", json!(3)),
+            ])
+        );
+        assert_eq!(
+            filter("SELECT * FROM t WHERE NOT (a = 1 OR b = 2)"),
+            !FilterExpr::or(vec![FilterExpr::eq("a", json!(1)), FilterExpr::eq("b", json!(2))])
+        );
+    }
+
+    #[test]
+    fn doubled_quotes_escape_a_quote() {
+        assert_eq!(filter("SELECT * FROM t WHERE name = 'O''Brien'"), FilterExpr::eq("name", json!("O'Brien")));
+        assert_eq!(filter("SELECT * FROM t WHERE name = ''''"), FilterExpr::eq("name", json!("'")));
+        assert!(parse("SELECT * FROM t WHERE name = 'open").is_err());
+    }
+
+    #[test]
+    fn not_in_negates_the_list() {
+        assert_eq!(
+            filter("SELECT * FROM t WHERE city NOT IN ('Seoul', 'Busan')"),
+            !FilterExpr::in_("city", vec![json!("Seoul"), json!("Busan")])
+        );
+        assert!(parse("SELECT * FROM t WHERE city NOT = 'Seoul'").is_err());
+
+        let db = InMemoryDB::new("test", TTL::NoTTL);
+        let users = db.create::<Value>().name("users").key("id").key_type(KeyType::String).build();
+        for (id, city) in [("u1", "Seoul"), ("u2", "Busan"), ("u3", "Daegu")] {
+            users.insert(json!({"id": id, "city": city}), None).unwrap();
+        }
+        let rows = prepare(&db, "SELECT id FROM users WHERE city NOT IN ('Seoul', 'Busan')").unwrap().execute().unwrap();
+        assert_eq!(rows, vec![json!({"id": "u3"})]);
+    }
+
+    #[test]
+    fn limit_and_offset_take_non_negative_integers() {
+        let statement = parse("SELECT * FROM t LIMIT 10 OFFSET 20").unwrap();
+        assert_eq!((statement.limit, statement.offset), (Some(10), 20));
+        let statement = parse("SELECT * FROM t OFFSET 5 LIMIT 1").unwrap();
+        assert_eq!((statement.limit, statement.offset), (Some(1), 5));
+        assert!(parse("SELECT * FROM t LIMIT -1").is_err());
+        assert!(parse("SELECT * FROM t LIMIT 1.5").is_err());
+        assert!(parse("SELECT * FROM t OFFSET 'a'").is_err());
+        assert!(parse("SELECT * FROM t LIMIT").is_err());
+        assert!(parse("SELECT * FROM t LIMIT 1 2").is_err());
+    }
+}
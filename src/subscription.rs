@@ -1,12 +1,17 @@
 // subscription.rs
 use serde_json::Value;
-use std::sync::{Arc, Mutex};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum EventType<'a> {
     Insert,
     Update,
     Delete,
     ColumnUpdate(&'a str), // Event for specific column updates
+    Evicted, // Document dropped to stay within the collection's memory limit
+    ColumnExpired(&'a str), // Field removed because its field-level TTL ran out
 }
 
 type Callback<'a> = Arc<Mutex<dyn Fn(&str, &Value) + Send + Sync + 'a>>;
@@ -24,6 +29,10 @@ impl<'a> Subscription<'a> {
         }
     }
 
+    pub fn matches(&self, event: &EventType) -> bool {
+        &self.event_type == event
+    }
+
     pub fn trigger(&self, id: &str, data: &Value) {
         if let Ok(callback) = self.callback.lock() {
             callback(id, data);
@@ -32,3 +41,48 @@ impl<'a> Subscription<'a> {
         }
     }
 }
+
+impl fmt::Debug for Subscription<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Subscription({:?})", self.event_type)
+    }
+}
+
+// Database-level notifications for operators (configuration changes, sweeps, limits)
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminEvent {
+    ConfigChanged { setting: String, old: Value, new: Value },
+    Swept { removed: usize },
+    MemoryLimitExceeded { used: usize, limit: usize },
+    SlowQuery { collection: String, elapsed: Duration, threshold: Duration },
+    CollectionDropped { collection: String },
+    CollectionRenamed { from: String, to: String },
+    // A write couldn't be propagated to the collection's CacheLoader
+    WriteThroughFailed { collection: String, id: String, error: String },
+}
+
+type AdminCallback = Arc<dyn Fn(&AdminEvent) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct AdminListeners {
+    listeners: Arc<RwLock<Vec<AdminCallback>>>,
+}
+
+impl AdminListeners {
+    pub fn add(&self, callback: impl Fn(&AdminEvent) + Send + Sync + 'static) {
+        self.listeners.write().unwrap().push(Arc::new(callback));
+    }
+
+    pub fn emit(&self, event: &AdminEvent) {
+        let listeners = self.listeners.read().unwrap().clone();
+        for listener in listeners {
+            listener(event);
+        }
+    }
+}
+
+impl fmt::Debug for AdminListeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AdminListeners({})", self.listeners.read().unwrap().len())
+    }
+}
@@ -0,0 +1,61 @@
+// sweeper.rs
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::config::DbConfig;
+use crate::db::InMemoryDB;
+
+// Upper bound on how long the sweeper sleeps, so interval changes and stop() take effect quickly
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Background thread removing expired documents. The interval is re-read from the database
+// config on every tick, so update_config changes it without restarting the sweeper.
+#[derive(Debug)]
+pub struct Sweeper {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Sweeper {
+    pub(crate) fn spawn(db: Weak<InMemoryDB>, config: Arc<RwLock<DbConfig>>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = thread::spawn(move || {
+            let mut last_sweep = Instant::now();
+            while !stopped.load(Ordering::SeqCst) {
+                let interval = config.read().unwrap().sweeper_interval;
+                if let Some(interval) = interval {
+                    if last_sweep.elapsed() >= interval {
+                        // The database is gone once every strong handle has been dropped
+                        let db = match db.upgrade() {
+                            Some(db) => db,
+                            None => break,
+                        };
+                        db.sweep_expired();
+                        last_sweep = Instant::now();
+                    }
+                }
+                thread::sleep(interval.map_or(POLL_INTERVAL, |i| i.min(POLL_INTERVAL)));
+            }
+        });
+        Sweeper { stop, handle: Some(handle) }
+    }
+
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
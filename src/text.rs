@@ -0,0 +1,208 @@
+// text.rs
+// Text analysis for full-text search: text is split into tokens, then optionally lowercased,
+// stripped of stop words and stemmed. Analyzers are configured per field with
+// CollectionBuilder::text_analyzer and used by QueryBuilder::text_search, which matches documents
+// whose field contains every token of the query.
+//
+//   .text_analyzer("title", Analyzer::new().stop_words(ENGLISH_STOP_WORDS).stemming(true))
+//   .text_analyzer("title_ko", Analyzer::new().tokenizer(Tokenizer::NGram { n: 2 }))
+//
+// Also edit distances for typo-tolerant matching (QueryBuilder::fuzzy) and Soundex codes for
+// phonetic matching (QueryBuilder::sounds_like).
+use serde::{Deserialize, Serialize};
+
+pub const ENGLISH_STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Tokenizer {
+    // Split on whitespace only; punctuation stays part of the token
+    Whitespace,
+    // Split on anything that isn't a letter or digit in any script
+    #[default]
+    Unicode,
+    // Overlapping runs of `n` characters of each word, for scripts without spaces between words
+    // (Chinese, Japanese, Korean). Words shorter than `n` are kept whole; 0 counts as 1.
+    NGram { n: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Analyzer {
+    #[serde(default)]
+    pub tokenizer: Tokenizer,
+    #[serde(default = "default_lowercase")]
+    pub lowercase: bool,
+    #[serde(default)]
+    pub stop_words: Vec<String>,
+    // Light English suffix stripping ("searching", "searched" and "searches" become "search")
+    #[serde(default)]
+    pub stemming: bool,
+}
+
+fn default_lowercase() -> bool {
+    true
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Analyzer {
+            tokenizer: Tokenizer::default(),
+            lowercase: true,
+            stop_words: Vec::new(),
+            stemming: false,
+        }
+    }
+}
+
+impl Analyzer {
+    // Unicode words, lowercased, no stop words and no stemming
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tokenizer(mut self, tokenizer: Tokenizer) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.lowercase = lowercase;
+        self
+    }
+
+    pub fn stop_words(mut self, words: &[&str]) -> Self {
+        self.stop_words = words.iter().map(|w| w.to_string()).collect();
+        self
+    }
+
+    pub fn stemming(mut self, stemming: bool) -> Self {
+        self.stemming = stemming;
+        self
+    }
+
+    pub fn analyze(&self, text: &str) -> Vec<String> {
+        let words: Vec<&str> = match self.tokenizer {
+            Tokenizer::Whitespace => text.split_whitespace().collect(),
+            Tokenizer::Unicode | Tokenizer::NGram { .. } => text.split(|c: char| !c.is_alphanumeric())
+                .filter(|w| !w.is_empty())
+                .collect(),
+        };
+        let words = words.into_iter()
+            .map(|word| if self.lowercase { word.to_lowercase() } else { word.to_string() })
+            .filter(|word| !self.stop_words.iter().any(|stop| stop.eq_ignore_ascii_case(word)))
+            .map(|word| if self.stemming { stem(&word) } else { word });
+        match self.tokenizer {
+            Tokenizer::NGram { n } => words.flat_map(|word| ngrams(&word, n.max(1))).collect(),
+            _ => words.collect(),
+        }
+    }
+
+    // Whether `text` contains every token of `query`
+    pub fn matches(&self, text: &str, query: &str) -> bool {
+        let tokens = self.analyze(text);
+        self.analyze(query).iter().all(|token| tokens.contains(token))
+    }
+}
+
+// Damerau-Levenshtein distance (optimal string alignment) between two strings, counting
+// insertions, deletions, substitutions and transpositions of adjacent characters as one edit each
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Rows i - 2, i - 1 and i of the distance matrix
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1).min(current[j - 1] + 1).min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+// Whether `text`, or any word of it, is within `max_distance` edits of `value`, ignoring case
+pub fn fuzzy_matches(text: &str, value: &str, max_distance: usize) -> bool {
+    let value = value.to_lowercase();
+    let length = value.chars().count();
+    let close = |candidate: &str| {
+        // The length difference alone is a lower bound on the distance
+        candidate.chars().count().abs_diff(length) <= max_distance && edit_distance(candidate, &value) <= max_distance
+    };
+    let text = text.to_lowercase();
+    close(&text) || text.split(|c: char| !c.is_alphanumeric()).any(|word| !word.is_empty() && close(word))
+}
+
+// American Soundex code of a word: its first letter followed by three digits for the consonant
+// sounds after it ("Robert" and "Rupert" are both "R163"). Characters other than ASCII letters
+// are ignored; None when there are no letters.
+pub fn soundex(word: &str) -> Option<String> {
+    fn digit(c: char) -> Option<char> {
+        match c {
+            'B' | 'F' | 'P' | 'V' => Some('1'),
+            'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => Some('2'),
+            'D' | 'T' => Some('3'),
+            'L' => Some('4'),
+            'M' | 'N' => Some('5'),
+            'R' => Some('6'),
+            _ => None,
+        }
+    }
+    let mut letters = word.chars().filter(char::is_ascii_alphabetic).map(|c| c.to_ascii_uppercase());
+    let first = letters.next()?;
+    let mut code = first.to_string();
+    let mut last = digit(first);
+    for c in letters {
+        let current = digit(c);
+        if let Some(d) = current.filter(|_| current != last) {
+            code.push(d);
+            if code.len() == 4 {
+                break;
+            }
+        }
+        // H and W don't separate letters with the same code; vowels do
+        if c != 'H' && c != 'W' {
+            last = current;
+        }
+    }
+    Some(format!("{:0<4}", code))
+}
+
+// Whether any word of `text` has the same Soundex code as any word of `value`
+pub fn sounds_like(text: &str, value: &str) -> bool {
+    let codes = |s: &str| -> Vec<String> { s.split(|c: char| !c.is_alphanumeric()).filter_map(soundex).collect() };
+    let wanted = codes(value);
+    !wanted.is_empty() && codes(text).iter().any(|code| wanted.contains(code))
+}
+
+fn ngrams(word: &str, n: usize) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    if chars.len() <= n {
+        return vec![word.to_string()];
+    }
+    chars.windows(n).map(|gram| gram.iter().collect()).collect()
+}
+
+// Strip common English inflections, keeping at least three characters of stem
+fn stem(word: &str) -> String {
+    const RULES: &[(&str, &str)] = &[("sses", "ss"), ("ies", "y"), ("ing", ""), ("edly", ""), ("ed", ""), ("ly", ""), ("es", ""), ("s", "")];
+    for (suffix, replacement) in RULES {
+        if let Some(stem) = word.strip_suffix(suffix) {
+            if stem.chars().count() >= 3 && !(*suffix == "s" && stem.ends_with('s')) {
+                return format!("{}{}", stem, replacement);
+            }
+        }
+    }
+    word.to_string()
+}
@@ -0,0 +1,93 @@
+// trigram.rs
+// Substring index over a string field (CollectionBuilder::trigram_index). Every run of three
+// characters of the field maps to the keys of the documents containing it, so contains() and
+// like() on the field only read the documents holding every trigram of their literal text; the
+// filter itself is still checked on each of them. Needles shorter than three characters can't
+// use the index and scan the collection as before.
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+#[derive(Debug)]
+pub struct TrigramIndex {
+    field: String,
+    postings: RwLock<HashMap<String, HashSet<String>>>,
+}
+
+impl TrigramIndex {
+    pub fn new(field: &str) -> Self {
+        TrigramIndex { field: field.to_string(), postings: RwLock::new(HashMap::new()) }
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    // Number of distinct trigrams indexed
+    pub fn len(&self) -> usize {
+        self.postings.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&self, id: &str, document: &Value) {
+        let Some(text) = document.get(&self.field).and_then(Value::as_str) else { return };
+        let mut postings = self.postings.write().unwrap();
+        for trigram in trigrams(text) {
+            postings.entry(trigram).or_default().insert(id.to_string());
+        }
+    }
+
+    pub fn remove(&self, id: &str, document: &Value) {
+        let Some(text) = document.get(&self.field).and_then(Value::as_str) else { return };
+        let mut postings = self.postings.write().unwrap();
+        for trigram in trigrams(text) {
+            if let Some(ids) = postings.get_mut(&trigram) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    postings.remove(&trigram);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        self.postings.write().unwrap().clear();
+    }
+
+    // Keys of the documents containing every literal, None when no literal is long enough to
+    // narrow the search
+    pub fn candidates(&self, literals: &[String]) -> Option<HashSet<String>> {
+        let wanted: HashSet<String> = literals.iter().flat_map(|literal| trigrams(literal)).collect();
+        if wanted.is_empty() {
+            return None;
+        }
+        let postings = self.postings.read().unwrap();
+        let mut lists: Vec<&HashSet<String>> = Vec::with_capacity(wanted.len());
+        for trigram in &wanted {
+            match postings.get(trigram) {
+                Some(ids) => lists.push(ids),
+                None => return Some(HashSet::new()),
+            }
+        }
+        // Intersect starting from the rarest trigram
+        lists.sort_by_key(|ids| ids.len());
+        let mut candidates = lists[0].clone();
+        for ids in &lists[1..] {
+            candidates.retain(|id| ids.contains(id));
+        }
+        Some(candidates)
+    }
+}
+
+pub fn trigrams(text: &str) -> HashSet<String> {
+    let chars: Vec<char> = text.chars().collect();
+    chars.windows(3).map(|gram| gram.iter().collect()).collect()
+}
+
+// The literal runs of a LIKE pattern, between its % and _ wildcards
+pub(crate) fn like_literals(pattern: &str) -> Vec<String> {
+    pattern.split(['%', '_']).filter(|run| !run.is_empty()).map(str::to_string).collect()
+}
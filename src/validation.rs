@@ -0,0 +1,275 @@
+// validation.rs
+use regex::Regex;
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+use crate::config::CollectionConfig;
+
+// A named check run against every document written to a collection.
+// Validators are executed in registration order and the first failure aborts the write.
+pub trait DocumentValidator: Send + Sync {
+    fn name(&self) -> &str;
+    fn validate(&self, document: &Value) -> Result<(), String>;
+}
+
+impl fmt::Debug for dyn DocumentValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DocumentValidator({})", self.name())
+    }
+}
+
+// Type names accepted in CollectionConfig::field_types
+pub const FIELD_TYPES: [&str; 10] = ["string", "number", "integer", "int", "float", "boolean", "bool", "object", "array", "any"];
+
+pub fn value_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "float",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+pub fn matches_type(value: &Value, type_name: &str) -> Result<bool, String> {
+    Ok(match type_name {
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" | "int" => value.is_i64() || value.is_u64(),
+        "float" => value.is_number(),
+        "boolean" | "bool" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "any" => true,
+        other => return Err(format!("Unknown field type '{}'", other)),
+    })
+}
+
+// Checks declared field types and not-null / nullable constraints
+#[derive(Debug, Clone, Default)]
+pub struct SchemaValidator {
+    field_types: Vec<(String, String)>,
+    not_null_fields: Vec<String>,
+    nullable_fields: Vec<String>,
+}
+
+impl SchemaValidator {
+    pub fn new(field_types: Vec<(&str, &str)>) -> Self {
+        SchemaValidator {
+            field_types: field_types.iter().map(|(f, t)| (f.to_string(), t.to_string())).collect(),
+            not_null_fields: Vec::new(),
+            nullable_fields: Vec::new(),
+        }
+    }
+
+    pub fn from_config(config: &CollectionConfig) -> Self {
+        Self::new(config.field_types.clone())
+            .not_null(config.not_null_fields.clone())
+            .nullable(config.nullable_fields.clone())
+    }
+
+    // Fields that must be present and non-null
+    pub fn not_null(mut self, fields: Vec<&str>) -> Self {
+        self.not_null_fields = fields.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    // Fields that may be explicitly set to null regardless of their declared type
+    pub fn nullable(mut self, fields: Vec<&str>) -> Self {
+        self.nullable_fields = fields.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.field_types.is_empty() && self.not_null_fields.is_empty() && self.nullable_fields.is_empty()
+    }
+}
+
+impl DocumentValidator for SchemaValidator {
+    fn name(&self) -> &str {
+        "schema"
+    }
+
+    fn validate(&self, document: &Value) -> Result<(), String> {
+        for field in &self.not_null_fields {
+            match document.get(field) {
+                None => return Err(format!("Field '{}' is required", field)),
+                Some(Value::Null) => return Err(format!("Field '{}' must not be null", field)),
+                Some(_) => {}
+            }
+        }
+
+        for (field, type_name) in &self.field_types {
+            if let Some(value) = document.get(field) {
+                if value.is_null() && self.nullable_fields.contains(field) {
+                    continue;
+                }
+                if !matches_type(value, type_name)? {
+                    return Err(format!(
+                        "Field '{}' expected type '{}' but got '{}'",
+                        field, type_name, value_type_name(value)
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+type Predicate = Arc<dyn Fn(&Value) -> bool + Send + Sync>;
+
+// Named invariant checked on every write
+#[derive(Clone)]
+pub struct CheckConstraint {
+    name: String,
+    predicate: Predicate,
+}
+
+impl CheckConstraint {
+    pub fn new<F>(name: &str, predicate: F) -> Self
+    where
+        F: Fn(&Value) -> bool + Send + Sync + 'static,
+    {
+        CheckConstraint {
+            name: name.to_string(),
+            predicate: Arc::new(predicate),
+        }
+    }
+}
+
+impl fmt::Debug for CheckConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CheckConstraint({})", self.name)
+    }
+}
+
+impl DocumentValidator for CheckConstraint {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn validate(&self, document: &Value) -> Result<(), String> {
+        if (self.predicate)(document) {
+            Ok(())
+        } else {
+            Err(format!("check constraint '{}' violated", self.name))
+        }
+    }
+}
+
+type Rule = Box<dyn Fn(&Value) -> Result<(), String> + Send + Sync>;
+
+// Custom business rule backed by a closure
+pub struct RuleValidator {
+    name: String,
+    rule: Rule,
+}
+
+impl RuleValidator {
+    pub fn new<F>(name: &str, rule: F) -> Self
+    where
+        F: Fn(&Value) -> Result<(), String> + Send + Sync + 'static,
+    {
+        RuleValidator {
+            name: name.to_string(),
+            rule: Box::new(rule),
+        }
+    }
+}
+
+impl DocumentValidator for RuleValidator {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn validate(&self, document: &Value) -> Result<(), String> {
+        (self.rule)(document)
+    }
+}
+
+// Rejects documents whose string values look like personal information
+pub struct PiiScanner {
+    name: String,
+    patterns: Vec<(String, Regex)>,
+    allowed_fields: Vec<String>,
+}
+
+impl PiiScanner {
+    // Scanner preloaded with email, phone number and Korean resident registration number patterns
+    pub fn new() -> Self {
+        PiiScanner {
+            name: "pii_scanner".to_string(),
+            patterns: vec![
+                ("email".to_string(), Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap()),
+                ("phone".to_string(), Regex::new(r"\b01[016789]-?\d{3,4}-?\d{4}\b").unwrap()),
+                ("rrn".to_string(), Regex::new(r"\b\d{6}-?[1-4]\d{6}\b").unwrap()),
+            ],
+            allowed_fields: Vec::new(),
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn pattern(mut self, label: &str, pattern: &str) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|e| format!("Invalid PII pattern '{}': {}", label, e))?;
+        self.patterns.push((label.to_string(), regex));
+        Ok(self)
+    }
+
+    // Fields that are expected to hold personal information (e.g. "email") and are not scanned
+    pub fn allow_fields(mut self, fields: Vec<&str>) -> Self {
+        self.allowed_fields = fields.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    fn scan(&self, path: &str, value: &Value) -> Result<(), String> {
+        match value {
+            Value::String(s) => {
+                for (label, regex) in &self.patterns {
+                    if regex.is_match(s) {
+                        return Err(format!("Field '{}' looks like it contains PII ({})", path, label));
+                    }
+                }
+                Ok(())
+            }
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    self.scan(&format!("{}[{}]", path, i), item)?;
+                }
+                Ok(())
+            }
+            Value::Object(map) => {
+                for (key, val) in map {
+                    let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    if self.allowed_fields.contains(&child) {
+                        continue;
+                    }
+                    self.scan(&child, val)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for PiiScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DocumentValidator for PiiScanner {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn validate(&self, document: &Value) -> Result<(), String> {
+        self.scan("", document)
+    }
+}
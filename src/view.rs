@@ -0,0 +1,150 @@
+// view.rs
+use dashmap::DashMap;
+use serde_json::Value;
+use std::fmt;
+use std::sync::{Arc, Weak};
+use crate::db::{Collection, DocumentEntry};
+use crate::error::EmemError;
+use crate::query::QueryBuilder;
+use crate::subscription::{EventType, Subscription};
+
+// Query results stored in their own collection and kept up to date as the source changes.
+// Writes to the source collection re-evaluate only the written document; writes to a joined
+// collection recompute the whole view.
+pub struct MaterializedView {
+    name: String,
+    query: QueryBuilder,
+    view: Arc<Collection>,
+    // Source document id -> ids of the view rows it produced
+    rows_by_source: DashMap<String, Vec<String>>,
+}
+
+impl fmt::Debug for MaterializedView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MaterializedView({})", self.name)
+    }
+}
+
+impl MaterializedView {
+    pub(crate) fn create(name: &str, query: QueryBuilder, view: Arc<Collection>) -> Result<Arc<Self>, EmemError> {
+        let materialized = Arc::new(MaterializedView {
+            name: name.to_string(),
+            query,
+            view,
+            rows_by_source: DashMap::new(),
+        });
+        materialized.refresh();
+
+        let source = materialized.query.source();
+        for event in [EventType::Insert, EventType::Update, EventType::Delete, EventType::Evicted] {
+            let weak = Arc::downgrade(&materialized);
+            source.subscribe(Subscription::new(event, move |id, _| {
+                if let Some(view) = Weak::upgrade(&weak) {
+                    view.refresh_document(id);
+                }
+            }))?;
+        }
+        for target in materialized.query.join_targets() {
+            for event in [EventType::Insert, EventType::Update, EventType::Delete, EventType::Evicted] {
+                let weak = Arc::downgrade(&materialized);
+                target.subscribe(Subscription::new(event, move |_, _| {
+                    if let Some(view) = Weak::upgrade(&weak) {
+                        view.refresh();
+                    }
+                }))?;
+            }
+        }
+        Ok(materialized)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The collection holding the view rows; query it like any other collection
+    pub fn collection(&self) -> Arc<Collection> {
+        self.view.clone()
+    }
+
+    pub fn select(&self, fields: &str) -> QueryBuilder {
+        self.view.select(fields)
+    }
+
+    pub fn len(&self) -> usize {
+        self.view.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.view.documents.is_empty()
+    }
+
+    // Recompute every row from scratch
+    pub fn refresh(&self) {
+        self.view.documents.clear();
+        self.rows_by_source.clear();
+        let source = self.query.source();
+        let ids: Vec<String> = source.documents.iter().map(|r| r.key().clone()).collect();
+        for id in ids {
+            self.refresh_document(&id);
+        }
+    }
+
+    // Re-evaluate the rows produced by one source document
+    pub fn refresh_document(&self, id: &str) {
+        if let Some((_, old_rows)) = self.rows_by_source.remove(id) {
+            for row in old_rows {
+                self.view.documents.remove(&row);
+            }
+        }
+
+        let source = self.query.source();
+        let document = match source.documents.get(id) {
+            Some(entry) if !source.is_deleted(&entry.value) => Value::clone(&entry.value),
+            _ => return,
+        };
+        // A join that fails (max_scan, timeout) leaves the document out of the view
+        let rows = self.query.evaluate(document).unwrap_or_default();
+        if rows.is_empty() {
+            return;
+        }
+        let single = rows.len() == 1;
+        let mut row_ids = Vec::with_capacity(rows.len());
+        for (i, row) in rows.into_iter().enumerate() {
+            let row_id = if single { id.to_string() } else { format!("{}#{}", id, i) };
+            self.view.documents.insert(row_id.clone(), DocumentEntry::new(row, None));
+            row_ids.push(row_id);
+        }
+        self.rows_by_source.insert(id.to_string(), row_ids);
+    }
+}
+
+type ViewFactory = Arc<dyn Fn() -> QueryBuilder + Send + Sync>;
+
+// A query registered under a name; the factory builds a fresh QueryBuilder for every run
+#[derive(Clone)]
+pub struct SavedView {
+    pub name: String,
+    factory: ViewFactory,
+}
+
+impl SavedView {
+    pub fn new<F>(name: &str, factory: F) -> Self
+    where
+        F: Fn() -> QueryBuilder + Send + Sync + 'static,
+    {
+        SavedView {
+            name: name.to_string(),
+            factory: Arc::new(factory),
+        }
+    }
+
+    pub fn query(&self) -> QueryBuilder {
+        (self.factory)()
+    }
+}
+
+impl fmt::Debug for SavedView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SavedView({})", self.name)
+    }
+}
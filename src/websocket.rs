@@ -0,0 +1,194 @@
+// websocket.rs
+// Change feed over WebSocket (feature "websocket"), mounted at /changes by http::router.
+//
+//   GET /changes?collections=users,orders&events=insert,update&since=42
+//
+// Every write matching the connection's filter is sent as a ChangeEvent JSON text message.
+// Clients replace the filter at any time by sending
+//
+//   {"collections": ["users"], "events": ["insert"], "filter": {"age": {"$gte": 18}}, "since": 42}
+//
+// where `filter` is a Mongo-style live-query filter and `since` replays retained events after
+// that sequence number before continuing live.
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::response::Response;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use crate::changelog::{ChangeEvent, ChangeFilter, ChangeLog, DEFAULT_CHANGE_LOG_CAPACITY};
+use crate::db::InMemoryDB;
+
+#[derive(Debug, Deserialize)]
+struct SubscribeMessage {
+    #[serde(default)]
+    collections: Vec<String>,
+    #[serde(default)]
+    events: Vec<String>,
+    #[serde(default)]
+    filter: Value,
+    #[serde(default)]
+    since: Option<u64>,
+}
+
+impl SubscribeMessage {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, String> {
+        let list = |key: &str| params.get(key).map_or(Vec::new(), |v| v.split(',').map(|s| s.trim().to_string()).collect());
+        Ok(SubscribeMessage {
+            collections: list("collections"),
+            events: list("events"),
+            filter: Value::Null,
+            since: params.get("since")
+                .map(|s| s.parse::<u64>().map_err(|_| format!("since expects a sequence number, found '{}'", s)))
+                .transpose()?,
+        })
+    }
+
+    fn change_filter(&self) -> Result<ChangeFilter, String> {
+        Ok(ChangeFilter {
+            collections: self.collections.clone(),
+            events: self.events.clone(),
+            filter: crate::protocol::parse_filter(&self.filter)?,
+        })
+    }
+}
+
+pub(crate) async fn changes(
+    ws: WebSocketUpgrade,
+    State(db): State<Arc<InMemoryDB>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let log = db.change_log().unwrap_or_else(|| db.enable_change_log(DEFAULT_CHANGE_LOG_CAPACITY));
+    ws.on_upgrade(move |socket| feed(socket, log, SubscribeMessage::from_params(&params)))
+}
+
+async fn send(socket: &mut WebSocket, value: Value) -> bool {
+    socket.send(Message::Text(value.to_string().into())).await.is_ok()
+}
+
+struct Connection {
+    filter: ChangeFilter,
+    // Highest sequence number already sent, so replayed events are not repeated live
+    last_seq: u64,
+}
+
+impl Connection {
+    // Apply a subscribe message and return the events to replay
+    fn subscribe(&mut self, log: &ChangeLog, message: Result<SubscribeMessage, String>) -> Result<Vec<ChangeEvent>, String> {
+        let message = message?;
+        self.filter = message.change_filter()?;
+        let replay = match message.since {
+            Some(since) => log.since(since)?,
+            None => Vec::new(),
+        };
+        let replay: Vec<ChangeEvent> = replay.into_iter().filter(|e| self.filter.matches(e)).collect();
+        if let Some(last) = replay.last() {
+            self.last_seq = self.last_seq.max(last.seq);
+        }
+        Ok(replay)
+    }
+
+    fn accept(&mut self, event: &ChangeEvent) -> bool {
+        if event.seq <= self.last_seq || !self.filter.matches(event) {
+            return false;
+        }
+        self.last_seq = event.seq;
+        true
+    }
+}
+
+async fn feed(mut socket: WebSocket, log: Arc<ChangeLog>, initial: Result<SubscribeMessage, String>) {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    log.listen(move |event| sender.send(event.clone()).is_ok());
+
+    let mut connection = Connection { filter: ChangeFilter::default(), last_seq: 0 };
+    let mut pending = Some(initial);
+    loop {
+        if let Some(message) = pending.take() {
+            match connection.subscribe(&log, message) {
+                Ok(replay) => {
+                    for event in replay {
+                        if !send(&mut socket, json!(event)).await {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    if !send(&mut socket, json!({ "error": e })).await {
+                        return;
+                    }
+                }
+            }
+        }
+
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Some(event) => {
+                    if connection.accept(&event) && !send(&mut socket, json!(event)).await {
+                        return;
+                    }
+                }
+                None => return,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    pending = Some(serde_json::from_str::<SubscribeMessage>(text.as_str())
+                        .map_err(|e| format!("Invalid subscribe message: {}", e)));
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(value: Value) -> Result<SubscribeMessage, String> {
+        serde_json::from_value(value).map_err(|e| e.to_string())
+    }
+
+    fn seqs(events: &[ChangeEvent]) -> Vec<u64> {
+        events.iter().map(|e| e.seq).collect()
+    }
+
+    #[test]
+    fn resume_replays_once_and_skips_duplicate_live_events() {
+        let log = ChangeLog::new(10);
+        for (collection, id) in [("users", "u1"), ("orders", "o1"), ("users", "u2")] {
+            log.append(collection, "insert", id, &json!({"id": id}));
+        }
+        let mut connection = Connection { filter: ChangeFilter::default(), last_seq: 0 };
+        let replay = connection.subscribe(&log, message(json!({"collections": ["users"], "since": 0}))).unwrap();
+        assert_eq!(seqs(&replay), [1, 3]);
+
+        // The same events also reach the live listener
+        let live = log.since(0).unwrap();
+        assert!(live.iter().all(|event| !connection.accept(event)));
+
+        log.append("users", "update", "u1", &json!({"id": "u1", "name": "kim"}));
+        log.append("orders", "insert", "o2", &json!({"id": "o2"}));
+        let live = log.since(3).unwrap();
+        assert_eq!(live.iter().filter(|event| connection.accept(event)).map(|e| e.seq).collect::<Vec<_>>(), [4]);
+        assert!(!connection.accept(&live[0]));
+    }
+
+    #[test]
+    fn live_filter_and_invalid_resume_points() {
+        let log = ChangeLog::new(2);
+        for age in [10, 20, 30] {
+            log.append("users", "insert", &age.to_string(), &json!({"age": age}));
+        }
+        let mut connection = Connection { filter: ChangeFilter::default(), last_seq: 0 };
+        let replay = connection.subscribe(&log, message(json!({"filter": {"age": {"$gte": 18}}, "since": 1}))).unwrap();
+        assert_eq!(seqs(&replay), [2, 3]);
+
+        assert!(connection.subscribe(&log, message(json!({"since": 0}))).is_err());
+        let params = HashMap::from([("since".to_string(), "soon".to_string())]);
+        assert!(connection.subscribe(&log, SubscribeMessage::from_params(&params)).is_err());
+    }
+}